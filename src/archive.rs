@@ -0,0 +1,149 @@
+use std::{cmp::Reverse, collections::{BTreeMap, HashSet}, fs::{create_dir_all, read, read_dir}, path::{Path, PathBuf}};
+
+use chrono::{Days, Local};
+
+use crate::{atomicfile, decode_tasks, encode_tasks, ArchivePeriod, Task, TaskList, TaskStore};
+
+const ARCHIVE_DIR : &str = "archive";
+const ARCHIVE_PREFIX : &str = "tasks-";
+const ARCHIVE_SUFFIX : &str = ".json";
+
+/// Completed tasks moved out of the task list, so that it stays small.
+///
+/// Tasks are archived in `archive/tasks-<period>.json` in the data directory,
+/// by the month (`tasks-2024-05.json`) or year (`tasks-2024.json`) in which
+/// they were completed, in the same format as `tasks.json`.
+pub struct Archive {
+    dir: PathBuf,
+    period: ArchivePeriod,
+}
+
+impl Archive {
+    #[must_use]
+    pub fn new(data_dir: &Path, period: ArchivePeriod) -> Self {
+        Self {
+            dir: data_dir.join(ARCHIVE_DIR),
+            period,
+        }
+    }
+
+    /// Returns true if `task` matches the search `query`, ignoring case.
+    /// An empty query matches every task.
+    #[must_use]
+    pub fn matches(task: &Task, query: &str) -> bool {
+        task.description().to_lowercase().contains(&query.to_lowercase())
+    }
+
+    fn file_name(&self, task: &Task) -> String {
+        let completed = task.completed_date_time();
+        let period = match self.period {
+            ArchivePeriod::Month => completed.format("%Y-%m"),
+            ArchivePeriod::Year => completed.format("%Y"),
+        };
+        format!("{ARCHIVE_PREFIX}{period}{ARCHIVE_SUFFIX}")
+    }
+
+    /// Moves tasks completed at least `after_days` days ago from `task_list`
+    /// into the archive.  Each archive file is written before the tasks are
+    /// removed from the list, so nothing is lost if we're interrupted, and
+    /// tasks already in the archive aren't added again.
+    /// Returns the number of tasks archived.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the archive can't be read or written, or the list
+    /// can't be written.
+    pub fn archive_completed<S: TaskStore>(&self, task_list: &mut TaskList<S>, after_days: u64) -> std::io::Result<usize> {
+        if task_list.is_read_only() {
+            return Ok(0);
+        }
+        let Some(cutoff) = Local::now().naive_local().checked_sub_days(Days::new(after_days)) else {
+            return Ok(0);
+        };
+        let mut by_file: BTreeMap<String, Vec<Task>> = BTreeMap::new();
+        for task in task_list.tasks().iter().filter(|t| t.completed().is_some_and(|completed| completed < cutoff)) {
+            by_file.entry(self.file_name(task)).or_default().push(task.clone());
+        }
+        if by_file.is_empty() {
+            return Ok(0);
+        }
+        create_dir_all(&self.dir)?;
+        let mut archived = vec![];
+        for (file_name, tasks) in by_file {
+            let path = self.dir.join(file_name);
+            let mut contents = if path.exists() { Self::load_file(&path)? } else { vec![] };
+            let present = contents.iter().map(Task::uuid).collect::<HashSet<_>>();
+            archived.extend(tasks.iter().map(Task::uuid));
+            contents.extend(tasks.into_iter().filter(|t| !present.contains(&t.uuid())));
+            let serialized = encode_tasks(&contents)?;
+            atomicfile::write_atomic(&path, None, serialized.as_bytes(), |written| {
+                if decode_tasks(written)?.tasks.len() == contents.len() {
+                    Ok(())
+                } else {
+                    Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Archived task count doesn't match"))
+                }
+            })?;
+        }
+        task_list.drop_tasks(&archived)?;
+        Ok(archived.len())
+    }
+
+    fn load_file(path: &Path) -> std::io::Result<Vec<Task>> {
+        Ok(decode_tasks(&read(path)?)?.tasks)
+    }
+
+    /// Returns the directory holding the archive files
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the archive files, newest period first
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the archive directory exists but can't be read.
+    pub fn files(&self) -> std::io::Result<Vec<PathBuf>> {
+        if !self.dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut files = vec![];
+        for entry in read_dir(&self.dir)? {
+            let path = entry?.path();
+            let is_archive = path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(ARCHIVE_PREFIX) && name.ends_with(ARCHIVE_SUFFIX));
+            if is_archive {
+                files.push(path);
+            }
+        }
+        files.sort_by(|a, b| b.cmp(a));
+        Ok(files)
+    }
+
+    /// Returns every archived task, most recently completed first
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if an archive file can't be read.
+    pub fn load_all(&self) -> std::io::Result<Vec<Task>> {
+        let mut tasks = vec![];
+        for path in self.files()? {
+            tasks.extend(Self::load_file(&path)?);
+        }
+        tasks.sort_by_key(|t| Reverse(t.completed()));
+        Ok(tasks)
+    }
+
+    /// Returns the archived tasks matching `query` (see ``matches``), most
+    /// recently completed first
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if an archive file can't be read.
+    pub fn search(&self, query: &str) -> std::io::Result<Vec<Task>> {
+        let mut tasks = self.load_all()?;
+        tasks.retain(|t| Self::matches(t, query));
+        Ok(tasks)
+    }
+}
@@ -0,0 +1,128 @@
+use std::{fs::read_to_string, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+const CONFIG_PATH : &str = "config.json";
+
+/// User settings, read from `config.json` in the data directory.
+/// Any settings missing from the file take their default values.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub snapshots: SnapshotRetention,
+    /// How often, in seconds, to reload the list when viewing it read-only
+    /// because another instance holds the lock
+    pub viewer_reload_secs: u64,
+    pub archive: ArchiveSettings,
+    pub git: GitSettings,
+    /// Whether to keep each list as a replica, to be synced with replicas on
+    /// other machines (see ``ReplicaStore``)
+    pub replicated: bool,
+}
+
+/// How many rolling snapshots of the task list to keep
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SnapshotRetention {
+    /// Number of most recent hourly snapshots to keep
+    pub hourly: usize,
+    /// Number of most recent days for which to keep the last snapshot of the day
+    pub daily: usize,
+}
+
+impl Default for SnapshotRetention {
+    fn default() -> Self {
+        Self {
+            hourly: 24,
+            daily: 14,
+        }
+    }
+}
+
+/// When to move completed tasks out of the task list into the archive
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArchiveSettings {
+    /// Archive tasks completed at least this many days ago, or never if `None`,
+    /// the default, so that archiving is only done once asked for
+    pub after_days: Option<u64>,
+    /// How archived tasks are grouped into files
+    pub period: ArchivePeriod,
+}
+
+/// The span of completion dates covered by each archive file
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ArchivePeriod {
+    Month,
+    Year,
+}
+
+impl Default for ArchiveSettings {
+    fn default() -> Self {
+        Self {
+            after_days: None,
+            period: ArchivePeriod::Month,
+        }
+    }
+}
+
+/// Whether to keep the data directory as a local git repository
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GitSettings {
+    pub enabled: bool,
+    /// How long, in seconds, the list must be left unchanged before its
+    /// changes are committed
+    pub debounce_secs: u64,
+}
+
+impl Default for GitSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            debounce_secs: 30,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            snapshots: SnapshotRetention::default(),
+            viewer_reload_secs: 5,
+            archive: ArchiveSettings::default(),
+            git: GitSettings::default(),
+            replicated: false,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config from `dir`, or returns the defaults if there is no
+    /// config file.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the config file exists but can't be read or parsed.
+    pub fn load(dir: &Path) -> std::io::Result<Self> {
+        let path = dir.join(CONFIG_PATH);
+        let config = match read_to_string(&path) {
+            Ok(serialized) => serde_json::from_str(&serialized).map_err(std::io::Error::from),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(err) => Err(err),
+        };
+        config.map_err(|err| std::io::Error::new(err.kind(), format!("Couldn't read {}: {err}", path.display())))
+    }
+
+    /// Loads the config as for ``load``, but falls back to the defaults if
+    /// the config file can't be read or parsed.  Returns the config, and what
+    /// went wrong, if anything, for telling the user.
+    #[must_use]
+    pub fn load_or_default(dir: &Path) -> (Self, Option<std::io::Error>) {
+        match Self::load(dir) {
+            Ok(config) => (config, None),
+            Err(err) => (Self::default(), Some(err)),
+        }
+    }
+}
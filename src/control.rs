@@ -0,0 +1,264 @@
+use std::{io::{ErrorKind, Read, Write}, os::unix::net::{UnixListener, UnixStream}, path::{Path, PathBuf}};
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{check_uuid_prefix, Task, TaskList, TaskStore};
+
+const SOCKET_PATH : &str = "task.sock";
+// Clients sending longer lines than this are disconnected
+const MAX_LINE : usize = 1 << 20;
+// Clients leaving more than this of their replies unread are disconnected
+const MAX_OUTGOING : usize = 1 << 20;
+
+/// A command sent to the control socket
+#[derive(Deserialize)]
+#[serde(tag = "command", rename_all = "lowercase")]
+enum ControlCommand {
+    /// Add a task to the bottom of the list
+    Add { description: String },
+    /// Complete the task whose uuid starts with `uuid`, which must be at least
+    /// ``MIN_UUID_PREFIX`` characters
+    Complete { uuid: String },
+    /// Return the next task, if there is one
+    Next,
+}
+
+struct Client {
+    stream: UnixStream,
+    // False once the client has closed its end, though commands it sent
+    // before then are still carried out
+    open: bool,
+    received: Vec<u8>,
+    // Replies not yet written, because the client isn't reading them
+    outgoing: Vec<u8>,
+}
+
+/// A Unix domain socket, `task.sock` in the list's directory, through which
+/// other programs can control the running instance holding the list.
+///
+/// Clients send one JSON command per line, and get one JSON response per
+/// line, in order:
+///
+/// - `{"command": "add", "description": "..."}` adds a task
+/// - `{"command": "complete", "uuid": "..."}` completes the task whose uuid
+///   starts with the given prefix, of at least four characters
+/// - `{"command": "next"}` returns the next task, or null
+///
+/// Responses are `{"ok": true, "task": ...}` or `{"ok": false, "error": "..."}`.
+///
+/// Nothing blocks: ``receive`` reads whatever has arrived, ``service``
+/// carries out the commands, and both write as much of the replies as each
+/// client will take, and return.  Clients leaving
+/// too much unread are disconnected.
+/// The socket file is removed when this is dropped.
+pub struct ControlSocket {
+    path: PathBuf,
+    listener: UnixListener,
+    clients: Vec<Client>,
+}
+
+impl ControlSocket {
+    /// Starts listening on `task.sock` in `dir`, replacing a socket left
+    /// behind by an instance that has gone
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if another instance is listening on the socket, or
+    /// it can't be created.
+    pub fn bind(dir: &Path) -> std::io::Result<Self> {
+        let path = dir.join(SOCKET_PATH);
+        if path.exists() {
+            if UnixStream::connect(&path).is_ok() {
+                return Err(std::io::Error::new(ErrorKind::AddrInUse, format!("{} is in use", path.display())));
+            }
+            std::fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self { path, listener, clients: vec![] })
+    }
+
+    /// Accepts any new connections, reads whatever has arrived, and writes
+    /// what replies it can.  Returns true if there are complete commands
+    /// waiting to be carried out by ``service``.
+    pub fn receive(&mut self) -> bool {
+        while let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_ok() {
+                self.clients.push(Client { stream, open: true, received: vec![], outgoing: vec![] });
+            }
+        }
+        self.clients.retain_mut(|client| {
+            client.open = client.open && client.receive();
+            client.flush() && client.keep()
+        });
+        self.clients.iter().any(Client::pending)
+    }
+
+    /// Carries out every complete command received on `task_list`, replying
+    /// to each.  Returns true if the list may have changed.
+    pub fn service<S: TaskStore>(&mut self, task_list: &mut TaskList<S>) -> bool {
+        let mut changed = false;
+        self.clients.retain_mut(|client| {
+            while let Some(end) = client.received.iter().position(|&byte| byte == b'\n') {
+                let line = client.received.drain(..=end).collect::<Vec<_>>();
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+                let (response, changed_list) = match serde_json::from_slice::<ControlCommand>(&line) {
+                    Ok(command) => run(command, task_list),
+                    Err(err) => (json!({ "ok": false, "error": format!("Bad command: {err}") }), false),
+                };
+                changed |= changed_list;
+                client.outgoing.extend_from_slice(response.to_string().as_bytes());
+                client.outgoing.push(b'\n');
+            }
+            client.flush() && client.keep()
+        });
+        changed
+    }
+}
+
+impl Client {
+    /// Reads whatever has arrived.  Returns false if the client has gone.
+    fn receive(&mut self) -> bool {
+        let mut buffer = [0_u8; 4096];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return false,
+                Ok(count) => self.received.extend_from_slice(&buffer[..count]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return true,
+                Err(err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(_) => return false,
+            }
+        }
+    }
+
+    /// Whether a complete command has arrived
+    fn pending(&self) -> bool {
+        self.received.contains(&b'\n')
+    }
+
+    /// Whether the client should stay connected
+    fn keep(&self) -> bool {
+        (self.open || self.pending())
+            && (self.pending() || self.received.len() <= MAX_LINE)
+            && self.outgoing.len() <= MAX_OUTGOING
+    }
+
+    /// Writes as much of the outgoing replies as the client will take
+    /// without waiting.  Returns false if the client has gone.
+    fn flush(&mut self) -> bool {
+        while !self.outgoing.is_empty() {
+            match self.stream.write(&self.outgoing) {
+                Ok(0) => return false,
+                Ok(count) => { self.outgoing.drain(..count); },
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return true,
+                Err(err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Carries out a command, returning the response, and whether the list changed
+fn run<S: TaskStore>(command: ControlCommand, task_list: &mut TaskList<S>) -> (serde_json::Value, bool) {
+    let result = match command {
+        ControlCommand::Add { description } => {
+            let task = Task::new(&description);
+            task_list.add(task.clone()).map(|()| (Some(task), true))
+        },
+        ControlCommand::Complete { uuid } => check_uuid_prefix(&uuid)
+            .and_then(|()| task_list.find_by_prefix(&uuid)).map(Task::uuid)
+            .and_then(|uuid| task_list.complete(uuid).map(|completed| (task_list.get(uuid).cloned(), completed))),
+        ControlCommand::Next => Ok((task_list.last_dotted_task().cloned(), false)),
+    };
+    match result {
+        Ok((task, changed)) => (json!({ "ok": true, "task": task }), changed),
+        Err(err) => (json!({ "ok": false, "error": err.to_string() }), false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::BufRead, time::{Duration, Instant}};
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::MemoryStore;
+
+    #[test]
+    fn commands_are_only_reported_once_complete() {
+        let dir = std::env::temp_dir().join(format!("task-control-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("a temporary directory");
+        let mut control_socket = ControlSocket::bind(&dir).expect("a control socket");
+        let mut task_list = TaskList::load(MemoryStore::new(vec![])).expect("a list in memory");
+        let mut stream = UnixStream::connect(dir.join(SOCKET_PATH)).expect("a connection");
+        assert!(!control_socket.receive());
+        stream.write_all(b"{\"command\": \"add\", ").expect("a partial command sent");
+        assert!(!control_socket.receive());
+        stream.write_all(b"\"description\": \"Water the plants\"}\n").expect("the rest sent");
+        let started = Instant::now();
+        while !control_socket.receive() {
+            assert!(started.elapsed() < Duration::from_secs(10), "the command never arrived");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert!(control_socket.service(&mut task_list));
+        assert!(!control_socket.receive());
+        assert_eq!(task_list.tasks().len(), 1);
+        let mut reply = String::new();
+        std::io::BufReader::new(stream).read_line(&mut reply).expect("a reply");
+        assert!(reply.contains("\"ok\":true") && reply.contains("Water the plants"));
+        drop(control_socket);
+        std::fs::remove_dir_all(&dir).expect("the directory removed");
+    }
+
+    #[test]
+    fn short_prefixes_complete_nothing() {
+        let task = Task::new("Only");
+        let mut task_list = TaskList::load(MemoryStore::new(vec![task.clone()])).expect("a list in memory");
+        for uuid in ["", "-", &task.uuid().to_string()[..3]] {
+            let (response, changed) = run(ControlCommand::Complete { uuid: uuid.to_string() }, &mut task_list);
+            assert_eq!(response["ok"], false, "{uuid}");
+            assert!(!changed);
+        }
+        assert!(!task_list.tasks()[0].is_complete());
+        let (response, changed) = run(ControlCommand::Complete { uuid: task.uuid().to_string()[..4].to_string() }, &mut task_list);
+        assert_eq!(response["ok"], true);
+        assert!(changed);
+    }
+
+    #[test]
+    fn clients_not_reading_replies_are_dropped_without_waiting() {
+        let dir = std::env::temp_dir().join(format!("task-control-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).expect("a temporary directory");
+        let mut control_socket = ControlSocket::bind(&dir).expect("a control socket");
+        let mut task_list = TaskList::load(MemoryStore::new(vec![])).expect("a list in memory");
+        let mut stream = UnixStream::connect(dir.join(SOCKET_PATH)).expect("a connection");
+        // Ask for far more replies than are allowed to wait, and never read them
+        let writer = std::thread::spawn(move || {
+            let commands = "{\"command\": \"next\"}\n".repeat(MAX_OUTGOING / 16);
+            let _ = stream.write_all(commands.as_bytes());
+            stream
+        });
+        let started = Instant::now();
+        control_socket.receive();
+        while !control_socket.clients.is_empty() {
+            assert!(started.elapsed() < Duration::from_secs(10), "the client was never dropped");
+            control_socket.service(&mut task_list);
+            control_socket.receive();
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        writer.join().expect("the writer finished");
+        drop(control_socket);
+        std::fs::remove_dir_all(&dir).expect("the directory removed");
+    }
+}
@@ -0,0 +1,504 @@
+use std::{collections::{BTreeMap, HashMap, HashSet}, fs::read, path::Path};
+
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{atomicfile, Task};
+
+// Gap left between positions appended to the end of the list, so that tasks
+// can later be placed between them without lengthening the position
+const POSITION_STEP : u64 = 1 << 16;
+const POSITION_LIMIT : u64 = 1 << 32;
+
+/// A Lamport timestamp: the replica's logical clock when it made a change,
+/// with the replica's id to order concurrent changes the same way everywhere
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Stamp {
+    counter: u64,
+    replica: Uuid,
+}
+
+/// A last-writer-wins register: merging keeps the value with the later stamp
+#[derive(Clone, Serialize, Deserialize)]
+struct Lww<T> {
+    value: T,
+    stamp: Stamp,
+}
+
+impl<T: Clone + PartialEq> Lww<T> {
+    fn new(value: T, stamp: Stamp) -> Self {
+        Self { value, stamp }
+    }
+
+    /// Sets the value if it has changed, using the stamp returned by `tick`
+    fn update(&mut self, value: &T, tick: &mut impl FnMut() -> Stamp) {
+        if self.value != *value {
+            *self = Self::new(value.clone(), tick());
+        }
+    }
+
+    fn merge(&mut self, other: &Self) {
+        if other.stamp > self.stamp {
+            *self = other.clone();
+        }
+    }
+}
+
+/// A task as replicated: each field is a register, so that concurrent edits
+/// of different fields both survive.  Deleted tasks are kept as tombstones,
+/// so that a replica which hasn't seen the deletion can't bring them back.
+#[derive(Clone, Serialize, Deserialize)]
+struct ReplicatedTask {
+    created: NaiveDateTime,
+    /// Where the task is in the list: a dense identifier, compared
+    /// lexicographically, with the task's uuid breaking ties
+    position: Lww<Vec<u32>>,
+    description: Lww<String>,
+    dot: Lww<bool>,
+    completed: Lww<Option<NaiveDateTime>>,
+    recurrence: Lww<(Option<u64>, Option<NaiveDateTime>)>,
+    snooze_until: Lww<Option<NaiveDateTime>>,
+    deleted: Lww<bool>,
+}
+
+impl ReplicatedTask {
+    fn new(task: &Task, position: Vec<u32>, stamp: Stamp) -> Self {
+        Self {
+            created: task.created(),
+            position: Lww::new(position, stamp),
+            description: Lww::new(task.description().to_string(), stamp),
+            dot: Lww::new(task.dot(), stamp),
+            completed: Lww::new(task.completed(), stamp),
+            recurrence: Lww::new(task.recurrence(), stamp),
+            snooze_until: Lww::new(task.snooze_until(), stamp),
+            deleted: Lww::new(false, stamp),
+        }
+    }
+
+    fn update(&mut self, task: &Task, tick: &mut impl FnMut() -> Stamp) {
+        self.description.update(&task.description().to_string(), tick);
+        self.dot.update(&task.dot(), tick);
+        self.completed.update(&task.completed(), tick);
+        self.recurrence.update(&task.recurrence(), tick);
+        self.snooze_until.update(&task.snooze_until(), tick);
+        self.deleted.update(&false, tick);
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.position.merge(&other.position);
+        self.description.merge(&other.description);
+        self.dot.merge(&other.dot);
+        self.completed.merge(&other.completed);
+        self.recurrence.merge(&other.recurrence);
+        self.snooze_until.merge(&other.snooze_until);
+        self.deleted.merge(&other.deleted);
+    }
+
+    fn task(&self, uuid: Uuid) -> Task {
+        Task::from_fields(uuid, self.created, self.description.value.clone(), self.dot.value,
+            self.completed.value, self.recurrence.value, self.snooze_until.value)
+    }
+}
+
+/// One replica of a task list that can be edited independently of the others,
+/// e.g. offline on another machine, and merged with them in any order, any
+/// number of times, always converging on the same list.
+///
+/// The list is a conflict-free replicated data type: each task's fields are
+/// last-writer-wins registers, ordered by Lamport timestamps, and its place in
+/// the list is a register holding a dense position, so that the list is a
+/// sequence in which any task can be moved.  Replicas exchange their whole
+/// state, as a file written with ``save`` and merged with ``merge_file``.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Replica {
+    id: Uuid,
+    clock: u64,
+    tasks: BTreeMap<Uuid, ReplicatedTask>,
+}
+
+impl Default for Replica {
+    fn default() -> Self {
+        Self::new(Uuid::new_v4())
+    }
+}
+
+impl Replica {
+    /// Creates an empty replica, identified by `id`, which must be unique to it
+    #[must_use]
+    pub fn new(id: Uuid) -> Self {
+        Self { id, clock: 0, tasks: BTreeMap::new() }
+    }
+
+    #[must_use]
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// Loads a replica's state from `path`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be read or parsed.
+    pub fn load(path: &Path) -> std::io::Result<Self> {
+        Ok(serde_json::from_slice(&read(path)?)?)
+    }
+
+    /// Saves the replica's state to `path`, atomically
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be written.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let serialized = serde_json::to_vec(self)?;
+        atomicfile::write_atomic(path, None, &serialized, |written| {
+            serde_json::from_slice::<Self>(written).map(|_| ()).map_err(Into::into)
+        })
+    }
+
+    /// Returns the list, in order, without deleted tasks
+    #[must_use]
+    pub fn tasks(&self) -> Vec<Task> {
+        let mut live = self.tasks.iter().filter(|(_, task)| !task.deleted.value).collect::<Vec<_>>();
+        live.sort_by(|(a_uuid, a), (b_uuid, b)| a.position.value.cmp(&b.position.value).then(a_uuid.cmp(b_uuid)));
+        live.into_iter().map(|(uuid, task)| task.task(*uuid)).collect()
+    }
+
+    /// Records the list as edited on this replica: tasks added, changed,
+    /// deleted and moved since it was last updated are stamped as changed here.
+    /// Moves are recorded for as few tasks as possible, so that they don't
+    /// override tasks moved concurrently elsewhere.
+    pub fn update(&mut self, tasks: &[Task]) {
+        let (id, clock) = (self.id, &mut self.clock);
+        let mut tick = || {
+            *clock += 1;
+            Stamp { counter: *clock, replica: id }
+        };
+        let listed = tasks.iter().map(Task::uuid).collect::<HashSet<_>>();
+        for (uuid, task) in &mut self.tasks {
+            if !listed.contains(uuid) {
+                task.deleted.update(&true, &mut tick);
+            }
+        }
+
+        // Keep the positions of the longest run of tasks already in order
+        let positions = tasks.iter()
+            .map(|task| self.tasks.get(&task.uuid()).filter(|t| !t.deleted.value).map(|t| t.position.value.clone()))
+            .collect::<Vec<_>>();
+        let keep = longest_increasing(&positions);
+        let mut previous: Vec<u32> = vec![];
+        for (index, task) in tasks.iter().enumerate() {
+            let position = if keep[index] {
+                positions[index].clone().unwrap_or_default()
+            } else {
+                let next = (index + 1..tasks.len()).find(|&next| keep[next])
+                    .and_then(|next| positions[next].clone());
+                between(&previous, next.as_deref())
+            };
+            if let Some(replicated) = self.tasks.get_mut(&task.uuid()) {
+                replicated.position.update(&position, &mut tick);
+                replicated.update(task, &mut tick);
+            } else {
+                self.tasks.insert(task.uuid(), ReplicatedTask::new(task, position.clone(), tick()));
+            }
+            previous = position;
+        }
+    }
+
+    /// Merges another replica's state into this one
+    pub fn merge(&mut self, other: &Self) {
+        self.clock = self.clock.max(other.clock);
+        for (uuid, task) in &other.tasks {
+            match self.tasks.get_mut(uuid) {
+                Some(ours) => ours.merge(task),
+                None => {
+                    self.tasks.insert(*uuid, task.clone());
+                },
+            }
+        }
+    }
+
+    /// Merges the state saved by another replica at `path`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the file can't be read or parsed.
+    pub fn merge_file(&mut self, path: &Path) -> std::io::Result<()> {
+        self.merge(&Self::load(path)?);
+        Ok(())
+    }
+}
+
+/// Returns which of `items` to keep so that the kept ones are in strictly
+/// increasing order and as many as possible.  `None` items are never kept.
+fn longest_increasing<T: Ord>(items: &[Option<T>]) -> Vec<bool> {
+    // tails[length - 1] is the index of the smallest item ending a run of that length
+    let mut tails: Vec<usize> = vec![];
+    let mut predecessor: HashMap<usize, usize> = HashMap::new();
+    for (index, item) in items.iter().enumerate() {
+        let Some(item) = item else {
+            continue;
+        };
+        let length = tails.partition_point(|&tail| items[tail].as_ref().is_some_and(|tail| tail < item));
+        if length > 0 {
+            predecessor.insert(index, tails[length - 1]);
+        }
+        if length == tails.len() {
+            tails.push(index);
+        } else {
+            tails[length] = index;
+        }
+    }
+    let mut keep = vec![false; items.len()];
+    let mut index = tails.last().copied();
+    while let Some(current) = index {
+        keep[current] = true;
+        index = predecessor.get(&current).copied();
+    }
+    keep
+}
+
+/// Returns a position after `low` and before `high` (or anywhere after `low`
+/// if `high` is `None`).  `low` must be before `high`.  Positions never end in
+/// 0, so there's always room between two of them.
+fn between(low: &[u32], high: Option<&[u32]>) -> Vec<u32> {
+    let mut position = vec![];
+    let mut high = high;
+    for depth in 0.. {
+        if depth >= low.len() && high.is_some_and(|high| depth >= high.len()) {
+            // Only if `low` isn't before `high`, so there's no right answer
+            high = None;
+        }
+        let low_digit = low.get(depth).copied().map_or(0, u64::from);
+        let high_digit = high.map_or(POSITION_LIMIT, |high| high.get(depth).copied().map_or(0, u64::from));
+        if high_digit > low_digit + 1 {
+            let digit = low_digit + ((high_digit - low_digit) / 2).min(POSITION_STEP);
+            position.push(u32::try_from(digit).unwrap_or(u32::MAX));
+            break;
+        }
+        position.push(u32::try_from(low_digit).unwrap_or(u32::MAX));
+        if high_digit > low_digit {
+            // Everything deeper is below `high` now
+            high = None;
+        }
+    }
+    position
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replica(id: u128) -> Replica {
+        Replica::new(Uuid::from_u128(id))
+    }
+
+    fn descriptions(replica: &Replica) -> Vec<String> {
+        replica.tasks().iter().map(|task| task.description().to_string()).collect()
+    }
+
+    // Replicas of a list of tasks named by `descriptions`, all starting from
+    // the same state
+    fn replicas<const N: usize>(descriptions: &[&str]) -> [Replica; N] {
+        let mut first = replica(1);
+        first.update(&descriptions.iter().map(|description| Task::new(description)).collect::<Vec<_>>());
+        std::array::from_fn(|index| {
+            let mut replica = replica(index as u128 + 1);
+            replica.merge(&first);
+            replica
+        })
+    }
+
+    fn moved(tasks: &[Task], from: usize, to: usize) -> Vec<Task> {
+        let mut tasks = tasks.to_vec();
+        let task = tasks.remove(from);
+        tasks.insert(to, task);
+        tasks
+    }
+
+    fn is_increasing(positions: &[Vec<u32>]) -> bool {
+        positions.windows(2).all(|pair| pair[0] < pair[1])
+    }
+
+    #[test]
+    fn concurrent_edits_to_different_fields_are_both_kept() {
+        let [mut a, mut b] = replicas(&["one"]);
+        let mut edited = a.tasks();
+        edited[0].update_description("renamed");
+        a.update(&edited);
+        let mut dotted = b.tasks();
+        dotted[0].toggle_dot();
+        b.update(&dotted);
+
+        a.merge(&b);
+        b.merge(&a);
+        for replica in [&a, &b] {
+            let tasks = replica.tasks();
+            assert_eq!(tasks.len(), 1);
+            assert_eq!(tasks[0].description(), "renamed");
+            assert!(tasks[0].dot());
+        }
+    }
+
+    #[test]
+    fn concurrent_edits_to_the_same_field_agree() {
+        let [mut a, mut b] = replicas(&["one"]);
+        for (replica, description) in [(&mut a, "by a"), (&mut b, "by b")] {
+            let mut tasks = replica.tasks();
+            tasks[0].update_description(description);
+            replica.update(&tasks);
+        }
+
+        a.merge(&b);
+        b.merge(&a);
+        assert_eq!(descriptions(&a), descriptions(&b));
+    }
+
+    #[test]
+    fn concurrent_move_and_delete_deletes() {
+        let [mut a, mut b] = replicas(&["one", "two", "three"]);
+        a.update(&moved(&a.tasks(), 0, 2));
+        b.update(&b.tasks()[1..]);
+
+        a.merge(&b);
+        b.merge(&a);
+        assert_eq!(descriptions(&a), ["two", "three"]);
+        assert_eq!(descriptions(&b), ["two", "three"]);
+    }
+
+    #[test]
+    fn concurrent_moves_of_different_tasks_are_both_kept() {
+        let [mut a, mut b] = replicas(&["one", "two", "three", "four"]);
+        a.update(&moved(&a.tasks(), 0, 1));
+        b.update(&moved(&b.tasks(), 3, 2));
+
+        a.merge(&b);
+        b.merge(&a);
+        assert_eq!(descriptions(&a), ["two", "one", "four", "three"]);
+        assert_eq!(descriptions(&b), ["two", "one", "four", "three"]);
+    }
+
+    #[test]
+    fn merging_in_any_order_converges() {
+        let [mut a, mut b, mut c] = replicas(&["one", "two", "three"]);
+        let mut tasks = moved(&a.tasks(), 2, 0);
+        tasks[1].update_description("first");
+        a.update(&tasks);
+        let mut tasks = b.tasks();
+        tasks.insert(1, Task::new("added"));
+        tasks[0].toggle_dot();
+        b.update(&tasks);
+        let mut tasks = c.tasks();
+        tasks.remove(1);
+        tasks[0].update_description("second");
+        c.update(&tasks);
+
+        let orders = [[&a, &b, &c], [&a, &c, &b], [&b, &a, &c], [&b, &c, &a], [&c, &a, &b], [&c, &b, &a]];
+        let merged = orders.map(|order| {
+            let mut merged = replica(4);
+            for replica in order {
+                merged.merge(replica);
+            }
+            merged.tasks()
+        });
+        assert!(merged.iter().all(|tasks| *tasks == merged[0]));
+        let first = &merged[0];
+        assert_eq!(first.iter().map(Task::description).collect::<Vec<_>>(), ["three", "second", "added"]);
+        assert!(first[1].dot());
+
+        // Merging again, or merging a replica into itself, changes nothing
+        let mut again = replica(4);
+        again.merge(&a);
+        again.merge(&b);
+        again.merge(&c);
+        let state = again.clone();
+        again.merge(&state);
+        again.merge(&b);
+        assert!(again.tasks() == *first);
+    }
+
+    #[test]
+    fn merges_through_saved_state() {
+        let [mut a, mut b] = replicas(&["one"]);
+        let mut tasks = a.tasks();
+        tasks.push(Task::new("two"));
+        a.update(&tasks);
+        let path = std::env::temp_dir().join(format!("task-crdt-test-{}.json", Uuid::new_v4()));
+        a.save(&path).expect("saving the state");
+        let merged = b.merge_file(&path);
+        std::fs::remove_file(&path).expect("removing the state");
+        merged.expect("merging the state");
+        assert_eq!(descriptions(&b), ["one", "two"]);
+    }
+
+    #[test]
+    fn update_keeps_positions_of_unmoved_tasks() {
+        let [mut a] = replicas(&["one", "two", "three", "four"]);
+        let before = a.tasks.clone();
+        a.update(&moved(&a.tasks(), 3, 0));
+        let changed = a.tasks.iter()
+            .filter(|(uuid, task)| task.position.stamp != before[uuid].position.stamp)
+            .count();
+        assert_eq!(changed, 1);
+        assert_eq!(descriptions(&a), ["four", "one", "two", "three"]);
+    }
+
+    #[test]
+    fn longest_increasing_keeps_the_longest_run() {
+        assert_eq!(longest_increasing::<u32>(&[]), Vec::<bool>::new());
+        assert_eq!(longest_increasing(&[Some(1), Some(2), Some(3)]), [true, true, true]);
+        assert_eq!(longest_increasing(&[Some(4), Some(1), Some(2), Some(3)]), [false, true, true, true]);
+        assert_eq!(longest_increasing(&[Some(1), None, Some(2)]), [true, false, true]);
+        assert_eq!(longest_increasing::<u32>(&[None, None]), [false, false]);
+        // Equal positions aren't in strictly increasing order
+        assert_eq!(longest_increasing(&[Some(1), Some(1), Some(1)]).iter().filter(|&&keep| keep).count(), 1);
+        assert_eq!(longest_increasing(&[Some(1), Some(2), Some(2), Some(3)]).iter().filter(|&&keep| keep).count(), 3);
+    }
+
+    #[test]
+    fn between_is_between() {
+        let cases: [(&[u32], Option<&[u32]>); 8] = [
+            (&[], None),
+            (&[], Some(&[1])),
+            (&[1], Some(&[2])),
+            (&[1], Some(&[1, 1])),
+            (&[5, 1], Some(&[6])),
+            (&[u32::MAX - 1], Some(&[u32::MAX])),
+            (&[u32::MAX - 1, u32::MAX], Some(&[u32::MAX])),
+            (&[u32::MAX], None),
+        ];
+        for (low, high) in cases {
+            let position = between(low, high);
+            assert!(position.as_slice() > low, "{position:?} isn't after {low:?}");
+            if let Some(high) = high {
+                assert!(position.as_slice() < high, "{position:?} isn't before {high:?}");
+            }
+            assert_ne!(position.last(), Some(&0));
+        }
+    }
+
+    #[test]
+    fn between_equal_positions_is_after_them() {
+        let position = between(&[5], Some(&[5]));
+        assert!(position.as_slice() > [5].as_slice());
+        assert_ne!(position.last(), Some(&0));
+    }
+
+    #[test]
+    fn between_repeatedly_stays_in_order() {
+        // Always inserting after the first task
+        let mut positions = vec![between(&[], None), between(&between(&[], None), None)];
+        for _ in 0..100 {
+            let position = between(&positions[0], Some(&positions[1]));
+            positions.insert(1, position);
+        }
+        assert!(is_increasing(&positions));
+        // Always appending, past the end of the range at the top level
+        let mut positions = vec![vec![u32::try_from(POSITION_LIMIT - POSITION_STEP).expect("a top-level position")]];
+        for _ in 0..100 {
+            positions.push(between(&positions[positions.len() - 1], None));
+        }
+        assert!(is_increasing(&positions));
+        assert!(positions.iter().all(|position| position.last() != Some(&0)));
+    }
+}
@@ -0,0 +1,35 @@
+use std::path::PathBuf;
+
+use crate::{JsonFileStore, TaskLists};
+
+/// Environment variable overriding the data directory
+pub const DATA_DIR_ENV : &str = "TASK_DATA_DIR";
+/// Environment variable selecting a profile
+pub const PROFILE_ENV : &str = "TASK_PROFILE";
+
+const PROFILES_DIR : &str = "profiles";
+
+/// Works out the data directory to use.
+///
+/// The base directory is `data_dir` if given (e.g. from the command line),
+/// otherwise `$TASK_DATA_DIR` if set, otherwise the default
+/// (``JsonFileStore::default_dir``).  If a profile is selected, by `profile`
+/// or `$TASK_PROFILE`, the data directory is `profiles/<profile>` under the
+/// base, so each profile has its own settings and lists.
+///
+/// # Errors
+///
+/// Will return `Err` with kind `InvalidInput` if the profile name isn't valid.
+pub fn resolve_data_dir(data_dir: Option<PathBuf>, profile: Option<String>) -> std::io::Result<PathBuf> {
+    let base = data_dir
+        .or_else(|| std::env::var_os(DATA_DIR_ENV).filter(|dir| !dir.is_empty()).map(PathBuf::from))
+        .unwrap_or_else(JsonFileStore::default_dir);
+    let profile = profile.or_else(|| std::env::var(PROFILE_ENV).ok().filter(|profile| !profile.is_empty()));
+    match profile {
+        Some(profile) => {
+            TaskLists::validate_name(&profile)?;
+            Ok(base.join(PROFILES_DIR).join(profile))
+        },
+        None => Ok(base),
+    }
+}
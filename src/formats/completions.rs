@@ -0,0 +1,265 @@
+use std::collections::HashSet;
+
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::Serialize;
+
+use crate::Task;
+
+const DATE_FORMAT : &str = "%Y-%m-%d";
+const TIME_FORMAT : &str = "%Y-%m-%d %H:%M:%S";
+
+/// The format of an export of the completion history
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HistoryFormat {
+    /// Comma separated values, with a header row
+    Csv,
+    /// A JSON object per line
+    JsonLines,
+}
+
+impl HistoryFormat {
+    /// The names of the formats, as given on the command line
+    pub const NAMES : &[&str] = &["csv", "jsonl"];
+
+    /// Returns the format called `name`, if there is one
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "csv" => Some(Self::Csv),
+            "jsonl" | "jsonlines" | "ndjson" => Some(Self::JsonLines),
+            _ => None,
+        }
+    }
+}
+
+/// The days, inclusive, from which completions are exported.  Either end may
+/// be open.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct DateRange {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
+}
+
+impl DateRange {
+    /// Parses a range written `FROM..TO`, where either date (`YYYY-MM-DD`)
+    /// may be left out, or a single date for just that day.  An empty range
+    /// is every day.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` with a message for the user if a date isn't valid.
+    pub fn parse(range: &str) -> Result<Self, String> {
+        let (from, to) = range.split_once("..").unwrap_or((range, range));
+        Ok(Self { from: Self::parse_date(from)?, to: Self::parse_date(to)? })
+    }
+
+    /// Parses a date written `YYYY-MM-DD`, or nothing, for an open end
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` with a message for the user if the date isn't valid.
+    pub fn parse_date(date: &str) -> Result<Option<NaiveDate>, String> {
+        let date = date.trim();
+        if date.is_empty() {
+            return Ok(None);
+        }
+        NaiveDate::parse_from_str(date, DATE_FORMAT).map(Some)
+            .map_err(|err| format!("Bad date {date}, which should be YYYY-MM-DD: {err}"))
+    }
+
+    #[must_use]
+    pub fn contains(&self, time: NaiveDateTime) -> bool {
+        self.from.is_none_or(|from| time.date() >= from) && self.to.is_none_or(|to| time.date() <= to)
+    }
+}
+
+/// A completed task, as a row of the history
+#[derive(Serialize)]
+struct Completion<'a> {
+    description: &'a str,
+    uuid: String,
+    created: String,
+    completed: String,
+    time_to_complete_secs: i64,
+    recur_interval_days: Option<u64>,
+}
+
+impl<'a> Completion<'a> {
+    fn of(task: &'a Task) -> Self {
+        let completed = task.completed_date_time();
+        Self {
+            description: task.description(),
+            uuid: task.uuid().to_string(),
+            created: task.created().format(TIME_FORMAT).to_string(),
+            completed: completed.format(TIME_FORMAT).to_string(),
+            time_to_complete_secs: (completed - task.created()).num_seconds(),
+            recur_interval_days: task.recur_interval_days(),
+        }
+    }
+
+    const CSV_HEADER : &'static str = "description,uuid,created,completed,time_to_complete_secs,recur_interval_days";
+
+    fn to_csv(&self) -> String {
+        [
+            csv_field(self.description),
+            self.uuid.clone(),
+            self.created.clone(),
+            self.completed.clone(),
+            self.time_to_complete_secs.to_string(),
+            self.recur_interval_days.map(|days| days.to_string()).unwrap_or_default(),
+        ].join(",")
+    }
+}
+
+/// Returns the completed tasks in `tasks`, e.g. the list and its archive,
+/// which were completed in `range`, oldest completion first.  A task found
+/// more than once (e.g. in both, if archiving was interrupted) is only
+/// returned once.
+#[must_use]
+pub fn completions<'a>(tasks: impl IntoIterator<Item = &'a Task>, range: DateRange) -> Vec<&'a Task> {
+    let mut seen = HashSet::new();
+    let mut completed = tasks.into_iter()
+        .filter(|task| task.is_complete() && range.contains(task.completed_date_time()))
+        .filter(|task| seen.insert(task.uuid()))
+        .collect::<Vec<_>>();
+    completed.sort_by_key(|task| task.completed_date_time());
+    completed
+}
+
+/// Writes completed tasks, e.g. from ``completions``, as a row each, with
+/// their description, uuid, the times they were created and completed, the
+/// seconds between, and the days between recurrences for recurring tasks.
+/// Times are local.  Descriptions which a spreadsheet would take for a
+/// formula are written to CSV after a `'`, so that they're shown as text.
+///
+/// # Errors
+///
+/// Will return `Err` if a row can't be written as JSON.
+pub fn to_history(completed: &[&Task], format: HistoryFormat) -> std::io::Result<String> {
+    let mut output = String::new();
+    if format == HistoryFormat::Csv {
+        output.push_str(Completion::CSV_HEADER);
+        output.push('\n');
+    }
+    for task in completed {
+        let completion = Completion::of(task);
+        match format {
+            HistoryFormat::Csv => output.push_str(&completion.to_csv()),
+            HistoryFormat::JsonLines => output.push_str(&serde_json::to_string(&completion)?),
+        }
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+/// Quotes a CSV field if it needs it, first putting a `'` before one that
+/// starts like a formula
+fn csv_field(field: &str) -> String {
+    if field.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        format!("\"'{}\"", field.replace('"', "\"\""))
+    } else if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn time(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, TIME_FORMAT).expect("a valid time")
+    }
+
+    fn date(text: &str) -> Option<NaiveDate> {
+        NaiveDate::parse_from_str(text, DATE_FORMAT).ok()
+    }
+
+    fn completed(description: &str, created: &str, completed: Option<&str>) -> Task {
+        Task::from_fields(Uuid::new_v4(), time(created), description.to_string(), false, completed.map(time),
+            (None, None), None)
+    }
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(DateRange::parse(""), Ok(DateRange::default()));
+        assert_eq!(DateRange::parse(".."), Ok(DateRange::default()));
+        assert_eq!(DateRange::parse("2024-01-01"), Ok(DateRange { from: date("2024-01-01"), to: date("2024-01-01") }));
+        assert_eq!(DateRange::parse("2024-01-01.."), Ok(DateRange { from: date("2024-01-01"), to: None }));
+        assert_eq!(DateRange::parse("..2024-02-01"), Ok(DateRange { from: None, to: date("2024-02-01") }));
+        assert_eq!(DateRange::parse(" 2024-01-01 .. 2024-02-01 "),
+            Ok(DateRange { from: date("2024-01-01"), to: date("2024-02-01") }));
+        assert!(DateRange::parse("2024-13-01").is_err());
+        assert!(DateRange::parse("yesterday..").is_err());
+    }
+
+    #[test]
+    fn ranges_include_both_ends() {
+        let range = DateRange { from: date("2024-01-01"), to: date("2024-01-31") };
+        assert!(range.contains(time("2024-01-01 00:00:00")));
+        assert!(range.contains(time("2024-01-31 23:59:59")));
+        assert!(!range.contains(time("2023-12-31 23:59:59")));
+        assert!(!range.contains(time("2024-02-01 00:00:00")));
+        assert!(DateRange::default().contains(time("1970-01-01 00:00:00")));
+    }
+
+    #[test]
+    fn names_formats() {
+        assert_eq!(HistoryFormat::from_name("CSV"), Some(HistoryFormat::Csv));
+        assert_eq!(HistoryFormat::from_name("ndjson"), Some(HistoryFormat::JsonLines));
+        assert_eq!(HistoryFormat::from_name("xml"), None);
+        assert!(HistoryFormat::NAMES.iter().all(|name| HistoryFormat::from_name(name).is_some()));
+    }
+
+    #[test]
+    fn completions_are_in_range_once_and_oldest_first() {
+        let later = completed("Later", "2024-01-01 09:00:00", Some("2024-01-20 10:00:00"));
+        let earlier = completed("Earlier", "2024-01-01 09:00:00", Some("2024-01-10 10:00:00"));
+        let outside = completed("Outside", "2024-01-01 09:00:00", Some("2024-03-01 10:00:00"));
+        let pending = completed("Pending", "2024-01-01 09:00:00", None);
+        let tasks = [later.clone(), pending, outside, earlier, later];
+        let range = DateRange::parse("..2024-01-31").expect("a valid range");
+        let found = completions(&tasks, range);
+        assert_eq!(found.iter().map(|task| task.description()).collect::<Vec<_>>(), ["Earlier", "Later"]);
+    }
+
+    #[test]
+    fn writes_csv() {
+        let task = completed("Say \"hi\", then\nleave", "2024-01-01 09:00:00", Some("2024-01-01 10:30:00"));
+        let csv = to_history(&[&task], HistoryFormat::Csv).expect("CSV");
+        assert_eq!(csv, format!("{}\n\"Say \"\"hi\"\", then\nleave\",{},2024-01-01 09:00:00,2024-01-01 10:30:00,5400,\n",
+            Completion::CSV_HEADER, task.uuid()));
+        assert_eq!(to_history(&[], HistoryFormat::Csv).expect("CSV"), format!("{}\n", Completion::CSV_HEADER));
+    }
+
+    #[test]
+    fn writes_formulas_in_csv_as_text() {
+        for (description, field) in [("=1+2", "\"'=1+2\""), ("+1", "\"'+1\""), ("-1", "\"'-1\""),
+                ("@SUM(A1)", "\"'@SUM(A1)\""), ("=HYPERLINK(\"x\")", "\"'=HYPERLINK(\"\"x\"\")\""),
+                ("a=1", "a=1"), ("1-2", "1-2")] {
+            assert_eq!(csv_field(description), field, "{description}");
+        }
+    }
+
+    #[test]
+    fn writes_json_lines() {
+        let mut task = completed("Daily", "2024-01-01 09:00:00", None);
+        task.set_recur_daily();
+        let task = Task::from_fields(task.uuid(), task.created(), task.description().to_string(), false,
+            Some(time("2024-01-02 09:00:01")), task.recurrence(), None);
+        let lines = to_history(&[&task, &task], HistoryFormat::JsonLines).expect("JSON Lines");
+        assert_eq!(lines.lines().count(), 2);
+        let row: serde_json::Value = serde_json::from_str(lines.lines().next().unwrap_or_default()).expect("JSON");
+        assert_eq!(row, serde_json::json!({
+            "description": "Daily",
+            "uuid": task.uuid().to_string(),
+            "created": "2024-01-01 09:00:00",
+            "completed": "2024-01-02 09:00:01",
+            "time_to_complete_secs": 86401,
+            "recur_interval_days": 1,
+        }));
+    }
+}
@@ -0,0 +1,404 @@
+use std::collections::BTreeMap;
+
+use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use uuid::Uuid;
+
+use crate::{Task, MAX_RECUR_DAYS};
+
+// Times in UTC, and in local time
+const UTC_FORMAT : &str = "%Y%m%dT%H%M%SZ";
+const LOCAL_FORMAT : &str = "%Y%m%dT%H%M%S";
+const DATE_FORMAT : &str = "%Y%m%d";
+
+// Lines longer than this many bytes are folded
+const MAX_LINE : usize = 75;
+
+// The priority dotted tasks are given: the highest
+const DOT_PRIORITY : u32 = 1;
+
+// Properties which say nothing about the task itself, so there's no need to
+// report them as lost
+const IGNORED_PROPERTIES : &[&str] = &["DTSTAMP", "LAST-MODIFIED", "SEQUENCE", "STATUS"];
+
+/// Writes tasks as an iCalendar file of VTODO components, in order.
+///
+/// Each task's uuid is its UID, and its description the SUMMARY.  CREATED
+/// and COMPLETED are the times it was created and completed.  Dotted tasks
+/// have the highest PRIORITY.  Recurring tasks have a daily RRULE, and the
+/// time a recurring or snoozed task next appears is its DTSTART.
+#[must_use]
+pub fn to_icalendar(tasks: &[Task]) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        format!("PRODID:-//{}//{}//EN", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+    ];
+    let stamp = Utc::now().naive_utc().format(UTC_FORMAT).to_string();
+    for task in tasks {
+        let (recur_interval_days, recur_next) = task.recurrence();
+        lines.push("BEGIN:VTODO".to_string());
+        lines.push(format!("UID:{}", task.uuid()));
+        lines.push(format!("DTSTAMP:{stamp}"));
+        lines.push(format!("SUMMARY:{}", escape(task.description())));
+        lines.push(format!("CREATED:{}", format_time(task.created())));
+        if let Some(completed) = task.completed() {
+            lines.push("STATUS:COMPLETED".to_string());
+            lines.push(format!("COMPLETED:{}", format_time(completed)));
+        } else {
+            lines.push("STATUS:NEEDS-ACTION".to_string());
+        }
+        if task.dot() {
+            lines.push(format!("PRIORITY:{DOT_PRIORITY}"));
+        }
+        // A recurrence rule needs a start, from which it recurs
+        let start = recur_next.or(task.snooze_until())
+            .or(recur_interval_days.map(|_| task.created()));
+        if let Some(start) = start {
+            lines.push(format!("DTSTART:{}", format_time(start)));
+        }
+        if let Some(days) = recur_interval_days {
+            lines.push(if days == 1 { "RRULE:FREQ=DAILY".to_string() } else { format!("RRULE:FREQ=DAILY;INTERVAL={days}") });
+        }
+        lines.push("END:VTODO".to_string());
+    }
+    lines.push("END:VCALENDAR".to_string());
+    let mut output = String::new();
+    for line in lines {
+        fold(&line, &mut output);
+    }
+    output
+}
+
+/// Reads the VTODO components of an iCalendar file as tasks, in order.
+///
+/// This reads what ``to_icalendar`` writes, and the same from calendar apps.
+/// UIDs which are uuids are kept, and other tasks are given new ones.  Any of
+/// the top four priorities dots a task.  Recurrence is kept if it's a simple
+/// daily or weekly RRULE, e.g. `FREQ=WEEKLY;INTERVAL=2`.  A DTSTART in the
+/// future snoozes a task until then, and is when a recurring task next
+/// occurs.  Times with a TZID are taken as local time.  Cancelled tasks are
+/// skipped.
+///
+/// Returns the tasks, and lines reporting anything that couldn't be kept.
+///
+/// # Errors
+///
+/// Will return `Err` if `text` isn't an iCalendar file, or has a VTODO with
+/// a bad time in it.
+pub fn from_icalendar(text: &str) -> std::io::Result<(Vec<Task>, Vec<String>)> {
+    let invalid = |problem: String| std::io::Error::new(std::io::ErrorKind::InvalidData, problem);
+    let lines = unfold(text);
+    if !lines.first().is_some_and(|line| line.eq_ignore_ascii_case("BEGIN:VCALENDAR")) {
+        return Err(invalid("Not an iCalendar file: it doesn't start with BEGIN:VCALENDAR".to_string()));
+    }
+    let mut tasks = vec![];
+    let mut unmapped: BTreeMap<String, usize> = BTreeMap::new();
+    let mut notes: BTreeMap<&str, usize> = BTreeMap::new();
+    // The properties of the VTODO we're in, if we are, and how deeply
+    // components are nested within it
+    let mut todo: Option<Vec<Property>> = None;
+    let mut depth = 0;
+    for line in &lines {
+        let property = Property::parse(line);
+        match (&mut todo, property.name.as_str()) {
+            (None, "BEGIN") if property.value.eq_ignore_ascii_case("VTODO") => todo = Some(vec![]),
+            (Some(properties), "END") if depth == 0 => {
+                let properties = std::mem::take(properties);
+                todo = None;
+                match to_task(&properties, &mut unmapped, &mut notes) {
+                    Ok(Some(task)) => tasks.push(task),
+                    Ok(None) => (),
+                    Err(problem) => return Err(invalid(format!("Task {}: {problem}", tasks.len() + 1))),
+                }
+            },
+            (Some(_), "BEGIN") => {
+                if depth == 0 {
+                    *unmapped.entry(property.value.to_ascii_uppercase()).or_default() += 1;
+                }
+                depth += 1;
+            },
+            (Some(_), "END") => depth -= 1,
+            (Some(properties), _) if depth == 0 => properties.push(property),
+            _ => (),
+        }
+    }
+    let mut report = unmapped.into_iter()
+        .map(|(name, count)| format!("{name}: not kept, on {count} tasks"))
+        .collect::<Vec<_>>();
+    report.extend(notes.into_iter().map(|(note, count)| format!("{count} {note}")));
+    Ok((tasks, report))
+}
+
+/// A content line: a property's name, parameters and value
+struct Property {
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    fn parse(line: &str) -> Self {
+        // The value starts at the first colon which isn't in a quoted parameter
+        let mut quoted = false;
+        let colon = line.char_indices()
+            .find(|&(_, c)| {
+                if c == '"' {
+                    quoted = !quoted;
+                }
+                c == ':' && !quoted
+            })
+            .map_or(line.len(), |(index, _)| index);
+        let (head, value) = line.split_at(colon);
+        let mut parts = head.split(';');
+        let name = parts.next().unwrap_or_default().to_ascii_uppercase();
+        let params = parts.filter_map(|param| param.split_once('='))
+            .map(|(name, value)| (name.to_ascii_uppercase(), value.trim_matches('"').to_string()))
+            .collect();
+        Self { name, params, value: value.strip_prefix(':').unwrap_or(value).to_string() }
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params.iter().find(|(param, _)| param == name).map(|(_, value)| value.as_str())
+    }
+
+    /// Reads the value as a time, in local time
+    fn time(&self) -> Result<NaiveDateTime, String> {
+        let value = self.value.trim();
+        if self.param("VALUE").is_some_and(|kind| kind.eq_ignore_ascii_case("DATE")) || !value.contains('T') {
+            return NaiveDate::parse_from_str(value, DATE_FORMAT).map(|date| date.and_time(NaiveTime::MIN))
+                .map_err(|err| format!("bad {} date '{value}': {err}", self.name));
+        }
+        match value.strip_suffix('Z') {
+            Some(utc) => NaiveDateTime::parse_from_str(utc, LOCAL_FORMAT)
+                .map(|time| Utc.from_utc_datetime(&time).with_timezone(&Local).naive_local()),
+            None => NaiveDateTime::parse_from_str(value, LOCAL_FORMAT),
+        }.map_err(|err| format!("bad {} time '{value}': {err}", self.name))
+    }
+}
+
+/// Makes a task from a VTODO's properties, noting anything that can't be
+/// kept.  Returns None if the task is to be skipped.
+fn to_task(properties: &[Property], unmapped: &mut BTreeMap<String, usize>, notes: &mut BTreeMap<&str, usize>)
+        -> Result<Option<Task>, String> {
+    let find = |name: &str| properties.iter().find(|property| property.name == name);
+    if find("STATUS").is_some_and(|status| status.value.eq_ignore_ascii_case("CANCELLED")) {
+        *notes.entry("cancelled tasks skipped").or_default() += 1;
+        return Ok(None);
+    }
+    if properties.iter().any(|property| property.param("TZID").is_some()) {
+        *notes.entry("tasks had times in other time zones, taken as local time").or_default() += 1;
+    }
+    let uuid = if let Some(Ok(uuid)) = find("UID").map(|uid| Uuid::parse_str(uid.value.trim())) {
+        uuid
+    } else {
+        *notes.entry("tasks without uuids for UIDs were given new ones").or_default() += 1;
+        Uuid::new_v4()
+    };
+    let description = find("SUMMARY").map(|summary| unescape(&summary.value)).unwrap_or_default();
+    let created = find("CREATED").map(Property::time).transpose()?.unwrap_or_else(|| Local::now().naive_local());
+    let completed = match find("COMPLETED").map(Property::time).transpose()? {
+        Some(completed) => Some(completed),
+        None if find("STATUS").is_some_and(|status| status.value.eq_ignore_ascii_case("COMPLETED")) => Some(created),
+        None => None,
+    };
+    let dot = completed.is_none()
+        && find("PRIORITY").and_then(|priority| priority.value.trim().parse::<u32>().ok())
+            .is_some_and(|priority| (1..=4).contains(&priority));
+    let rule = find("RRULE");
+    let recur_interval_days = rule.and_then(|rule| parse_rule(&rule.value));
+    if let (Some(rule), None) = (rule, recur_interval_days) {
+        *unmapped.entry(format!("RRULE '{}'", rule.value)).or_default() += 1;
+    }
+    let start = find("DTSTART").map(Property::time).transpose()?;
+    // Recurring tasks can't be snoozed, but wait until they next occur
+    let (recur_next, snooze_until) = match start {
+        Some(start) if recur_interval_days.is_some() => (Some(start), None),
+        Some(start) if start > Local::now().naive_local() => (None, Some(start)),
+        _ => (None, None),
+    };
+    for property in properties {
+        if !["UID", "SUMMARY", "CREATED", "COMPLETED", "PRIORITY", "RRULE", "DTSTART"].contains(&property.name.as_str())
+                && !IGNORED_PROPERTIES.contains(&property.name.as_str()) {
+            *unmapped.entry(property.name.clone()).or_default() += 1;
+        }
+    }
+    Ok(Some(Task::from_fields(uuid, created, description, dot, completed,
+        (recur_interval_days, recur_next), snooze_until)))
+}
+
+/// Parses a recurrence rule as a number of days, if it's simply every so
+/// many days or weeks
+fn parse_rule(rule: &str) -> Option<u64> {
+    let mut days: Option<u64> = None;
+    let mut interval = 1;
+    for part in rule.split(';') {
+        match part.split_once('=')? {
+            ("FREQ", "DAILY") => days = Some(1),
+            ("FREQ", "WEEKLY") => days = Some(7),
+            ("INTERVAL", count) => interval = count.parse::<u64>().ok().filter(|&count| count > 0)?,
+            ("WKST", _) => (),
+            _ => return None,
+        }
+    }
+    days?.checked_mul(interval).filter(|&days| days <= MAX_RECUR_DAYS)
+}
+
+fn format_time(time: NaiveDateTime) -> String {
+    // A local time which doesn't exist (in a clock change) is taken as UTC
+    Local.from_local_datetime(&time).earliest().map_or(time, |time| time.naive_utc())
+        .format(UTC_FORMAT).to_string()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace(';', "\\;").replace(',', "\\,").replace('\n', "\\n")
+}
+
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n' | 'N') => unescaped.push('\n'),
+                Some(escaped) => unescaped.push(escaped),
+                None => (),
+            }
+        } else {
+            unescaped.push(c);
+        }
+    }
+    unescaped
+}
+
+/// Writes a content line, folding it onto continuation lines if it's long
+fn fold(line: &str, output: &mut String) {
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > MAX_LINE {
+            output.push_str("\r\n ");
+            length = 1;
+        }
+        output.push(c);
+        length += c.len_utf8();
+    }
+    output.push_str("\r\n");
+}
+
+/// Splits text into content lines, joining continuation lines onto the line
+/// they continue
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    for line in text.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(continuation), Some(last)) => last.push_str(continuation),
+            _ if line.trim().is_empty() => (),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, LOCAL_FORMAT).expect("a valid time")
+    }
+
+    fn calendar(todos: &str) -> String {
+        format!("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n{todos}END:VCALENDAR\r\n")
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        let created = time("20240115T120000");
+        let tasks = vec![
+            Task::from_fields(Uuid::new_v4(), created, "Plain; with, escapes\\ and\nlines".to_string(), false,
+                None, (None, None), None),
+            Task::from_fields(Uuid::new_v4(), created, "Dotted ".repeat(30), true, None, (None, None), None),
+            Task::from_fields(Uuid::new_v4(), created, "Done".to_string(), false, Some(time("20240116T093000")),
+                (None, None), None),
+            Task::from_fields(Uuid::new_v4(), created, "Every 3 days".to_string(), false, None,
+                (Some(3), Some(time("20240118T050000"))), None),
+            Task::from_fields(Uuid::new_v4(), created, "Snoozed".to_string(), false, None,
+                (None, None), Some(time("20990101T050000"))),
+        ];
+        let (read, report) = from_icalendar(&to_icalendar(&tasks)).expect("a valid calendar");
+        assert!(report.is_empty(), "{report:?}");
+        assert_eq!(read.len(), tasks.len());
+        for (read, task) in read.iter().zip(&tasks) {
+            assert!(read == task, "{} was read back differently", task.description());
+        }
+    }
+
+    #[test]
+    fn folds_long_lines() {
+        let text = to_icalendar(&[Task::new(&"é".repeat(100))]);
+        assert!(text.split("\r\n").all(|line| line.len() <= MAX_LINE));
+        let (read, _) = from_icalendar(&text).expect("a valid calendar");
+        assert_eq!(read[0].description(), "é".repeat(100));
+    }
+
+    #[test]
+    fn reads_tasks_from_calendar_apps() {
+        let text = calendar("BEGIN:VTODO\r\nUID:not-a-uuid@example.com\r\nSUMMARY:Folded\r\n  summary\r\n\
+            PRIORITY:3\r\nDTSTART;TZID=\"Europe/London\":20990101T090000\r\nBEGIN:VALARM\r\nACTION:DISPLAY\r\n\
+            END:VALARM\r\nLOCATION:Home\r\nEND:VTODO\r\n\
+            BEGIN:VTODO\r\nSUMMARY:Fortnightly\r\nRRULE:FREQ=WEEKLY;INTERVAL=2;WKST=MO\r\n\
+            DTSTART;VALUE=DATE:20240120\r\nSTATUS:COMPLETED\r\nPRIORITY:1\r\nEND:VTODO\r\n\
+            BEGIN:VTODO\r\nSUMMARY:Monthly\r\nRRULE:FREQ=MONTHLY\r\nDTSTART:20200101T000000Z\r\nEND:VTODO\r\n\
+            BEGIN:VTODO\r\nSUMMARY:Cancelled\r\nSTATUS:CANCELLED\r\nEND:VTODO\r\n");
+        let (tasks, report) = from_icalendar(&text).expect("a valid calendar");
+        assert_eq!(tasks.iter().map(Task::description).collect::<Vec<_>>(), ["Folded summary", "Fortnightly", "Monthly"]);
+        assert!(tasks[0].dot());
+        assert_eq!(tasks[0].snooze_until(), Some(time("20990101T090000")));
+        assert!(tasks[1].is_complete());
+        assert_eq!(tasks[1].completed(), Some(tasks[1].created()));
+        assert!(!tasks[1].dot());
+        assert_eq!(tasks[1].recurrence(), (Some(14), Some(time("20240120T000000"))));
+        // A start in the past doesn't snooze
+        assert_eq!(tasks[2].recurrence(), (None, None));
+        assert_eq!(tasks[2].snooze_until(), None);
+        assert_eq!(report, [
+            "LOCATION: not kept, on 1 tasks",
+            "RRULE 'FREQ=MONTHLY': not kept, on 1 tasks",
+            "VALARM: not kept, on 1 tasks",
+            "1 cancelled tasks skipped",
+            "1 tasks had times in other time zones, taken as local time",
+            "3 tasks without uuids for UIDs were given new ones",
+        ]);
+    }
+
+    #[test]
+    fn rejects_invalid_calendars() {
+        assert!(from_icalendar("BEGIN:VTODO\r\nEND:VTODO\r\n").is_err());
+        let error = from_icalendar(&calendar("BEGIN:VTODO\r\nEND:VTODO\r\nBEGIN:VTODO\r\nCREATED:yesterday\r\nEND:VTODO\r\n"))
+            .err().map(|error| error.to_string());
+        assert!(error.is_some_and(|error| error.starts_with("Task 2: bad CREATED date 'yesterday'")));
+    }
+
+    #[test]
+    fn parses_properties() {
+        let property = Property::parse("dtstart;tzid=\"Zone:With:Colons\";VALUE=DATE-TIME:20240101T090000");
+        assert_eq!(property.name, "DTSTART");
+        assert_eq!(property.param("TZID"), Some("Zone:With:Colons"));
+        assert_eq!(property.param("VALUE"), Some("DATE-TIME"));
+        assert_eq!(property.value, "20240101T090000");
+        assert_eq!(Property::parse("SUMMARY:a:b").value, "a:b");
+    }
+
+    #[test]
+    fn parses_rules() {
+        assert_eq!(parse_rule("FREQ=DAILY"), Some(1));
+        assert_eq!(parse_rule("FREQ=DAILY;INTERVAL=3"), Some(3));
+        assert_eq!(parse_rule("INTERVAL=2;FREQ=WEEKLY"), Some(14));
+        assert_eq!(parse_rule("FREQ=WEEKLY;BYDAY=MO,WE"), None);
+        assert_eq!(parse_rule("FREQ=DAILY;INTERVAL=0"), None);
+        assert_eq!(parse_rule("FREQ=YEARLY"), None);
+        assert_eq!(parse_rule("FREQ=DAILY;INTERVAL=3650"), Some(3650));
+        assert_eq!(parse_rule("FREQ=WEEKLY;INTERVAL=600"), None);
+        assert_eq!(parse_rule("FREQ=DAILY;INTERVAL=100000000"), None);
+        assert_eq!(parse_rule("INTERVAL=2"), None);
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::BTreeMap;
+
+use chrono::Local;
+use uuid::Uuid;
+
+use crate::{Task, MAX_RECUR_DAYS};
+
+// Markers, as used by the Tasks plugin for Obsidian: dotted tasks are given
+// high priority, and recurring tasks say how often they recur
+const DOT_MARKER : &str = "⏫";
+const HIGHEST_PRIORITY_MARKER : &str = "🔺";
+const RECUR_MARKER : &str = "🔁";
+
+/// Writes tasks as a Markdown checklist, in order: `- [ ]` for tasks to do,
+/// and `- [x]` for completed tasks.  Dotted tasks are marked ⏫, and
+/// recurring tasks 🔁 with how often they recur, e.g. `🔁 every 2 days`.
+#[must_use]
+pub fn to_markdown<'a>(tasks: impl IntoIterator<Item = &'a Task>) -> String {
+    let mut output = String::new();
+    for task in tasks {
+        output.push_str(if task.is_complete() { "- [x] " } else { "- [ ] " });
+        // An item is a line
+        output.push_str(&task.description().split_whitespace().collect::<Vec<_>>().join(" "));
+        if task.dot() {
+            output.push(' ');
+            output.push_str(DOT_MARKER);
+        }
+        if let Some(days) = task.recur_interval_days() {
+            let every = match days {
+                1 => "every day".to_string(),
+                7 => "every week".to_string(),
+                _ if days % 7 == 0 => format!("every {} weeks", days / 7),
+                _ => format!("every {days} days"),
+            };
+            output.push(' ');
+            output.push_str(RECUR_MARKER);
+            output.push(' ');
+            output.push_str(&every);
+        }
+        output.push('\n');
+    }
+    output
+}
+
+/// Reads the unchecked items of Markdown checklists as new tasks, in order,
+/// ignoring everything else in the file.  Items at any depth are read, and
+/// may start with `-`, `*` or `+`.
+///
+/// This reads the markers ``to_markdown`` writes: ⏫ or 🔺 dots a task, and
+/// 🔁 with every so many days or weeks makes it recur.
+///
+/// Returns the tasks, and lines reporting anything that couldn't be kept,
+/// including how many checked items were skipped.
+#[must_use]
+pub fn from_markdown(text: &str) -> (Vec<Task>, Vec<String>) {
+    let mut tasks = vec![];
+    let mut checked = 0;
+    let mut unmapped: BTreeMap<String, usize> = BTreeMap::new();
+    for line in text.lines() {
+        let Some(item) = line.trim_start().strip_prefix(['-', '*', '+']).and_then(|item| item.strip_prefix(' ')) else {
+            continue;
+        };
+        let item = item.trim_start();
+        if item.starts_with("[x]") || item.starts_with("[X]") {
+            checked += 1;
+            continue;
+        }
+        let Some(item) = item.strip_prefix("[ ]") else {
+            continue;
+        };
+        let mut description = item.trim().to_string();
+        let mut dot = false;
+        for marker in [DOT_MARKER, HIGHEST_PRIORITY_MARKER] {
+            if description.contains(marker) {
+                dot = true;
+                description = description.replace(marker, "");
+            }
+        }
+        let mut recur_interval_days = None;
+        if let Some((before, every)) = description.split_once(RECUR_MARKER) {
+            match parse_every(every) {
+                Some(days) => {
+                    recur_interval_days = Some(days);
+                    description = before.to_string();
+                },
+                None => *unmapped.entry(format!("{RECUR_MARKER} '{}'", every.trim())).or_default() += 1,
+            }
+        }
+        let description = description.split_whitespace().collect::<Vec<_>>().join(" ");
+        if description.is_empty() {
+            continue;
+        }
+        tasks.push(Task::from_fields(Uuid::new_v4(), Local::now().naive_local(), description, dot, None,
+            (recur_interval_days, None), None));
+    }
+    let mut report = unmapped.into_iter()
+        .map(|(marker, count)| format!("{marker}: not kept, on {count} tasks"))
+        .collect::<Vec<_>>();
+    if checked > 0 {
+        report.push(format!("{checked} checked items skipped"));
+    }
+    (tasks, report)
+}
+
+/// Parses how often a task recurs, e.g. `every day` or `every 3 weeks`, as a
+/// number of days
+fn parse_every(every: &str) -> Option<u64> {
+    let words = every.split_whitespace().collect::<Vec<_>>();
+    let (count, unit) = match words.as_slice() {
+        ["every", unit] => (1, *unit),
+        ["every", count, unit] => (count.parse::<u64>().ok().filter(|&count| count > 0)?, *unit),
+        _ => return None,
+    };
+    let days = match unit {
+        "day" | "days" => 1,
+        "week" | "weeks" => 7,
+        _ => return None,
+    };
+    count.checked_mul(days).filter(|&days| days <= MAX_RECUR_DAYS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptions(tasks: &[Task]) -> Vec<&str> {
+        tasks.iter().map(Task::description).collect()
+    }
+
+    #[test]
+    fn reads_unchecked_items() {
+        let text = "# Heading\n\n- [ ] First\nSome text\n  * [ ] Nested\n+ [ ]   Spaced   out  \n\
+            - [x] Done\n- [X] Also done\n-[ ] Not an item\n- [ ]\n1. [ ] Numbered\n- Plain item\n";
+        let (tasks, report) = from_markdown(text);
+        assert_eq!(descriptions(&tasks), ["First", "Nested", "Spaced out"]);
+        assert_eq!(report, ["2 checked items skipped"]);
+    }
+
+    #[test]
+    fn reads_markers() {
+        let text = "- [ ] Urgent ⏫\n- [ ] Highest 🔺 priority\n- [ ] Daily 🔁 every day\n\
+            - [ ] Fortnightly ⏫ 🔁 every 2 weeks\n- [ ] Monthly 🔁 every month\n";
+        let (tasks, report) = from_markdown(text);
+        assert_eq!(descriptions(&tasks), ["Urgent", "Highest priority", "Daily", "Fortnightly", "Monthly 🔁 every month"]);
+        assert_eq!(tasks.iter().map(Task::dot).collect::<Vec<_>>(), [true, true, false, true, false]);
+        assert_eq!(tasks.iter().map(Task::recur_interval_days).collect::<Vec<_>>(), [None, None, Some(1), Some(14), None]);
+        assert_eq!(report, ["🔁 'every month': not kept, on 1 tasks"]);
+    }
+
+    #[test]
+    fn parses_how_often() {
+        assert_eq!(parse_every("every day"), Some(1));
+        assert_eq!(parse_every(" every 3 days "), Some(3));
+        assert_eq!(parse_every("every week"), Some(7));
+        assert_eq!(parse_every("every 2 weeks"), Some(14));
+        assert_eq!(parse_every("every 0 days"), None);
+        assert_eq!(parse_every("every weekday"), None);
+        assert_eq!(parse_every("daily"), None);
+        assert_eq!(parse_every("every 3650 days"), Some(3650));
+        assert_eq!(parse_every("every 600 weeks"), None);
+        assert_eq!(parse_every("every 100000000 days"), None);
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        let mut dotted = Task::new("Dotted");
+        dotted.toggle_dot();
+        let mut recurring = Task::new("Multi\nline recurring");
+        recurring.set_recur_daily();
+        let mut done = Task::new("Done");
+        let _ = done.complete();
+        let tasks = [dotted, recurring, done];
+        let text = to_markdown(&tasks);
+        assert_eq!(text, "- [ ] Dotted ⏫\n- [ ] Multi line recurring 🔁 every day\n- [x] Done\n");
+
+        let (read, report) = from_markdown(&text);
+        assert_eq!(descriptions(&read), ["Dotted", "Multi line recurring"]);
+        assert!(read[0].dot());
+        assert_eq!(read[1].recur_interval_days(), Some(1));
+        assert_eq!(report, ["1 checked items skipped"]);
+    }
+}
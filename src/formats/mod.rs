@@ -0,0 +1,82 @@
+pub mod todotxt;
+pub use todotxt::*;
+
+pub mod taskwarrior;
+pub use taskwarrior::*;
+
+pub mod icalendar;
+pub use icalendar::*;
+
+pub mod markdown;
+pub use markdown::*;
+
+pub mod completions;
+pub use completions::*;
+
+use crate::Task;
+
+/// A format that task lists can be exported to, and imported from, to
+/// exchange them with other apps
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    /// todo.txt, one task per line
+    TodoTxt,
+    /// Taskwarrior's JSON, as used by `task export` and `task import`
+    Taskwarrior,
+    /// iCalendar, as VTODO components, for calendar apps
+    ICalendar,
+    /// A Markdown checklist
+    Markdown,
+}
+
+impl Format {
+    /// The names of the formats, as given on the command line
+    pub const NAMES : &[&str] = &["todotxt", "taskwarrior", "ical", "markdown"];
+
+    /// Returns the format called `name`, if there is one
+    #[must_use]
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "todotxt" | "todo.txt" => Some(Self::TodoTxt),
+            "taskwarrior" | "tw" => Some(Self::Taskwarrior),
+            "ical" | "icalendar" | "ics" => Some(Self::ICalendar),
+            "markdown" | "md" => Some(Self::Markdown),
+            _ => None,
+        }
+    }
+
+    /// Writes `tasks` in this format.  Returns the text, and lines reporting
+    /// anything the format can't hold.
+    #[must_use]
+    pub fn export(self, tasks: &[Task]) -> (String, Vec<String>) {
+        match self {
+            Self::TodoTxt => (to_todo_txt(tasks), vec![]),
+            Self::Taskwarrior => to_taskwarrior(tasks),
+            Self::ICalendar => (to_icalendar(tasks), vec![]),
+            Self::Markdown => (to_markdown(tasks), vec![]),
+        }
+    }
+
+    /// Reads tasks written in this format.  Returns the tasks, and lines
+    /// reporting anything that couldn't be kept.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `text` isn't valid in this format.
+    pub fn import(self, text: &str) -> std::io::Result<(Vec<Task>, Vec<String>)> {
+        match self {
+            Self::TodoTxt => Ok((from_todo_txt(text), vec![])),
+            Self::Taskwarrior => from_taskwarrior(text),
+            Self::ICalendar => from_icalendar(text),
+            Self::Markdown => Ok(from_markdown(text)),
+        }
+    }
+
+    /// Returns true if tasks imported in this format are new tasks, which
+    /// are only duplicates of tasks in the list if their descriptions are the
+    /// same.  Otherwise, tasks are the same if their uuids are.
+    #[must_use]
+    pub fn imports_new_tasks(self) -> bool {
+        self == Self::Markdown
+    }
+}
@@ -0,0 +1,264 @@
+use std::collections::BTreeMap;
+
+use chrono::{Local, NaiveDateTime, TimeZone, Utc};
+use serde_json::{json, Map, Value};
+use uuid::Uuid;
+
+use crate::{Task, MAX_RECUR_DAYS};
+
+// Taskwarrior's dates, which are in UTC
+const DATE_FORMAT : &str = "%Y%m%dT%H%M%SZ";
+
+// Fields Taskwarrior works out for itself, or uses to keep track of
+// recurrence, which there's no need to report as lost
+const DERIVED_FIELDS : &[&str] = &["id", "urgency", "modified", "mask", "imask", "parent", "rtype"];
+
+/// Writes tasks as Taskwarrior JSON, as read by `task import`, in order.
+///
+/// Completed tasks have the status `completed` and their completion time as
+/// `end`.  The time a snoozed or recurring task next appears is its `wait`.
+/// Recurring tasks are given `due`, when they next occur, as Taskwarrior
+/// needs, and it makes those which aren't complete into recurring templates.
+///
+/// Returns the JSON, and lines reporting anything Taskwarrior has no field
+/// for (dots).
+#[must_use]
+pub fn to_taskwarrior(tasks: &[Task]) -> (String, Vec<String>) {
+    let mut unmapped = BTreeMap::new();
+    let exported = tasks.iter().map(|task| {
+        let (recur_interval_days, recur_next) = task.recurrence();
+        let mut fields = Map::new();
+        fields.insert("uuid".to_string(), json!(task.uuid()));
+        fields.insert("description".to_string(), json!(task.description()));
+        fields.insert("entry".to_string(), json!(format_date(task.created())));
+        let status = if let Some(completed) = task.completed() {
+            fields.insert("end".to_string(), json!(format_date(completed)));
+            "completed"
+        } else {
+            "pending"
+        };
+        fields.insert("status".to_string(), json!(status));
+        if let Some(days) = recur_interval_days {
+            fields.insert("recur".to_string(), json!(match days {
+                1 => "daily".to_string(),
+                7 => "weekly".to_string(),
+                _ => format!("{days}d"),
+            }));
+            fields.insert("due".to_string(), json!(format_date(recur_next.unwrap_or(task.created()))));
+        }
+        if let Some(wait) = recur_next.or(task.snooze_until()) {
+            fields.insert("wait".to_string(), json!(format_date(wait)));
+        }
+        if task.dot() {
+            *unmapped.entry("dot".to_string()).or_default() += 1;
+        }
+        Value::Object(fields)
+    }).collect::<Vec<_>>();
+    let report = unmapped.into_iter()
+        .map(|(field, count): (String, usize)| format!("{field}: Taskwarrior has no such field, lost from {count} tasks"))
+        .collect();
+    (Value::Array(exported).to_string(), report)
+}
+
+/// Reads Taskwarrior JSON, as written by `task export`, as tasks, keeping
+/// their uuids and order.
+///
+/// `description`, `uuid`, `entry` (the creation time), `end` (the completion
+/// time, for completed tasks), `wait` (as a snooze, or for recurring tasks,
+/// the next occurrence) and `recur` are kept.
+/// Recurrence is kept if it's a number of days or weeks, e.g. `daily`, `3d`
+/// or `P2W`.  Deleted tasks, and the templates Taskwarrior makes recurring
+/// tasks from, are skipped, but each instance of a recurring task is kept.
+///
+/// Returns the tasks, and lines reporting anything that couldn't be kept.
+///
+/// # Errors
+///
+/// Will return `Err` if `text` isn't a JSON array of tasks with uuids and
+/// descriptions.
+pub fn from_taskwarrior(text: &str) -> std::io::Result<(Vec<Task>, Vec<String>)> {
+    let exported: Vec<Map<String, Value>> = serde_json::from_str(text)?;
+    let mut tasks = vec![];
+    let mut unmapped: BTreeMap<String, usize> = BTreeMap::new();
+    let mut skipped: BTreeMap<String, usize> = BTreeMap::new();
+    for (index, fields) in exported.iter().enumerate() {
+        let invalid = |problem: &str| std::io::Error::new(std::io::ErrorKind::InvalidData,
+            format!("Task {} of {}: {problem}", index + 1, exported.len()));
+        let uuid = fields.get("uuid").and_then(Value::as_str).and_then(|uuid| Uuid::parse_str(uuid).ok())
+            .ok_or_else(|| invalid("no valid uuid"))?;
+        let description = fields.get("description").and_then(Value::as_str)
+            .ok_or_else(|| invalid("no description"))?;
+        let date = |name: &str| match fields.get(name).map(|date| date.as_str().and_then(parse_date)) {
+            Some(None) => Err(invalid(&format!("bad {name} date"))),
+            Some(Some(date)) => Ok(Some(date)),
+            None => Ok(None),
+        };
+        let status = fields.get("status").and_then(Value::as_str).unwrap_or("pending");
+        if status == "deleted" || status == "recurring" {
+            *skipped.entry(status.to_string()).or_default() += 1;
+            continue;
+        }
+        let created = date("entry")?.unwrap_or_else(|| Local::now().naive_local());
+        let completed = if status == "completed" { Some(date("end")?.unwrap_or(created)) } else { None };
+        let wait = date("wait")?;
+        let recur = fields.get("recur").and_then(Value::as_str);
+        let recur_interval_days = recur.and_then(parse_recur);
+        if let (Some(recur), None) = (recur, recur_interval_days) {
+            *unmapped.entry(format!("recur '{recur}'")).or_default() += 1;
+        }
+        let mut kept = vec!["uuid", "description", "status", "entry", "end", "wait", "recur"];
+        if recur_interval_days.is_some() {
+            // We work out when recurring tasks are next due for ourselves
+            kept.push("due");
+        }
+        for field in fields.keys() {
+            if !kept.contains(&field.as_str()) && !DERIVED_FIELDS.contains(&field.as_str()) {
+                *unmapped.entry(field.clone()).or_default() += 1;
+            }
+        }
+        // Recurring tasks can't be snoozed, but wait until they next occur
+        let (recur_next, snooze_until) = if recur_interval_days.is_some() { (wait, None) } else { (None, wait) };
+        tasks.push(Task::from_fields(uuid, created, description.to_string(), false, completed,
+            (recur_interval_days, recur_next), snooze_until));
+    }
+    let mut report = unmapped.into_iter()
+        .map(|(field, count)| format!("{field}: not kept, on {count} tasks"))
+        .collect::<Vec<_>>();
+    report.extend(skipped.into_iter().map(|(status, count)| match status.as_str() {
+        "recurring" => format!("{count} recurring task templates skipped, keeping their instances"),
+        _ => format!("{count} {status} tasks skipped"),
+    }));
+    Ok((tasks, report))
+}
+
+fn format_date(date: NaiveDateTime) -> String {
+    // A local time which doesn't exist (in a clock change) is taken as UTC
+    Local.from_local_datetime(&date).earliest().map_or(date, |date| date.naive_utc())
+        .format(DATE_FORMAT).to_string()
+}
+
+fn parse_date(date: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(date, DATE_FORMAT).ok()
+        .map(|date| Utc.from_utc_datetime(&date).with_timezone(&Local).naive_local())
+}
+
+/// Parses a Taskwarrior recurrence period as a number of days, if it is one
+fn parse_recur(recur: &str) -> Option<u64> {
+    let recur = recur.trim().to_ascii_lowercase();
+    match recur.as_str() {
+        "daily" | "day" => return Some(1),
+        "weekly" | "week" => return Some(7),
+        "biweekly" | "fortnight" => return Some(14),
+        _ => (),
+    }
+    let period = recur.strip_prefix('p').unwrap_or(&recur);
+    let count_end = period.find(|c: char| !c.is_ascii_digit()).unwrap_or(period.len());
+    let (count, unit) = period.split_at(count_end);
+    let count = if count.is_empty() { 1 } else { count.parse::<u64>().ok()? };
+    let days = match unit.trim() {
+        "d" | "day" | "days" => 1,
+        "w" | "wk" | "wks" | "week" | "weeks" => 7,
+        _ => return None,
+    };
+    count.checked_mul(days).filter(|&days| days > 0 && days <= MAX_RECUR_DAYS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID : &str = "7c8a3f4e-2b1d-4e5f-9a6b-0c1d2e3f4a5b";
+
+    fn exported(fields: &[Value]) -> String {
+        Value::Array(fields.to_vec()).to_string()
+    }
+
+    #[test]
+    fn reads_tasks() {
+        let text = exported(&[
+            json!({"uuid": UUID, "description": "Pending", "entry": "20240115T120000Z", "status": "pending",
+                "wait": "20300101T050000Z", "urgency": 1.5, "id": 1}),
+            json!({"uuid": Uuid::new_v4(), "description": "Done", "status": "completed", "end": "20240116T120000Z"}),
+            json!({"uuid": Uuid::new_v4(), "description": "Daily", "status": "pending", "recur": "daily",
+                "due": "20240117T050000Z", "wait": "20240117T050000Z"}),
+        ]);
+        let (tasks, report) = from_taskwarrior(&text).expect("valid tasks");
+        assert!(report.is_empty(), "{report:?}");
+        assert_eq!(tasks.len(), 3);
+        assert_eq!(tasks[0].uuid().to_string(), UUID);
+        assert_eq!(tasks[0].description(), "Pending");
+        assert_eq!(Some(tasks[0].created()), parse_date("20240115T120000Z"));
+        assert_eq!(tasks[0].snooze_until(), parse_date("20300101T050000Z"));
+        assert!(!tasks[0].is_complete());
+        assert_eq!(tasks[1].completed(), parse_date("20240116T120000Z"));
+        assert_eq!(tasks[2].recurrence(), (Some(1), parse_date("20240117T050000Z")));
+        assert_eq!(tasks[2].snooze_until(), None);
+    }
+
+    #[test]
+    fn reports_what_isnt_kept() {
+        let text = exported(&[
+            json!({"uuid": Uuid::new_v4(), "description": "Tagged", "tags": ["a"], "project": "p"}),
+            json!({"uuid": Uuid::new_v4(), "description": "Monthly", "recur": "monthly", "due": "20240117T050000Z"}),
+            json!({"uuid": Uuid::new_v4(), "description": "Deleted", "status": "deleted"}),
+            json!({"uuid": Uuid::new_v4(), "description": "Template", "status": "recurring", "recur": "daily"}),
+        ]);
+        let (tasks, report) = from_taskwarrior(&text).expect("valid tasks");
+        assert_eq!(tasks.iter().map(Task::description).collect::<Vec<_>>(), ["Tagged", "Monthly"]);
+        assert_eq!(tasks[1].recurrence(), (None, None));
+        assert_eq!(report, [
+            "due: not kept, on 1 tasks",
+            "project: not kept, on 1 tasks",
+            "recur 'monthly': not kept, on 1 tasks",
+            "tags: not kept, on 1 tasks",
+            "1 deleted tasks skipped",
+            "1 recurring task templates skipped, keeping their instances",
+        ]);
+    }
+
+    #[test]
+    fn rejects_invalid_tasks() {
+        assert!(from_taskwarrior("{}").is_err());
+        assert!(from_taskwarrior(&exported(&[json!({"description": "No uuid"})])).is_err());
+        assert!(from_taskwarrior(&exported(&[json!({"uuid": "not a uuid", "description": "Bad"})])).is_err());
+        assert!(from_taskwarrior(&exported(&[json!({"uuid": UUID})])).is_err());
+        let error = from_taskwarrior(&exported(&[
+            json!({"uuid": UUID, "description": "Fine"}),
+            json!({"uuid": UUID, "description": "Bad date", "entry": "yesterday"}),
+        ])).err().map(|error| error.to_string());
+        assert_eq!(error.as_deref(), Some("Task 2 of 2: bad entry date"));
+    }
+
+    #[test]
+    fn parses_recurrence_periods() {
+        for (recur, days) in [("daily", Some(1)), ("Weekly", Some(7)), ("fortnight", Some(14)), ("3d", Some(3)),
+                ("P2W", Some(14)), ("2 weeks", Some(14)), ("day", Some(1)), ("0d", None), ("monthly", None),
+                ("P1M", None), ("", None), ("3650d", Some(3650)), ("600w", None), ("99999999d", None)] {
+            assert_eq!(parse_recur(recur), days, "{recur}");
+        }
+    }
+
+    #[test]
+    fn writes_what_it_reads() {
+        let text = exported(&[
+            json!({"uuid": UUID, "description": "Pending", "entry": "20240115T120000Z", "status": "pending",
+                "wait": "20300101T050000Z"}),
+            json!({"uuid": Uuid::new_v4(), "description": "Done", "entry": "20240115T120000Z",
+                "status": "completed", "end": "20240116T120000Z"}),
+            json!({"uuid": Uuid::new_v4(), "description": "Every 3 days", "entry": "20240115T120000Z",
+                "status": "pending", "recur": "3d", "due": "20240117T050000Z", "wait": "20240117T050000Z"}),
+        ]);
+        let (tasks, _) = from_taskwarrior(&text).expect("valid tasks");
+        let (written, report) = to_taskwarrior(&tasks);
+        assert!(report.is_empty());
+        assert_eq!(serde_json::from_str::<Value>(&written).ok(), serde_json::from_str::<Value>(&text).ok());
+    }
+
+    #[test]
+    fn reports_dots_as_lost() {
+        let mut task = Task::new("Dotted");
+        task.toggle_dot();
+        let (_, report) = to_taskwarrior(&[task.clone(), task]);
+        assert_eq!(report, ["dot: Taskwarrior has no such field, lost from 2 tasks"]);
+    }
+}
@@ -0,0 +1,208 @@
+use chrono::{Local, NaiveDate, NaiveDateTime, NaiveTime};
+use uuid::Uuid;
+
+use crate::{Task, MAX_RECUR_DAYS};
+
+const DATE_FORMAT : &str = "%Y-%m-%d";
+
+// Snoozes end, and recurring tasks come back, at this hour
+const WAKE_HOUR : u32 = 5;
+
+/// Writes tasks as todo.txt lines, in order.
+///
+/// Completed tasks start with `x` and their completion date, and every task
+/// has its creation date.  Dotted tasks are given priority `(A)`.  Recurrence
+/// is written as `rec:<days>d`, and the date a snoozed or recurring task
+/// next appears as the threshold `t:<date>`, which todo.txt apps use to hide
+/// tasks until then.
+#[must_use]
+pub fn to_todo_txt(tasks: &[Task]) -> String {
+    let mut output = String::new();
+    for task in tasks {
+        let mut fields = vec![];
+        if let Some(completed) = task.completed() {
+            fields.push("x".to_string());
+            fields.push(completed.format(DATE_FORMAT).to_string());
+        } else if task.dot() {
+            fields.push("(A)".to_string());
+        }
+        fields.push(task.created().format(DATE_FORMAT).to_string());
+        // todo.txt has a task per line
+        fields.push(task.description().split_whitespace().collect::<Vec<_>>().join(" "));
+        let (recur_interval_days, recur_next) = task.recurrence();
+        if let Some(days) = recur_interval_days {
+            fields.push(format!("rec:{days}d"));
+        }
+        if let Some(threshold) = recur_next.or(task.snooze_until()) {
+            fields.push(format!("t:{}", threshold.format(DATE_FORMAT)));
+        }
+        output.push_str(&fields.join(" "));
+        output.push('\n');
+    }
+    output
+}
+
+/// Reads todo.txt lines as new tasks, in order, skipping blank lines.
+///
+/// This reads what ``to_todo_txt`` writes, and the same from other todo.txt
+/// apps: any priority dots the task, `rec:` with a number of days or weeks
+/// (optionally with a `+`, and up to ``MAX_RECUR_DAYS``) makes it recur, and a threshold date `t:` snoozes
+/// it until then, or for a recurring task, is when it next occurs.  Tasks
+/// without a creation date are created now.  Anything else, including other
+/// `key:value` extensions, stays in the description.
+#[must_use]
+pub fn from_todo_txt(text: &str) -> Vec<Task> {
+    text.lines().filter(|line| !line.trim().is_empty()).map(parse_line).collect()
+}
+
+fn parse_line(line: &str) -> Task {
+    let mut words = line.split_whitespace().peekable();
+    let mut done = false;
+    let mut completed = None;
+    let mut dot = false;
+    if words.peek() == Some(&"x") {
+        words.next();
+        done = true;
+        completed = words.next_if(|word| parse_date(word).is_some()).and_then(parse_date);
+    } else if words.next_if(|word| is_priority(word)).is_some() {
+        dot = true;
+    }
+    let now = Local::now().naive_local();
+    let created = words.next_if(|word| parse_date(word).is_some()).and_then(parse_date).unwrap_or(now);
+    // The completion date is optional, but completed tasks need one
+    let completed = completed.or(done.then_some(created));
+    let mut recur_interval_days = None;
+    let mut threshold = None;
+    let mut description = vec![];
+    for word in words {
+        match word.split_once(':').map(|(key, value)| (key, parse_interval(value), parse_date(value))) {
+            Some(("rec", Some(days), _)) => recur_interval_days = Some(days),
+            Some(("t", _, Some(date))) => threshold = Some(date.date().and_time(wake_time())),
+            _ => description.push(word),
+        }
+    }
+    // Recurring tasks can't be snoozed, but wait until they next occur
+    let (recur_next, snooze_until) = if recur_interval_days.is_some() { (threshold, None) } else { (None, threshold) };
+    Task::from_fields(Uuid::new_v4(), created, description.join(" "), dot, completed,
+        (recur_interval_days, recur_next), snooze_until)
+}
+
+fn wake_time() -> NaiveTime {
+    NaiveTime::from_hms_opt(WAKE_HOUR, 0, 0).unwrap_or(NaiveTime::MIN)
+}
+
+fn is_priority(word: &str) -> bool {
+    let bytes = word.as_bytes();
+    bytes.len() == 3 && bytes[0] == b'(' && bytes[1].is_ascii_uppercase() && bytes[2] == b')'
+}
+
+fn parse_date(word: &str) -> Option<NaiveDateTime> {
+    NaiveDate::parse_from_str(word, DATE_FORMAT).ok().map(|date| date.and_time(NaiveTime::MIN))
+}
+
+/// Parses a recurrence interval such as `1d`, `+3d` or `2w` as a number of days
+fn parse_interval(interval: &str) -> Option<u64> {
+    let interval = interval.strip_prefix('+').unwrap_or(interval);
+    let (count, days) = if let Some(count) = interval.strip_suffix('d') {
+        (count, 1)
+    } else if let Some(count) = interval.strip_suffix('w') {
+        (count, 7)
+    } else {
+        return None;
+    };
+    count.parse::<u64>().ok().filter(|&count| count > 0).and_then(|count| count.checked_mul(days))
+        .filter(|&days| days <= MAX_RECUR_DAYS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(text: &str) -> NaiveDateTime {
+        parse_date(text).expect("a valid date")
+    }
+
+    #[test]
+    fn reads_completion_priority_and_dates() {
+        let tasks = from_todo_txt("x 2024-03-02 2024-03-01 Done\n\n(B) 2024-01-05 Dotted +project @home\nPlain\n");
+        assert_eq!(tasks.len(), 3);
+        assert_eq!(tasks[0].description(), "Done");
+        assert_eq!(tasks[0].completed(), Some(date("2024-03-02")));
+        assert_eq!(tasks[0].created(), date("2024-03-01"));
+        assert!(!tasks[0].dot());
+        assert_eq!(tasks[1].description(), "Dotted +project @home");
+        assert!(tasks[1].dot());
+        assert_eq!(tasks[1].created(), date("2024-01-05"));
+        assert_eq!(tasks[2].description(), "Plain");
+        assert!(!tasks[2].is_complete());
+    }
+
+    #[test]
+    fn completed_tasks_have_a_completion_date() {
+        let tasks = from_todo_txt("x 2024-03-01 Done\nx Undated\n");
+        // The first date of a completed task is when it was completed
+        assert_eq!(tasks[0].completed(), Some(date("2024-03-01")));
+        assert!(tasks[0].created() > date("2024-03-01"));
+        assert_eq!(tasks[1].description(), "Undated");
+        assert_eq!(tasks[1].completed(), Some(tasks[1].created()));
+    }
+
+    #[test]
+    fn reads_recurrence_and_thresholds() {
+        let tasks = from_todo_txt("Weekly rec:+1w t:2030-01-01\nSnoozed t:2030-01-01\nEvery other day rec:2d\n");
+        let wake = date("2030-01-01").date().and_time(wake_time());
+        assert_eq!(tasks[0].description(), "Weekly");
+        assert_eq!(tasks[0].recurrence(), (Some(7), Some(wake)));
+        assert_eq!(tasks[0].snooze_until(), None);
+        assert_eq!(tasks[1].recurrence(), (None, None));
+        assert_eq!(tasks[1].snooze_until(), Some(wake));
+        assert_eq!(tasks[2].recurrence(), (Some(2), None));
+    }
+
+    #[test]
+    fn keeps_what_it_cant_read_in_the_description() {
+        let tasks = from_todo_txt("(a) x rec:1m t:soon due:2024-01-01 http://example.com\n");
+        assert_eq!(tasks[0].description(), "(a) x rec:1m t:soon due:2024-01-01 http://example.com");
+        assert!(!tasks[0].dot());
+        assert_eq!(tasks[0].recurrence(), (None, None));
+        assert_eq!(tasks[0].snooze_until(), None);
+    }
+
+    #[test]
+    fn keeps_intervals_too_long_to_recur_in_the_description() {
+        let tasks = from_todo_txt("Decade rec:3650d
+Forever rec:100000000d
+Weeks rec:600w
+");
+        assert_eq!(tasks[0].recur_interval_days(), Some(3650));
+        assert_eq!(tasks[1].description(), "Forever rec:100000000d");
+        assert_eq!(tasks[1].recurrence(), (None, None));
+        assert_eq!(tasks[2].description(), "Weeks rec:600w");
+        assert_eq!(tasks[2].recurrence(), (None, None));
+    }
+
+    #[test]
+    fn parses_intervals() {
+        assert_eq!(parse_interval("1d"), Some(1));
+        assert_eq!(parse_interval("+3d"), Some(3));
+        assert_eq!(parse_interval("2w"), Some(14));
+        assert_eq!(parse_interval("0d"), None);
+        assert_eq!(parse_interval("1m"), None);
+        assert_eq!(parse_interval("d"), None);
+        assert_eq!(parse_interval(&format!("{}w", u64::MAX)), None);
+    }
+
+    #[test]
+    fn writes_what_it_reads() {
+        let text = "x 2024-03-02 2024-03-01 Done\n(A) 2024-01-05 Dotted\n2024-01-06 Weekly rec:7d t:2030-01-01\n\
+            2024-01-07 Snoozed t:2030-01-01\n";
+        assert_eq!(to_todo_txt(&from_todo_txt(text)), text);
+    }
+
+    #[test]
+    fn writes_each_task_on_one_line() {
+        let text = to_todo_txt(&[Task::new("two\nlines")]);
+        assert_eq!(text.lines().count(), 1);
+        assert!(text.trim_end().ends_with("two lines"));
+    }
+}
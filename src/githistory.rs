@@ -0,0 +1,175 @@
+use std::{collections::HashMap, fs::{create_dir_all, write}, path::{Path, PathBuf}, process::{Command, Stdio}, time::{Duration, Instant}};
+
+use crate::{GitSettings, Task};
+
+const GITIGNORE_PATH : &str = ".gitignore";
+// Lock and temporary files come and go, and snapshots and the journal are
+// histories of their own
+const IGNORED : &str = "task.lock\ntask.sock\n*.tmp\n*.db-journal\nsnapshots/\ntasks.journal\ntasks.journal.1\n";
+const IDENTITY : [&str; 4] = ["-c", "user.name=task", "-c", "user.email=task@localhost"];
+
+/// Keeps the data directory as a local git repository, committing the task
+/// file of the current list whenever the list has changed and then been left
+/// alone for a while, so that a burst of changes becomes a single commit.
+///
+/// Commit messages describe what changed in the list, e.g. "complete: Buy
+/// milk", found by comparing it with the list as last committed.  Only the
+/// list's task file, and `.gitignore`, are committed: nothing else in the
+/// data directory (other lists, the archive, settings, copies kept by the
+/// store) is.
+///
+/// Encrypted lists aren't committed, as their history from before they were
+/// encrypted would give away what the encryption hides.
+///
+/// This runs the `git` command; nothing is ever pushed anywhere.
+pub struct GitHistory {
+    dir: PathBuf,
+    debounce: Duration,
+    committed: Option<Vec<Task>>,
+    changed_since: Option<Instant>,
+    // Whether to supply a committer identity, as git has none configured
+    needs_identity: bool,
+}
+
+impl GitHistory {
+    /// Prepares to keep the history of `data_dir`, initialising the
+    /// repository if necessary.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `git` can't be run, or fails.
+    pub fn open(data_dir: &Path, settings: &GitSettings) -> std::io::Result<Self> {
+        create_dir_all(data_dir)?;
+        let mut history = Self {
+            dir: data_dir.to_path_buf(),
+            debounce: Duration::from_secs(settings.debounce_secs),
+            committed: None,
+            changed_since: None,
+            needs_identity: false,
+        };
+        if !data_dir.join(".git").exists() {
+            history.git(&["init", "--quiet"])?;
+        }
+        if !data_dir.join(GITIGNORE_PATH).exists() {
+            write(data_dir.join(GITIGNORE_PATH), IGNORED)?;
+        }
+        history.needs_identity = history.git(&["config", "user.email"]).is_err();
+        Ok(history)
+    }
+
+    /// Runs git in the data directory, returning what it printed
+    fn git(&self, args: &[&str]) -> std::io::Result<String> {
+        let mut command = Command::new("git");
+        command.arg("-C").arg(&self.dir).args(["-c", "commit.gpgsign=false"]);
+        if self.needs_identity {
+            command.args(IDENTITY);
+        }
+        let output = command.args(args).stdin(Stdio::null()).output()?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        } else {
+            Err(std::io::Error::other(format!("git {} failed: {}",
+                args.first().unwrap_or(&""), String::from_utf8_lossy(&output.stderr).trim())))
+        }
+    }
+
+    /// Commits `files`, the list's task files, and `.gitignore`, if any of
+    /// them have changed, leaving anything else alone, even if staged.
+    /// The first line of `message` is the subject.
+    fn commit(&self, message: &str, files: &[PathBuf]) -> std::io::Result<()> {
+        let mut paths = vec![self.dir.join(GITIGNORE_PATH)];
+        paths.extend(files.iter().filter(|file| file.exists()).cloned());
+        let paths = paths.iter().map(|path| path.to_string_lossy()).collect::<Vec<_>>();
+        let paths = paths.iter().map(AsRef::as_ref).collect::<Vec<&str>>();
+        self.git(&[&["add", "--"], paths.as_slice()].concat())?;
+        if self.git(&[&["status", "--porcelain", "--"], paths.as_slice()].concat())?.trim().is_empty() {
+            return Ok(());
+        }
+        self.git(&[&["commit", "--quiet", "--message", message, "--"], paths.as_slice()].concat())?;
+        Ok(())
+    }
+
+    /// Commits the list, stored in `files`, if it has changed since it was
+    /// last committed, and hasn't changed again for the debounce interval.
+    /// The first call just commits any changes made outside the task list.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `git` fails.
+    pub fn commit_if_due(&mut self, tasks: &[Task], files: &[PathBuf]) -> std::io::Result<()> {
+        let Some(committed) = &self.committed else {
+            self.commit("sync: changes made outside the task list", files)?;
+            self.committed = Some(tasks.to_vec());
+            return Ok(());
+        };
+        if committed == tasks {
+            self.changed_since = None;
+            return Ok(());
+        }
+        let changed_since = *self.changed_since.get_or_insert_with(Instant::now);
+        if changed_since.elapsed() >= self.debounce {
+            self.commit_now(tasks, files)?;
+        }
+        Ok(())
+    }
+
+    /// Commits the list, stored in `files`, straight away if it has changed,
+    /// e.g. before quitting or switching to another list.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `git` fails.
+    pub fn commit_now(&mut self, tasks: &[Task], files: &[PathBuf]) -> std::io::Result<()> {
+        let changes = match &self.committed {
+            Some(committed) => describe_changes(committed, tasks),
+            None => vec![],
+        };
+        let message = match changes.as_slice() {
+            [] => "update".to_string(),
+            [change] => change.clone(),
+            [first, ..] => format!("{first} (and {} more)\n\n{}", changes.len() - 1, changes.join("\n")),
+        };
+        self.commit(&message, files)?;
+        self.committed = Some(tasks.to_vec());
+        self.changed_since = None;
+        Ok(())
+    }
+
+    /// Forgets the list as last committed, when switching to another list
+    pub fn forget(&mut self) {
+        self.committed = None;
+        self.changed_since = None;
+    }
+}
+
+/// Describes how the list changed from `before` to `after`, one line per
+/// task, e.g. "complete: Buy milk".
+#[must_use]
+pub fn describe_changes(before: &[Task], after: &[Task]) -> Vec<String> {
+    let before_by_uuid: HashMap<_, _> = before.iter().map(|task| (task.uuid(), task)).collect();
+    let after_by_uuid: HashMap<_, _> = after.iter().map(|task| (task.uuid(), task)).collect();
+    let mut changes = Vec::new();
+    for task in after {
+        let operation = match before_by_uuid.get(&task.uuid()) {
+            None => "add",
+            Some(old) if *old == task => continue,
+            Some(old) if old.is_complete() != task.is_complete() =>
+                if task.is_complete() { "complete" } else { "uncomplete" },
+            Some(old) if old.description() != task.description() => "edit",
+            Some(old) if old.dot() != task.dot() => if task.dot() { "dot" } else { "undot" },
+            Some(old) if old.snooze_until() != task.snooze_until() =>
+                if task.snooze_until().is_some() { "snooze" } else { "unsnooze" },
+            Some(old) if old.recur_interval_days() != task.recur_interval_days() =>
+                if task.is_recurring() { "recur" } else { "stop recurring" },
+            Some(_) => "update",
+        };
+        changes.push(format!("{operation}: {}", task.description()));
+    }
+    for task in before.iter().filter(|task| !after_by_uuid.contains_key(&task.uuid())) {
+        changes.push(format!("delete: {}", task.description()));
+    }
+    if changes.is_empty() && before != after {
+        changes.push("reorder".to_string());
+    }
+    changes
+}
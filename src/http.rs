@@ -0,0 +1,91 @@
+use std::{io::{BufRead, BufReader, Read, Write}, net::TcpStream, time::Duration};
+
+// Requests and responses larger than this are refused
+const MAX_BODY : usize = 16 << 20;
+const TIMEOUT : Duration = Duration::from_secs(10);
+
+/// The version of the list a response reflects, incremented whenever it
+/// changes, or a request is based on
+pub(crate) const VERSION_HEADER : &str = "X-Task-Version";
+
+/// An HTTP request or response: just as much as the server and its clients
+/// need, which is the first line, the headers, and a body of known length
+pub(crate) struct Message {
+    pub start_line: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Message {
+    /// Returns the value of the header `name`, ignoring case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(header, _)| header.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    /// Reads a message from `stream`, using its Content-Length to find the
+    /// end of the body
+    pub fn read(stream: &TcpStream) -> std::io::Result<Self> {
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        let mut reader = BufReader::new(stream);
+        let mut start_line = String::new();
+        reader.read_line(&mut start_line)?;
+        let mut headers = vec![];
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 || line.trim_end().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.trim().to_string(), value.trim().to_string()));
+            }
+        }
+        let mut message = Self { start_line: start_line.trim_end().to_string(), headers, body: vec![] };
+        let length = message.header("Content-Length").map_or(Ok(0), str::parse::<usize>)
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Bad Content-Length: {err}")))?;
+        if length > MAX_BODY {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Message too large"));
+        }
+        message.body = vec![0; length];
+        reader.read_exact(&mut message.body)?;
+        Ok(message)
+    }
+
+    /// Writes the message to `stream`, adding Content-Length, and asking for
+    /// the connection to be closed afterwards
+    pub fn write(&self, mut stream: &TcpStream) -> std::io::Result<()> {
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut head = Vec::new();
+        write!(head, "{}\r\n", self.start_line)?;
+        for (name, value) in &self.headers {
+            write!(head, "{name}: {value}\r\n")?;
+        }
+        write!(head, "Content-Length: {}\r\nConnection: close\r\n\r\n", self.body.len())?;
+        stream.write_all(&head)?;
+        stream.write_all(&self.body)?;
+        stream.flush()
+    }
+}
+
+/// Sends a request carrying the server's `token` to the server at `addr`,
+/// and returns the status code and the response.  `version` is the version
+/// of the list the request is based on, if it matters.
+pub(crate) fn request(addr: &str, token: &str, method: &str, path: &str, version: Option<u64>, body: Option<&[u8]>)
+        -> std::io::Result<(u16, Message)> {
+    let stream = TcpStream::connect(addr)?;
+    let mut headers = vec![
+        ("Host".to_string(), addr.to_string()),
+        ("Authorization".to_string(), format!("Bearer {token}")),
+    ];
+    if method != "GET" {
+        headers.push(("Content-Type".to_string(), "application/json".to_string()));
+    }
+    if let Some(version) = version {
+        headers.push((VERSION_HEADER.to_string(), version.to_string()));
+    }
+    Message { start_line: format!("{method} {path} HTTP/1.1"), headers, body: body.unwrap_or_default().to_vec() }
+        .write(&stream)?;
+    let response = Message::read(&stream)?;
+    let status = response.start_line.split_whitespace().nth(1).and_then(|status| status.parse().ok())
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Bad response from {addr}")))?;
+    Ok((status, response))
+}
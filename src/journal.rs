@@ -0,0 +1,261 @@
+use std::{fs::{create_dir_all, read_to_string, rename, OpenOptions}, io::Write, path::{Path, PathBuf}};
+
+use chrono::{Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{Snapshot, Task};
+
+const JOURNAL_PATH : &str = "tasks.journal";
+// The journal is moved here once it reaches MAX_JOURNAL_BYTES, replacing
+// what was here before
+const ROTATED_PATH : &str = "tasks.journal.1";
+const MAX_JOURNAL_BYTES : u64 = 8 << 20;
+
+/// A single change to a ``TaskList``, as recorded in the journal.
+/// Operations record their outcome rather than their intent (e.g. a recurring
+/// task which "removes" itself is recorded as a `Replace`), so that replaying
+/// them is deterministic.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    /// The whole list, recorded when journalling starts, when the list is
+    /// replaced wholesale, and at the start of each rotated journal file
+    Checkpoint { tasks: Vec<Task> },
+    /// A task added at the bottom of the list
+    Add { task: Task },
+    /// A task replaced in place
+    Replace { uuid: Uuid, task: Task },
+    /// A task removed from the list
+    Remove { uuid: Uuid },
+    /// A task inserted at a position in the list, e.g. by undo
+    Insert { index: usize, task: Task },
+    /// A task replaced, and moved to the bottom of the list
+    ReplaceAtBottom { uuid: Uuid, task: Task },
+    /// Recurring and snoozed tasks reset, and moved to the bottom of the list in order
+    Reset { tasks: Vec<Task> },
+}
+
+impl Operation {
+    /// Applies the operation to `tasks`
+    pub fn apply(&self, tasks: &mut Vec<Task>) {
+        let position = |tasks: &[Task], uuid: Uuid| tasks.iter().position(|t| t.uuid() == uuid);
+        match self {
+            Operation::Checkpoint { tasks: checkpoint } => tasks.clone_from(checkpoint),
+            Operation::Add { task } => tasks.push(task.clone()),
+            Operation::Replace { uuid, task } => {
+                if let Some(index) = position(tasks, *uuid) {
+                    tasks[index] = task.clone();
+                }
+            },
+            Operation::Remove { uuid } => {
+                if let Some(index) = position(tasks, *uuid) {
+                    tasks.remove(index);
+                }
+            },
+            Operation::Insert { index, task } => tasks.insert(usize::min(*index, tasks.len()), task.clone()),
+            Operation::ReplaceAtBottom { uuid, task } => {
+                if let Some(index) = position(tasks, *uuid) {
+                    tasks.remove(index);
+                    tasks.push(task.clone());
+                }
+            },
+            Operation::Reset { tasks: reset } => {
+                for task in reset {
+                    if let Some(index) = position(tasks, task.uuid()) {
+                        tasks.remove(index);
+                        tasks.push(task.clone());
+                    }
+                }
+            },
+        }
+    }
+}
+
+/// An operation, and when it happened
+#[derive(Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub time: NaiveDateTime,
+    #[serde(flatten)]
+    pub operation: Operation,
+}
+
+/// An append-only log of every change made to a ``TaskList``, kept as JSON
+/// lines in `tasks.journal` alongside `tasks.json`.
+///
+/// The list can be rebuilt as it was at any point in time by replaying the
+/// journal from a checkpoint, or onto a snapshot.  Once the journal reaches
+/// 8MB it is rotated to `tasks.journal.1`, replacing the one rotated before,
+/// and a new one is started with a checkpoint, so that the journal is bounded
+/// but always covers at least the latest 8MB of changes.
+pub struct Journal {
+    path: PathBuf,
+    rotated_path: PathBuf,
+    max_bytes: u64,
+}
+
+impl Journal {
+    /// Returns the journal in the data directory `dir`
+    #[must_use]
+    pub fn new(dir: &Path) -> Self {
+        Self { path: dir.join(JOURNAL_PATH), rotated_path: dir.join(ROTATED_PATH), max_bytes: MAX_JOURNAL_BYTES }
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the journal's files which exist, oldest first
+    #[must_use]
+    pub fn files(&self) -> Vec<PathBuf> {
+        [&self.rotated_path, &self.path].into_iter().filter(|path| path.exists()).cloned().collect()
+    }
+
+    /// Appends an operation to the journal, and syncs it to disk.  If the
+    /// journal is full, it is rotated first, and the new one started with a
+    /// checkpoint of the list as journalled so far.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the journal can't be read, rotated or written
+    pub fn append(&mut self, operation: Operation) -> std::io::Result<()> {
+        let time = Local::now().naive_local();
+        let mut entries = vec![];
+        if std::fs::metadata(&self.path).is_ok_and(|metadata| metadata.len() >= self.max_bytes) {
+            if !matches!(operation, Operation::Checkpoint { .. }) {
+                entries.push(JournalEntry { time, operation: Operation::Checkpoint { tasks: self.reconstruct(None)? } });
+            }
+            rename(&self.path, &self.rotated_path)?;
+        }
+        entries.push(JournalEntry { time, operation });
+        let mut lines = String::new();
+        for entry in &entries {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+        if let Some(dir) = self.path.parent() {
+            create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all(lines.as_bytes())?;
+        file.sync_data()
+    }
+
+    /// Reads every entry in the journal, including the one rotated before,
+    /// oldest first.  A partially written last line, as left by a crash, is
+    /// ignored.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the journal can't be read, or any other line is invalid
+    pub fn entries(&self) -> std::io::Result<Vec<JournalEntry>> {
+        let mut entries = Self::read_entries(&self.rotated_path)?;
+        entries.extend(Self::read_entries(&self.path)?);
+        Ok(entries)
+    }
+
+    fn read_entries(path: &Path) -> std::io::Result<Vec<JournalEntry>> {
+        let serialized = match read_to_string(path) {
+            Ok(serialized) => serialized,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err),
+        };
+        let lines = serialized.lines().collect::<Vec<_>>();
+        let mut entries = Vec::with_capacity(lines.len());
+        for (index, line) in lines.iter().enumerate() {
+            match serde_json::from_str(line) {
+                Ok(entry) => entries.push(entry),
+                Err(_) if index == lines.len() - 1 => (),
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Applies `entries` in order to `tasks`, and returns the result
+    #[must_use]
+    pub fn replay<'a>(mut tasks: Vec<Task>, entries: impl IntoIterator<Item = &'a JournalEntry>) -> Vec<Task> {
+        for entry in entries {
+            entry.operation.apply(&mut tasks);
+        }
+        tasks
+    }
+
+    /// Rebuilds the list as it was at `at`, or as it is now if `at` is `None`,
+    /// by replaying from the last checkpoint before then.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the journal can't be read
+    pub fn reconstruct(&self, at: Option<NaiveDateTime>) -> std::io::Result<Vec<Task>> {
+        let entries = self.entries()?;
+        let entries = entries.iter()
+            .take_while(|e| at.is_none_or(|at| e.time <= at))
+            .collect::<Vec<_>>();
+        let start = entries.iter()
+            .rposition(|e| matches!(e.operation, Operation::Checkpoint { .. }))
+            .unwrap_or(0);
+        Ok(Self::replay(vec![], entries[start..].iter().copied()))
+    }
+
+    /// Rebuilds the list as it was at `at`, or as it is now if `at` is `None`,
+    /// by replaying the journal onto `snapshot`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the snapshot or journal can't be read
+    pub fn replay_onto(&self, snapshot: &Snapshot, at: Option<NaiveDateTime>) -> std::io::Result<Vec<Task>> {
+        let entries = self.entries()?;
+        Ok(Self::replay(snapshot.load()?, entries.iter()
+            .skip_while(|e| e.time <= snapshot.taken())
+            .take_while(|e| at.is_none_or(|at| e.time <= at))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("task-journal-{}", Uuid::new_v4()));
+        create_dir_all(&dir).expect("a temporary directory");
+        dir
+    }
+
+    #[test]
+    fn checkpoints_are_appended() {
+        let dir = temp_dir();
+        let mut journal = Journal::new(&dir);
+        let first = Task::new("First");
+        let second = Task::new("Second");
+        journal.append(Operation::Checkpoint { tasks: vec![first.clone()] }).expect("a checkpoint");
+        journal.append(Operation::Add { task: second.clone() }).expect("an add");
+        journal.append(Operation::Checkpoint { tasks: vec![second.clone()] }).expect("another checkpoint");
+        assert_eq!(journal.entries().expect("the entries").len(), 3);
+        assert!(journal.reconstruct(None).expect("the list") == vec![second]);
+        std::fs::remove_dir_all(&dir).expect("the directory removed");
+    }
+
+    #[test]
+    fn full_journals_are_rotated_with_a_checkpoint() {
+        let dir = temp_dir();
+        let mut journal = Journal::new(&dir);
+        journal.max_bytes = 1;
+        let tasks = ["First", "Second", "Third"].map(Task::new);
+        journal.append(Operation::Checkpoint { tasks: vec![] }).expect("a checkpoint");
+        for task in &tasks {
+            journal.append(Operation::Add { task: task.clone() }).expect("an add");
+        }
+        // Only the latest two files are kept, each starting with a checkpoint
+        assert_eq!(journal.files(), [dir.join(ROTATED_PATH), dir.join(JOURNAL_PATH)]);
+        let entries = journal.entries().expect("the entries");
+        assert_eq!(entries.len(), 4);
+        assert!(matches!(&entries[0].operation, Operation::Checkpoint { tasks: checkpoint } if checkpoint.len() == 1));
+        assert!(matches!(&entries[2].operation, Operation::Checkpoint { tasks: checkpoint } if checkpoint.len() == 2));
+        assert!(journal.reconstruct(None).expect("the list") == tasks);
+        std::fs::remove_dir_all(&dir).expect("the directory removed");
+    }
+}
@@ -6,5 +6,54 @@ pub use task::*;
 mod tasklist;
 pub use tasklist::*;
 
+mod config;
+pub use config::*;
+
+mod snapshots;
+pub use snapshots::*;
+
+mod journal;
+pub use journal::*;
+
+mod lock;
+pub use lock::*;
+
+mod merge;
+pub use merge::*;
+
+mod sync;
+pub use sync::*;
+
+mod crdt;
+pub use crdt::*;
+
+mod lists;
+pub use lists::*;
+
+mod datadir;
+pub use datadir::*;
+
+mod archive;
+pub use archive::*;
+
+mod githistory;
+pub use githistory::*;
+
+mod http;
+
+mod server;
+pub use server::*;
+
+#[cfg(unix)]
+mod control;
+#[cfg(unix)]
+pub use control::*;
+
+pub mod stores;
+pub use stores::*;
+
+pub mod formats;
+pub use formats::*;
+
 pub mod views;
 pub use views::*;
@@ -0,0 +1,105 @@
+use std::{fs::{create_dir_all, read_dir}, path::{Path, PathBuf}};
+
+const LISTS_DIR : &str = "lists";
+const MAX_NAME_LEN : usize = 64;
+
+/// The name of the list kept directly in the data directory
+pub const DEFAULT_LIST : &str = "default";
+
+/// The named task lists kept under a data directory.
+///
+/// The default list lives in the data directory itself, where the single list
+/// always has, and every other list in `lists/<name>`.  Each list's directory
+/// holds its own task file, backup, journal, snapshots and lock, so lists are
+/// completely independent of each other.  Settings in `config.json` in the
+/// data directory apply to every list.
+pub struct TaskLists {
+    root: PathBuf,
+}
+
+impl TaskLists {
+    #[must_use]
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Returns the data directory holding every list
+    #[must_use]
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Checks that `name` can be used as a list (or profile) name: letters,
+    /// digits, `-` and `_` only.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` with kind `InvalidInput` if it can't.
+    pub fn validate_name(name: &str) -> std::io::Result<()> {
+        if !name.is_empty()
+                && name.len() <= MAX_NAME_LEN
+                && name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+            Ok(())
+        } else {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid name '{name}': use up to {MAX_NAME_LEN} letters, digits, - and _"),
+            ))
+        }
+    }
+
+    /// Returns the directory holding the list `name`, which may not exist yet
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `name` isn't a valid list name.
+    pub fn dir(&self, name: &str) -> std::io::Result<PathBuf> {
+        Self::validate_name(name)?;
+        if name == DEFAULT_LIST {
+            Ok(self.root.clone())
+        } else {
+            Ok(self.root.join(LISTS_DIR).join(name))
+        }
+    }
+
+    /// Creates the list `name`, if it doesn't already exist, returning its directory
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `name` isn't a valid list name, or the directory
+    /// can't be created.
+    pub fn create(&self, name: &str) -> std::io::Result<PathBuf> {
+        let dir = self.dir(name)?;
+        create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    /// Returns the names of every list, the default first, then the others
+    /// in alphabetical order
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the lists directory exists but can't be read.
+    pub fn names(&self) -> std::io::Result<Vec<String>> {
+        let mut names = vec![];
+        match read_dir(self.root.join(LISTS_DIR)) {
+            Ok(entries) => {
+                for entry in entries {
+                    let entry = entry?;
+                    if entry.file_type()?.is_dir() {
+                        if let Some(name) = entry.file_name().to_str() {
+                            if name != DEFAULT_LIST && Self::validate_name(name).is_ok() {
+                                names.push(name.to_string());
+                            }
+                        }
+                    }
+                }
+            },
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err),
+        }
+        names.sort();
+        names.insert(0, DEFAULT_LIST.to_string());
+        Ok(names)
+    }
+}
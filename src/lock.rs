@@ -0,0 +1,173 @@
+use std::{fs::{create_dir_all, File, OpenOptions}, io::{Read, Seek, Write}, path::Path};
+
+use chrono::{Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+const LOCK_PATH : &str = "task.lock";
+
+/// The instance holding the lock, as recorded in the lock file
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LockOwner {
+    pub pid: u32,
+    pub hostname: String,
+    pub started: NaiveDateTime,
+}
+
+impl LockOwner {
+    fn this_process() -> Self {
+        Self {
+            pid: std::process::id(),
+            hostname: hostname(),
+            started: Local::now().naive_local(),
+        }
+    }
+
+    fn is_this_host(&self) -> bool {
+        self.hostname == hostname()
+    }
+}
+
+impl std::fmt::Display for LockOwner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "process {} on {}, started {}", self.pid, self.hostname, self.started.format("%Y-%m-%d %H:%M:%S"))
+    }
+}
+
+/// The outcome of trying to take the lock
+pub enum LockAttempt {
+    /// We now hold the lock
+    Acquired(InstanceLock),
+    /// A running instance holds the lock.  The owner is `None` if it couldn't be read.
+    Held(Option<LockOwner>),
+    /// The lock was left by an instance which may or may not still be running,
+    /// e.g. on another host.  The user must decide whether to break it.
+    Ambiguous(LockOwner),
+}
+
+/// Ensures only one instance at a time writes to the task list.
+///
+/// The lock is `task.lock` in the data directory, which records the owner's
+/// PID, hostname and start time, and is held with an OS advisory lock.  The
+/// OS releases the advisory lock when the owner exits, however it exits, so a
+/// lock left behind by a crash is detected and taken over automatically.
+/// Owners on other hosts can't be checked, so those locks are reported as
+/// ambiguous.
+///
+/// The lock is released when this is dropped.
+pub struct InstanceLock {
+    file: File,
+}
+
+impl InstanceLock {
+    /// Attempts to take the lock in `dir`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the lock file can't be created, read or written.
+    pub fn acquire(dir: &Path) -> std::io::Result<LockAttempt> {
+        let mut file = Self::open(dir)?;
+        match file.try_lock() {
+            Ok(()) => (),
+            Err(std::fs::TryLockError::WouldBlock) => return Ok(LockAttempt::Held(Self::read_owner(&mut file))),
+            Err(std::fs::TryLockError::Error(err)) => return Err(err),
+        }
+        // Nobody holds the advisory lock, so any owner recorded in the file
+        // has exited, unless it's on another host, or its PID is still in use,
+        // in which case we can't be sure.
+        if let Some(owner) = Self::read_owner(&mut file) {
+            if !owner.is_this_host() || (owner.pid != std::process::id() && process_alive(owner.pid) != Some(false)) {
+                return Ok(LockAttempt::Ambiguous(owner));
+            }
+        }
+        Ok(LockAttempt::Acquired(Self::claim(file)?))
+    }
+
+    /// Takes the lock in `dir` regardless of any recorded owner, as long as
+    /// it isn't actually held by a running instance on this host.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` with kind `WouldBlock` if the lock is still held, or
+    /// any other error if the lock file can't be created or written.
+    pub fn break_lock(dir: &Path) -> std::io::Result<Self> {
+        let file = Self::open(dir)?;
+        match file.try_lock() {
+            Ok(()) => Self::claim(file),
+            Err(std::fs::TryLockError::WouldBlock) => Err(std::io::Error::new(
+                std::io::ErrorKind::WouldBlock,
+                "Task list is locked by a running instance",
+            )),
+            Err(std::fs::TryLockError::Error(err)) => Err(err),
+        }
+    }
+
+    fn open(dir: &Path) -> std::io::Result<File> {
+        create_dir_all(dir)?;
+        OpenOptions::new().read(true).write(true).create(true).truncate(false).open(dir.join(LOCK_PATH))
+    }
+
+    fn read_owner(file: &mut File) -> Option<LockOwner> {
+        let mut serialized = String::new();
+        file.rewind().ok()?;
+        file.read_to_string(&mut serialized).ok()?;
+        serde_json::from_str(&serialized).ok()
+    }
+
+    /// Records this process as the owner of a file we hold the advisory lock on
+    fn claim(mut file: File) -> std::io::Result<Self> {
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(serde_json::to_string(&LockOwner::this_process())?.as_bytes())?;
+        file.sync_all()?;
+        Ok(Self { file })
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // An empty lock file means nobody owns the lock.  Errors are ignored,
+        // as the advisory lock is released regardless, and a stale owner
+        // recorded in the file is detected by the next instance.
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(unix)]
+fn hostname() -> String {
+    let mut buf = [0_u8; 256];
+    // SAFETY: buf is valid for writes of its length
+    let result = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+    if result == 0 {
+        let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+        String::from_utf8_lossy(&buf[..len]).to_string()
+    } else {
+        String::new()
+    }
+}
+
+#[cfg(not(unix))]
+fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_default()
+}
+
+/// Returns whether a process with `pid` exists on this host, or `None` if
+/// that can't be determined.
+#[cfg(unix)]
+fn process_alive(pid: u32) -> Option<bool> {
+    let pid = libc::pid_t::try_from(pid).ok()?;
+    // SAFETY: signal 0 performs error checking only, and sends no signal
+    if unsafe { libc::kill(pid, 0) } == 0 {
+        return Some(true);
+    }
+    match std::io::Error::last_os_error().raw_os_error() {
+        Some(libc::ESRCH) => Some(false),
+        Some(libc::EPERM) => Some(true),
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+fn process_alive(_pid: u32) -> Option<bool> {
+    None
+}
@@ -1,11 +1,11 @@
 #![warn(clippy::pedantic, clippy::all, clippy::unwrap_used)]
-use std::io::{stdout, Result, Stdout};
+use std::{io::{stdout, Result, Stdout, Write}, path::{Path, PathBuf}};
 
 use crossterm::{
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen}, ExecutableCommand
 };
 use ratatui::{backend::CrosstermBackend, Terminal};
-use task::MainView;
+use task::{completions, create_server_token, open_store, passphrase_from_env, resolve_data_dir, sync_files, to_history, Archive, Config, DateRange, Format, HistoryFormat, InstanceLock, Journal, LockAttempt, MainView, Server, Snapshots, TaskList, TaskLists, TaskStore, DEFAULT_LIST, DEFAULT_SERVER_ADDR, SERVER_TOKEN_FILE};
 
 fn setup_ratatui() -> Result<Terminal<CrosstermBackend<Stdout>>> {
     stdout().execute(EnterAlternateScreen)?;
@@ -21,13 +21,361 @@ fn shutdown_ratatui() -> Result<()> {
     Ok(())
 }
 
+const USAGE : &str = "Usage: task [OPTIONS] [LIST]
+       task [OPTIONS] serve [LIST]
+       task [OPTIONS] connect
+       task [OPTIONS] export FORMAT [FILE]
+       task [OPTIONS] import FORMAT FILE
+       task [OPTIONS] history FORMAT [FILE]
+       task sync ANCESTOR OURS THEIRS [OUTPUT]
+
+Opens the named task list, or the default list if none is given.
+
+Commands:
+  serve                 Hold the list, serving it to scripts and other clients
+                        over a JSON HTTP API on localhost.  Clients must send
+                        the token the server writes to server-token in the
+                        list's directory.
+  connect               Open the list held by a server, as its client
+  export                Write the list to FILE (default: standard output) in
+                        FORMAT, which is one of: todotxt, taskwarrior, ical,
+                        markdown
+  import                Add the tasks in FILE, written in FORMAT, to the
+                        bottom of the list, skipping any already in it
+  history               Write every task completed, including those archived,
+                        to FILE (default: standard output) as a row each, in
+                        FORMAT, which is csv or jsonl
+  sync                  Merge two copies of a task file that have both changed
+                        since their common ANCESTOR, writing the result to
+                        OUTPUT (default: OURS) and ANCESTOR, ready for the
+                        next sync, and list any conflicts.  Exits
+                        with status 1 if there were conflicts, for which OURS
+                        was kept.  Encrypted files are read using
+                        $TASK_PASSPHRASE or $TASK_KEYFILE.
+
+Options:
+  -d, --data-dir DIR    Keep task data in DIR (default: $TASK_DATA_DIR, or the
+                        user's local config directory)
+  -p, --profile NAME    Use the profile NAME, with its own settings and lists,
+                        under the data directory (default: $TASK_PROFILE)
+  -l, --list LIST       The list to connect to, export, import, or write the
+                        history of (default: the default list)
+  -f, --from DATE       Only write history from DATE (YYYY-MM-DD) on
+  -t, --to DATE         Only write history up to and including DATE
+  -a, --addr ADDR       The address to serve on, or connect to
+                        (default: 127.0.0.1:7420)
+  -h, --help            Show this help";
+
+/// A command given instead of a list to open
+enum Command {
+    Serve,
+    Connect,
+    Export { format: Format, file: Option<PathBuf> },
+    Import { format: Format, file: PathBuf },
+    History { format: HistoryFormat, file: Option<PathBuf> },
+    Sync { ancestor: PathBuf, ours: PathBuf, theirs: PathBuf, output: PathBuf },
+}
+
+/// Options from the command line
+#[derive(Default)]
+struct Args {
+    help: bool,
+    data_dir: Option<PathBuf>,
+    profile: Option<String>,
+    addr: Option<String>,
+    list: Option<String>,
+    range: DateRange,
+    command: Option<Command>,
+}
+
+impl Args {
+    /// Parses the command line arguments, excluding the program name.
+    /// Returns `Err` with a message for the user if they aren't valid.
+    fn parse(args: impl IntoIterator<Item = String>) -> std::result::Result<Self, String> {
+        let mut parsed = Self::default();
+        let mut positional = vec![];
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag.to_string(), Some(value.to_string())),
+                _ => (arg.clone(), None),
+            };
+            let mut value = || inline_value.clone().or_else(|| args.next())
+                .ok_or_else(|| format!("{flag} needs a value"));
+            match flag.as_str() {
+                "-h" | "--help" => parsed.help = true,
+                "-d" | "--data-dir" => parsed.data_dir = Some(PathBuf::from(value()?)),
+                "-p" | "--profile" => parsed.profile = Some(value()?),
+                "-l" | "--list" => parsed.list = Some(value()?),
+                "-f" | "--from" => parsed.range.from = DateRange::parse_date(&value()?)?,
+                "-t" | "--to" => parsed.range.to = DateRange::parse_date(&value()?)?,
+                "-a" | "--addr" => parsed.addr = Some(value()?),
+                _ if arg.starts_with('-') => return Err(format!("Unknown option {arg}")),
+                _ => positional.push(arg),
+            }
+        }
+        match positional.as_slice() {
+            [command, list @ ..] if command == "serve" => {
+                parsed.command = Some(Command::Serve);
+                match list {
+                    [] => (),
+                    [list] => parsed.list = Some(list.clone()),
+                    [_, extra, ..] => return Err(format!("Unexpected argument {extra}")),
+                }
+            },
+            [command, extra @ ..] if command == "connect" => {
+                if let Some(extra) = extra.first() {
+                    return Err(format!("Unexpected argument {extra}"));
+                }
+                parsed.command = Some(Command::Connect);
+            },
+            [command, args @ ..] if command == "export" || command == "import" => {
+                let [format, file @ ..] = args else {
+                    return Err(format!("{command} needs a FORMAT: one of {}", Format::NAMES.join(", ")));
+                };
+                let format = Format::from_name(format)
+                    .ok_or_else(|| format!("Unknown format {format}: use one of {}", Format::NAMES.join(", ")))?;
+                let file = match file {
+                    [] => None,
+                    [file] => Some(PathBuf::from(file)),
+                    [_, extra, ..] => return Err(format!("Unexpected argument {extra}")),
+                };
+                parsed.command = Some(if command == "export" {
+                    Command::Export { format, file }
+                } else {
+                    Command::Import { format, file: file.ok_or_else(|| "import needs a FILE".to_string())? }
+                });
+            },
+            [command, args @ ..] if command == "history" => {
+                let [format, file @ ..] = args else {
+                    return Err(format!("history needs a FORMAT: one of {}", HistoryFormat::NAMES.join(", ")));
+                };
+                let format = HistoryFormat::from_name(format)
+                    .ok_or_else(|| format!("Unknown format {format}: use one of {}", HistoryFormat::NAMES.join(", ")))?;
+                let file = match file {
+                    [] => None,
+                    [file] => Some(PathBuf::from(file)),
+                    [_, extra, ..] => return Err(format!("Unexpected argument {extra}")),
+                };
+                parsed.command = Some(Command::History { format, file });
+            },
+            [command, files @ ..] if command == "sync" => {
+                let [ancestor, ours, theirs, output @ ..] = files else {
+                    return Err("sync needs the ANCESTOR, OURS and THEIRS files".to_string());
+                };
+                let output = match output {
+                    [] => ours,
+                    [output] => output,
+                    [_, extra, ..] => return Err(format!("Unexpected argument {extra}")),
+                };
+                parsed.command = Some(Command::Sync {
+                    ancestor: PathBuf::from(ancestor),
+                    ours: PathBuf::from(ours),
+                    theirs: PathBuf::from(theirs),
+                    output: PathBuf::from(output),
+                });
+            },
+            [] => (),
+            [list] => parsed.list = Some(list.clone()),
+            [_, extra, ..] => return Err(format!("Unexpected argument {extra}")),
+        }
+        Ok(parsed)
+    }
+}
+
+/// Merges two copies of a task file, reporting what happened.
+/// Returns the exit status.
+fn sync(ancestor: &Path, ours: &Path, theirs: &Path, output: &Path) -> i32 {
+    let merged = passphrase_from_env()
+        .and_then(|passphrase| sync_files(ancestor, ours, theirs, output, passphrase.as_ref()));
+    let merged = match merged {
+        Ok(merged) => merged,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            return 2;
+        },
+    };
+    println!("Merged {} tasks into {}", merged.tasks.len(), output.display());
+    for conflict in &merged.conflicts {
+        let description = conflict.kept().map_or("", |task| task.description());
+        let problem = match (&conflict.ours, &conflict.theirs) {
+            (None, _) => "deleted in ours, but changed in theirs, so kept".to_string(),
+            (_, None) => "deleted in theirs, but changed in ours, so kept".to_string(),
+            _ if conflict.fields.is_empty() => "added differently in both, kept ours".to_string(),
+            _ => format!("both changed {}, kept ours", conflict.fields.join(", ")),
+        };
+        println!("Conflict: {description} ({}): {problem}", conflict.uuid);
+    }
+    if merged.order_conflict {
+        println!("Conflict: both reordered the list, kept the order of ours");
+    }
+    i32::from(!merged.conflicts.is_empty() || merged.order_conflict)
+}
+
+/// Takes the lock on the list in `dir`, and loads it, unlocking it with the
+/// passphrase from the environment if it's encrypted.  Unencrypted lists are
+/// journalled.
+fn hold_list(dir: &Path, list_name: &str, config: &Config) -> Result<(InstanceLock, TaskList<Box<dyn TaskStore>>)> {
+    let LockAttempt::Acquired(lock) = InstanceLock::acquire(dir)? else {
+        return Err(std::io::Error::new(std::io::ErrorKind::WouldBlock,
+            format!("List {list_name} is in use by another instance")));
+    };
+    let mut tasks = TaskList::load(unlocked_store(dir, false, config)?)?;
+    // The journal isn't encrypted, so isn't kept for encrypted lists
+    if !tasks.store().is_encrypted() {
+        tasks.set_journal(Journal::new(dir))?;
+    }
+    Ok((lock, tasks))
+}
+
+/// Opens the store for the list in `dir`, unlocking it with the passphrase
+/// from the environment, if there is one
+fn unlocked_store(dir: &Path, read_only: bool, config: &Config) -> Result<Box<dyn TaskStore>> {
+    let mut store = open_store(dir, read_only, config.replicated)?;
+    if let Some(passphrase) = passphrase_from_env()? {
+        // Stores that don't support encryption have nothing to unlock
+        let _ = store.unlock(passphrase);
+    }
+    Ok(store)
+}
+
+/// Runs a command on the named list without the TUI, reporting any error.
+/// Returns the exit status.
+fn run_headless(data_dir: PathBuf, list_name: &str, run: impl FnOnce(&Path, &Config) -> Result<()>) -> i32 {
+    let lists = TaskLists::new(data_dir);
+    let (config, config_error) = Config::load_or_default(lists.root());
+    if let Some(err) = config_error {
+        eprintln!("Warning: {err}. Using the default settings");
+    }
+    match lists.dir(list_name).and_then(|dir| run(&dir, &config)) {
+        Ok(()) => 0,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            2
+        },
+    }
+}
+
+/// Serves the named list until killed, or it can't be served
+fn serve(dir: &Path, list_name: &str, config: &Config, addr: &str) -> Result<()> {
+    let (_lock, mut tasks) = hold_list(dir, list_name, config)?;
+    // The archive and snapshots aren't encrypted, so aren't kept for
+    // encrypted lists
+    let encrypted = tasks.store().is_encrypted();
+    if !encrypted {
+        if let Some(after_days) = config.archive.after_days {
+            Archive::new(dir, config.archive.period).archive_completed(&mut tasks, after_days)?;
+        }
+    }
+    let mut server = Server::bind(addr, tasks, create_server_token(dir)?)?;
+    if !encrypted {
+        server.set_snapshots(Snapshots::new(dir, config.snapshots.clone()));
+    }
+    println!("Serving list {list_name} on http://{}, to clients with the token in {}",
+        server.local_addr()?, dir.join(SERVER_TOKEN_FILE).display());
+    server.serve()
+}
+
+/// Loads the list in `dir` read-only, without touching the files on disk
+fn load_for_reading(dir: &Path, config: &Config) -> Result<TaskList<Box<dyn TaskStore>>> {
+    let mut tasks = TaskList::new(unlocked_store(dir, true, config)?);
+    tasks.set_read_only(true);
+    tasks.reload()?;
+    Ok(tasks)
+}
+
+/// Writes `text` to `file`, or standard output
+fn write_out(file: Option<&Path>, text: &str) -> Result<()> {
+    match file {
+        Some(file) => std::fs::write(file, text),
+        None => stdout().write_all(text.as_bytes()),
+    }
+}
+
+/// Writes the list in `format` to `file`, or standard output
+fn export(dir: &Path, config: &Config, format: Format, file: Option<&Path>) -> Result<()> {
+    let tasks = load_for_reading(dir, config)?;
+    let (exported, report) = format.export(tasks.tasks());
+    write_out(file, &exported)?;
+    for line in report {
+        eprintln!("Note: {line}");
+    }
+    Ok(())
+}
+
+/// Writes the tasks in the list and its archive completed in `range` to
+/// `file`, or standard output
+fn history(dir: &Path, config: &Config, format: HistoryFormat, range: DateRange, file: Option<&Path>) -> Result<()> {
+    let tasks = load_for_reading(dir, config)?;
+    let archived = Archive::new(dir, config.archive.period).load_all()?;
+    write_out(file, &to_history(&completions(tasks.tasks().iter().chain(&archived), range), format)?)
+}
+
+/// Adds the tasks in `file`, written in `format`, to the bottom of the list
+fn import(dir: &Path, list_name: &str, config: &Config, format: Format, file: &Path) -> Result<()> {
+    let (imported, report) = format.import(&std::fs::read_to_string(file)?)?;
+    let (_lock, mut tasks) = hold_list(dir, list_name, config)?;
+    let added = tasks.add_imported(imported, format.imports_new_tasks())?;
+    println!("Imported {added} tasks into list {list_name}");
+    for line in report {
+        println!("Note: {line}");
+    }
+    Ok(())
+}
+
 fn main() -> Result<()> {
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("Error: {message}\n\n{USAGE}");
+            std::process::exit(2);
+        },
+    };
+    if args.help {
+        println!("{USAGE}");
+        return Ok(());
+    }
+    if let Some(Command::Sync { ancestor, ours, theirs, output }) = &args.command {
+        std::process::exit(sync(ancestor, ours, theirs, output));
+    }
+    let addr = args.addr.as_deref().unwrap_or(DEFAULT_SERVER_ADDR);
+    let list_name = args.list.as_deref().unwrap_or(DEFAULT_LIST);
+    let data_dir = match TaskLists::validate_name(list_name).and_then(|()| resolve_data_dir(args.data_dir, args.profile)) {
+        Ok(data_dir) => data_dir,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            std::process::exit(2);
+        },
+    };
+    if let Some(Command::Connect) = &args.command {
+        let mut mainview = match MainView::connect(data_dir, list_name, addr) {
+            Ok(mainview) => mainview,
+            Err(err) => {
+                eprintln!("Error: Couldn't load the list from {addr}: {err}");
+                std::process::exit(2);
+            },
+        };
+        let mut terminal = setup_ratatui()?;
+        mainview.run(&mut terminal);
+        return shutdown_ratatui();
+    }
+    match &args.command {
+        Some(Command::Serve) => std::process::exit(run_headless(data_dir, list_name,
+            |dir, config| serve(dir, list_name, config, addr))),
+        Some(Command::Export { format, file }) => std::process::exit(run_headless(data_dir, list_name,
+            |dir, config| export(dir, config, *format, file.as_deref()))),
+        Some(Command::Import { format, file }) => std::process::exit(run_headless(data_dir, list_name,
+            |dir, config| import(dir, list_name, config, *format, file))),
+        Some(Command::History { format, file }) => std::process::exit(run_headless(data_dir, list_name,
+            |dir, config| history(dir, config, *format, args.range, file.as_deref()))),
+        _ => (),
+    }
     let mut terminal = setup_ratatui()?;
-    let mut mainview = MainView::new();
+    let mut mainview = MainView::open(data_dir, list_name);
     let result = mainview.run(&mut terminal);
     shutdown_ratatui()?;
     if !result {
-        eprintln!("Error: Failed to acquire lock file. Another instance may be running.");
+        eprintln!("Error: Failed to check the lock file. Check the task data directory is accessible.");
     }
     Ok(())
 }
@@ -0,0 +1,311 @@
+use std::collections::{HashMap, HashSet};
+
+use uuid::Uuid;
+
+use crate::Task;
+
+/// A task changed differently in two versions of the list
+#[derive(Clone)]
+pub struct Conflict {
+    pub uuid: Uuid,
+    /// Our version of the task, or `None` if we deleted it.  Where both sides
+    /// edited the task, this is the merged task with our values for the
+    /// conflicting fields.
+    pub ours: Option<Task>,
+    /// Their version of the task, or `None` if they deleted it.  Where both
+    /// sides edited the task, this is the merged task with their values for
+    /// the conflicting fields.
+    pub theirs: Option<Task>,
+    /// The fields changed differently on both sides, or empty if one side
+    /// deleted the task, or both added it differently
+    pub fields: Vec<&'static str>,
+}
+
+impl Conflict {
+    /// Returns the version kept by the merge: ours, unless we deleted the task,
+    /// in which case theirs, so that nothing is lost.
+    #[must_use]
+    pub fn kept(&self) -> Option<&Task> {
+        self.ours.as_ref().or(self.theirs.as_ref())
+    }
+}
+
+/// The result of a three-way merge
+pub struct MergeResult {
+    pub tasks: Vec<Task>,
+    pub conflicts: Vec<Conflict>,
+    /// True if both sides reordered the list, in which case our order is kept
+    pub order_conflict: bool,
+}
+
+/// Merges two versions of the task list, `ours` and `theirs`, which have both
+/// changed since their common ancestor `base`.
+///
+/// Tasks are matched by uuid.  A task changed (or added, or deleted) on only
+/// one side takes that side's version.  A task edited on both sides is merged
+/// field by field (see ``Task::merge_fields``), and any field changed
+/// differently on both sides is a conflict, for which our value is kept.  A
+/// task deleted on one side and edited on the other is also a conflict: the
+/// merge keeps the edited version, so that nothing is lost.
+///
+/// If we haven't moved any tasks, the result follows their order, otherwise
+/// ours (which is reported as a conflict if they moved tasks too).  Tasks only
+/// in the other version are placed after the task they follow there.
+/// Conflicts are returned in the order of the merged list.
+#[must_use]
+pub fn merge(base: &[Task], ours: &[Task], theirs: &[Task]) -> MergeResult {
+    let base_by_uuid = by_uuid(base);
+    let ours_by_uuid = by_uuid(ours);
+    let theirs_by_uuid = by_uuid(theirs);
+
+    let mut kept: HashMap<Uuid, Task> = HashMap::new();
+    let mut conflicts = vec![];
+    let all_uuids = ours.iter().chain(theirs).chain(base).map(Task::uuid).collect::<HashSet<_>>();
+    for uuid in all_uuids {
+        let base_task = base_by_uuid.get(&uuid).copied();
+        let our_task = ours_by_uuid.get(&uuid).copied();
+        let their_task = theirs_by_uuid.get(&uuid).copied();
+        let merged = if our_task == their_task || their_task == base_task {
+            our_task.cloned()
+        } else if our_task == base_task {
+            their_task.cloned()
+        } else if let (Some(base_task), Some(our_task), Some(their_task)) = (base_task, our_task, their_task) {
+            let (merged, fields) = Task::merge_fields(base_task, our_task, their_task, false);
+            if !fields.is_empty() {
+                let (theirs, _) = Task::merge_fields(base_task, our_task, their_task, true);
+                conflicts.push(Conflict { uuid, ours: Some(merged.clone()), theirs: Some(theirs), fields });
+            }
+            Some(merged)
+        } else {
+            let conflict = Conflict { uuid, ours: our_task.cloned(), theirs: their_task.cloned(), fields: vec![] };
+            conflicts.push(conflict);
+            our_task.or(their_task).cloned()
+        };
+        if let Some(task) = merged {
+            kept.insert(uuid, task);
+        }
+    }
+
+    let base_order = base.iter().map(Task::uuid).collect::<Vec<_>>();
+    let our_order = ours.iter().map(Task::uuid).collect::<Vec<_>>();
+    let their_order = theirs.iter().map(Task::uuid).collect::<Vec<_>>();
+    let we_moved = !same_relative_order(&base_order, &our_order);
+    let order_conflict = we_moved && !same_relative_order(&base_order, &their_order)
+        && !same_relative_order(&our_order, &their_order);
+    let (primary, secondary) = if we_moved {
+        (our_order, their_order)
+    } else {
+        (their_order, our_order)
+    };
+    let tasks = merge_order(&primary, &secondary).into_iter()
+        .filter_map(|uuid| kept.remove(&uuid))
+        .collect::<Vec<_>>();
+    // Conflicts come in list order, rather than the order of the set
+    let positions = tasks.iter().enumerate().map(|(index, task)| (task.uuid(), index)).collect::<HashMap<_, _>>();
+    conflicts.sort_by_key(|conflict| positions.get(&conflict.uuid).copied());
+    MergeResult { tasks, conflicts, order_conflict }
+}
+
+fn by_uuid(tasks: &[Task]) -> HashMap<Uuid, &Task> {
+    tasks.iter().map(|t| (t.uuid(), t)).collect()
+}
+
+/// Returns true if the tasks in both `a` and `b` are in the same order in each,
+/// i.e. the sides only added or removed tasks, rather than moving them
+fn same_relative_order(a: &[Uuid], b: &[Uuid]) -> bool {
+    let in_a = a.iter().copied().collect::<HashSet<_>>();
+    let in_b = b.iter().copied().collect::<HashSet<_>>();
+    a.iter().filter(|uuid| in_b.contains(uuid)).eq(b.iter().filter(|uuid| in_a.contains(uuid)))
+}
+
+/// Returns `primary`, with any uuids only in `secondary` inserted after the
+/// uuid they follow in `secondary` (or at the start, if they're first).
+fn merge_order(primary: &[Uuid], secondary: &[Uuid]) -> Vec<Uuid> {
+    let mut order = primary.to_vec();
+    let mut present = primary.iter().copied().collect::<HashSet<_>>();
+    let mut previous: Option<Uuid> = None;
+    for uuid in secondary {
+        if present.insert(*uuid) {
+            let index = previous
+                .and_then(|previous| order.iter().position(|u| *u == previous))
+                .map_or(0, |index| index + 1);
+            order.insert(index, *uuid);
+        }
+        previous = Some(*uuid);
+    }
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tasks(descriptions: &[&str]) -> Vec<Task> {
+        descriptions.iter().map(|description| Task::new(description)).collect()
+    }
+
+    fn descriptions(tasks: &[Task]) -> Vec<&str> {
+        tasks.iter().map(Task::description).collect()
+    }
+
+    fn edited(task: &Task, description: &str) -> Task {
+        let mut task = task.clone();
+        task.update_description(description);
+        task
+    }
+
+    #[test]
+    fn unchanged_sides_merge_to_the_same_list() {
+        let base = tasks(&["one", "two"]);
+        let merged = merge(&base, &base, &base);
+        assert!(merged.tasks == base);
+        assert!(merged.conflicts.is_empty());
+        assert!(!merged.order_conflict);
+    }
+
+    #[test]
+    fn additions_and_deletions_on_both_sides_are_kept() {
+        let base = tasks(&["one", "two", "three"]);
+        let mut ours = base[1..].to_vec();
+        ours.push(Task::new("ours"));
+        let mut theirs = vec![base[0].clone(), base[2].clone()];
+        theirs.insert(1, Task::new("theirs"));
+
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(descriptions(&merged.tasks), ["theirs", "three", "ours"]);
+        assert!(merged.conflicts.is_empty());
+    }
+
+    #[test]
+    fn edits_on_one_side_are_taken() {
+        let base = tasks(&["one", "two"]);
+        let ours = vec![edited(&base[0], "ours"), base[1].clone()];
+        let theirs = vec![base[0].clone(), edited(&base[1], "theirs")];
+
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(descriptions(&merged.tasks), ["ours", "theirs"]);
+        assert!(merged.conflicts.is_empty());
+    }
+
+    #[test]
+    fn the_same_edit_on_both_sides_isnt_a_conflict() {
+        let base = tasks(&["one"]);
+        let both = vec![edited(&base[0], "both")];
+
+        let merged = merge(&base, &both, &both);
+        assert_eq!(descriptions(&merged.tasks), ["both"]);
+        assert!(merged.conflicts.is_empty());
+    }
+
+    #[test]
+    fn deleting_an_edited_task_keeps_the_edit() {
+        let base = tasks(&["one", "two"]);
+        let ours = vec![edited(&base[0], "ours"), base[1].clone()];
+        let theirs = vec![base[1].clone()];
+
+        for merged in [merge(&base, &ours, &theirs), merge(&base, &theirs, &ours)] {
+            assert_eq!(descriptions(&merged.tasks), ["ours", "two"]);
+            assert_eq!(merged.conflicts.len(), 1);
+            let conflict = &merged.conflicts[0];
+            assert_eq!(conflict.uuid, base[0].uuid());
+            assert!(conflict.fields.is_empty());
+            assert_eq!(conflict.kept().map(Task::description), Some("ours"));
+        }
+    }
+
+    #[test]
+    fn their_order_is_taken_if_we_didnt_move_tasks() {
+        let base = tasks(&["one", "two", "three"]);
+        let ours = vec![base[0].clone(), base[1].clone()];
+        let theirs = vec![base[2].clone(), base[0].clone(), base[1].clone()];
+
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(descriptions(&merged.tasks), ["one", "two"]);
+        let merged = merge(&base, &base, &theirs);
+        assert_eq!(descriptions(&merged.tasks), ["three", "one", "two"]);
+        assert!(!merged.order_conflict);
+    }
+
+    #[test]
+    fn our_order_is_kept_if_both_moved_tasks() {
+        let base = tasks(&["one", "two", "three"]);
+        let ours = vec![base[1].clone(), base[0].clone(), base[2].clone()];
+        let theirs = vec![base[0].clone(), base[2].clone(), base[1].clone()];
+
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(descriptions(&merged.tasks), ["two", "one", "three"]);
+        assert!(merged.order_conflict);
+        // Moving tasks the same way isn't a conflict
+        let merged = merge(&base, &ours, &ours);
+        assert!(!merged.order_conflict);
+    }
+
+    #[test]
+    fn added_tasks_follow_the_task_before_them() {
+        let base = tasks(&["one", "two"]);
+        let ours = vec![base[1].clone(), base[0].clone()];
+        let added = Task::new("added");
+        let first = Task::new("first");
+        let theirs = vec![first.clone(), base[0].clone(), added.clone(), base[1].clone()];
+
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(descriptions(&merged.tasks), ["first", "two", "one", "added"]);
+    }
+
+    #[test]
+    fn conflicts_are_in_list_order() {
+        let base = tasks(&["one", "two", "three", "four"]);
+        let ours = base.iter().map(|task| edited(task, "ours")).collect::<Vec<_>>();
+        let theirs = vec![];
+
+        let merged = merge(&base, &ours, &theirs);
+        let order = merged.conflicts.iter().map(|conflict| conflict.uuid).collect::<Vec<_>>();
+        assert!(order == base.iter().map(Task::uuid).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn same_relative_order_ignores_added_and_removed() {
+        let [a, b, c, d] = [1, 2, 3, 4].map(Uuid::from_u128);
+        assert!(same_relative_order(&[a, b, c], &[a, d, c]));
+        assert!(same_relative_order(&[a, b, c], &[]));
+        assert!(!same_relative_order(&[a, b, c], &[c, a]));
+    }
+
+    #[test]
+    fn merge_order_inserts_after_the_previous_uuid() {
+        let [a, b, c, d] = [1, 2, 3, 4].map(Uuid::from_u128);
+        assert_eq!(merge_order(&[a, c], &[d, a, b, c]), [d, a, b, c]);
+        assert_eq!(merge_order(&[c, a], &[a, b, d]), [c, a, b, d]);
+        assert_eq!(merge_order(&[], &[a, b]), [a, b]);
+    }
+
+    #[test]
+    fn tasks_edited_on_both_sides_are_merged_field_by_field() {
+        let base = tasks(&["one", "two"]);
+        let mut ours = vec![edited(&base[0], "ours"), edited(&base[1], "ours")];
+        let mut theirs = base.clone();
+        theirs[0].toggle_dot();
+        theirs[1].update_description("theirs");
+
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(descriptions(&merged.tasks), ["ours", "ours"]);
+        assert!(merged.tasks[0].dot());
+        assert_eq!(merged.conflicts.len(), 1);
+        let conflict = &merged.conflicts[0];
+        assert_eq!(conflict.uuid, base[1].uuid());
+        assert_eq!(conflict.fields, ["description"]);
+        assert_eq!(conflict.ours.as_ref().map(Task::description), Some("ours"));
+        assert_eq!(conflict.theirs.as_ref().map(Task::description), Some("theirs"));
+
+        // Their other changes are in both versions of the conflicting task
+        ours[1].toggle_dot();
+        theirs[1].snooze_tomorrow();
+        let merged = merge(&base, &ours, &theirs);
+        assert_eq!(merged.conflicts.len(), 1);
+        let conflict = &merged.conflicts[0];
+        for version in [&conflict.ours, &conflict.theirs] {
+            let version = version.as_ref().map(|task| (task.dot(), task.snooze_until()));
+            assert_eq!(version, Some((true, theirs[1].snooze_until())));
+        }
+    }
+}
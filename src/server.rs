@@ -0,0 +1,389 @@
+use std::{fs::{read_to_string, remove_file, OpenOptions}, io::Write, net::{IpAddr, SocketAddr, TcpListener}, path::Path, sync::mpsc, thread};
+
+use serde::Deserialize;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{http::{Message, VERSION_HEADER}, Snapshots, Task, TaskList, TaskStore};
+
+/// The address the server listens on, and clients connect to, by default
+pub const DEFAULT_SERVER_ADDR : &str = "127.0.0.1:7420";
+
+/// The file, in the list's directory, holding the token clients must send
+pub const SERVER_TOKEN_FILE : &str = "server-token";
+
+/// The body of a request to add a task
+#[derive(Deserialize)]
+struct NewTask {
+    description: String,
+}
+
+/// The body of a request to dot or undot a task
+#[derive(Deserialize)]
+struct SetDot {
+    dot: bool,
+}
+
+/// The body of a request to set or clear a task's daily recurrence
+#[derive(Deserialize)]
+struct SetRecur {
+    recur: bool,
+}
+
+/// A headless server owning a task list, so that any number of clients (the
+/// TUI, scripts, editor plugins) can work on the same live list.
+///
+/// It speaks JSON over HTTP, and only listens on the loopback interface.
+/// So that web pages can't use it, requests must be addressed to localhost,
+/// carry the token from ``create_server_token`` as `Authorization: Bearer
+/// <token>`, and have `Content-Type: application/json` unless they're GETs.
+/// Tasks are referred to by uuid, or any unambiguous prefix of one at least
+/// four characters long.
+///
+/// - `GET /tasks` - every task, in list order
+/// - `PUT /tasks` - replace the list with an edited copy of it, giving the
+///   version it's a copy of in `X-Task-Version`.  If the list has changed
+///   since, this fails with status 409, and the copy must be merged with the
+///   list as it is now before trying again.
+/// - `POST /tasks` - add a task: `{"description": "..."}`
+/// - `GET /tasks/next` - the next task, as shown by the TUI
+/// - `GET /tasks/<uuid>` - one task
+/// - `POST /tasks/<uuid>/complete` - complete a task, if it isn't already
+/// - `POST /tasks/<uuid>/dot` - dot or undot a task: `{"dot": true}`
+/// - `POST /tasks/<uuid>/snooze` - snooze a task until tomorrow
+/// - `POST /tasks/<uuid>/recur` - set or clear daily recurrence:
+///   `{"recur": true}`
+/// - `DELETE /tasks/<uuid>` - delete a task
+/// - `GET /version` - the list's version
+///
+/// Every response carries the list's version in `X-Task-Version`, which goes
+/// up whenever the list changes, so clients can tell when to reload.  Errors
+/// are returned as `{"error": "..."}`.
+pub struct Server<S: TaskStore> {
+    listener: TcpListener,
+    tasks: TaskList<S>,
+    token: String,
+    version: u64,
+    snapshots: Option<Snapshots>,
+}
+
+/// Creates a new token for serving the list in `dir`, replacing any earlier
+/// one, and writes it to a file there which only its owner can read, for
+/// clients to send with their requests
+///
+/// # Errors
+///
+/// Will return `Err` if the file can't be written.
+pub fn create_server_token(dir: &Path) -> std::io::Result<String> {
+    let path = dir.join(SERVER_TOKEN_FILE);
+    match remove_file(&path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err),
+        _ => (),
+    }
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let token = Uuid::new_v4().simple().to_string();
+    options.open(&path)?.write_all(token.as_bytes())?;
+    Ok(token)
+}
+
+/// Reads the token clients of the server of the list in `dir` must send
+///
+/// # Errors
+///
+/// Will return `Err` if the file can't be read, e.g. because the list has
+/// never been served.
+pub fn read_server_token(dir: &Path) -> std::io::Result<String> {
+    let path = dir.join(SERVER_TOKEN_FILE);
+    read_to_string(&path).map(|token| token.trim().to_string())
+        .map_err(|err| std::io::Error::new(err.kind(), format!("Couldn't read the server's token from {}: {err}", path.display())))
+}
+
+impl<S: TaskStore> Server<S> {
+    /// Starts listening on `addr`, which must be a loopback address, for
+    /// requests carrying `token`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if `addr` isn't a loopback address, or can't be
+    /// listened on.
+    pub fn bind(addr: &str, tasks: TaskList<S>, token: String) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        if !listener.local_addr()?.ip().is_loopback() {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                format!("{addr} isn't a local address: the server only listens on localhost")));
+        }
+        Ok(Self { listener, tasks, token, version: 1, snapshots: None })
+    }
+
+    /// Returns the address being listened on
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the address can't be found.
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Takes rolling snapshots of the list as it changes
+    pub fn set_snapshots(&mut self, snapshots: Snapshots) {
+        self.snapshots = Some(snapshots);
+    }
+
+    /// Serves requests forever.  Each connection is read from and written to
+    /// on a thread of its own, so a slow client holds up no one else, but
+    /// requests are carried out one at a time, in the order they arrive.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the listener fails.  Failures of individual
+    /// connections are ignored.
+    pub fn serve(&mut self) -> std::io::Result<()> {
+        let listener = self.listener.try_clone()?;
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || loop {
+            let (stream, _) = match listener.accept() {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    let _ = sender.send(Err(err));
+                    return;
+                },
+            };
+            let sender = sender.clone();
+            thread::spawn(move || {
+                let (respond, response) = mpsc::channel::<Message>();
+                if sender.send(Ok((Message::read(&stream), respond))).is_ok() {
+                    if let Ok(response) = response.recv() {
+                        let _ = response.write(&stream);
+                    }
+                }
+            });
+        });
+        for request in requests {
+            let (request, respond) = request?;
+            let _ = respond.send(self.handle(request));
+        }
+        Ok(())
+    }
+
+    /// Returns the response to `request`, as read from a connection
+    fn handle(&mut self, request: std::io::Result<Message>) -> Message {
+        let (status, body) = match request {
+            Ok(request) => {
+                let mut parts = request.start_line.split_whitespace();
+                let method = parts.next().unwrap_or_default().to_string();
+                let path = parts.next().unwrap_or_default();
+                let path = path.split_once('?').map_or(path, |(path, _)| path).to_string();
+                let seen_version = request.header(VERSION_HEADER).and_then(|version| version.parse().ok());
+                match self.refusal(&request, &method) {
+                    Some((status, error)) => (status, json!({ "error": error })),
+                    None => self.respond(&method, &path, seen_version, &request.body),
+                }
+            },
+            Err(err) => (400, json!({ "error": err.to_string() })),
+        };
+        let reason = match status {
+            200 => "OK",
+            201 => "Created",
+            204 => "No Content",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            415 => "Unsupported Media Type",
+            428 => "Precondition Required",
+            _ => "Internal Server Error",
+        };
+        let body = if status == 204 { vec![] } else { body.to_string().into_bytes() };
+        Message {
+            start_line: format!("HTTP/1.1 {status} {reason}"),
+            headers: vec![
+                ("Content-Type".to_string(), "application/json".to_string()),
+                (VERSION_HEADER.to_string(), self.version.to_string()),
+            ],
+            body,
+        }
+    }
+
+    /// Returns the status and error to refuse `request` with, if it might have
+    /// come from a web page, rather than a client of ours
+    fn refusal(&self, request: &Message, method: &str) -> Option<(u16, String)> {
+        if !request.header("Host").is_some_and(is_local_host) {
+            return Some((403, "Requests must be addressed to localhost".to_string()));
+        }
+        let token = request.header("Authorization").and_then(|authorization| authorization.strip_prefix("Bearer "));
+        if !token.is_some_and(|token| same_token(token.trim(), &self.token)) {
+            return Some((401, format!("Requests must carry the token in {SERVER_TOKEN_FILE}, as Authorization: Bearer <token>")));
+        }
+        let content_type = request.header("Content-Type").map(|content_type| content_type.split(';').next().unwrap_or_default().trim());
+        if method != "GET" && !content_type.is_some_and(|content_type| content_type.eq_ignore_ascii_case("application/json")) {
+            return Some((415, "Requests other than GETs must have Content-Type: application/json".to_string()));
+        }
+        None
+    }
+
+    /// Handles a request, returning the status and JSON body of the response.
+    /// External changes to the list are merged in first.  `seen_version` is
+    /// the version of the list the request is based on, which is required
+    /// for replacing the list, and must be the current version.
+    pub fn respond(&mut self, method: &str, path: &str, seen_version: Option<u64>, body: &[u8]) -> (u16, serde_json::Value) {
+        let before = self.tasks.tasks().to_vec();
+        let merged = self.tasks.merge_external_changes().and_then(|_| self.tasks.pre_render());
+        self.note_changes(&before);
+        let response = merged.and_then(|()| {
+            let before = self.tasks.tasks().to_vec();
+            let response = self.route(method, path, seen_version, body);
+            self.note_changes(&before);
+            response
+        });
+        response.unwrap_or_else(|err| {
+            let status = match err.kind() {
+                std::io::ErrorKind::NotFound => 404,
+                std::io::ErrorKind::InvalidInput | std::io::ErrorKind::InvalidData => 400,
+                std::io::ErrorKind::PermissionDenied => 403,
+                _ => 500,
+            };
+            (status, json!({ "error": err.to_string() }))
+        })
+    }
+
+    /// Moves the version on if the list has changed from `before`, taking a
+    /// snapshot if one is due
+    fn note_changes(&mut self, before: &[Task]) {
+        if self.tasks.tasks() != before {
+            self.version += 1;
+            if let Some(snapshots) = &mut self.snapshots {
+                // A missed snapshot mustn't fail the request
+                let _ = snapshots.take_if_due(self.tasks.tasks());
+            }
+        }
+    }
+
+    fn route(&mut self, method: &str, path: &str, seen_version: Option<u64>, body: &[u8])
+            -> std::io::Result<(u16, serde_json::Value)> {
+        let segments = path.trim_matches('/').split('/').collect::<Vec<_>>();
+        match (method, segments.as_slice()) {
+            ("GET", ["version"]) => Ok((200, json!({ "version": self.version }))),
+            ("GET", ["tasks"]) => Ok((200, serde_json::to_value(self.tasks.tasks())?)),
+            ("PUT", ["tasks"]) => match seen_version {
+                None => Ok((428, json!({ "error": format!("Replacing the list needs the version it was based on, in {VERSION_HEADER}") }))),
+                // Replacing the list would undo the changes made since
+                Some(seen) if seen != self.version => Ok((409, json!({
+                    "error": format!("The list has changed since version {seen}, so the edited copy must be merged with it first")
+                }))),
+                Some(_) => {
+                    self.tasks.apply_edited(serde_json::from_slice(body)?)?;
+                    Ok((200, serde_json::to_value(self.tasks.tasks())?))
+                },
+            },
+            ("POST", ["tasks"]) => {
+                let new_task: NewTask = serde_json::from_slice(body)?;
+                let task = Task::new(&new_task.description);
+                self.tasks.add(task.clone())?;
+                Ok((201, serde_json::to_value(task)?))
+            },
+            ("GET", ["tasks", "next"]) => match self.tasks.last_dotted_task() {
+                Some(task) => Ok((200, serde_json::to_value(task)?)),
+                None => Err(std::io::Error::new(std::io::ErrorKind::NotFound, "No next task")),
+            },
+            ("GET", ["tasks", prefix]) => Ok((200, serde_json::to_value(self.tasks.find_by_prefix(prefix)?)?)),
+            ("DELETE", ["tasks", prefix]) => {
+                let uuid = self.tasks.find_by_prefix(prefix)?.uuid();
+                self.tasks.delete(uuid)?;
+                Ok((204, serde_json::Value::Null))
+            },
+            ("POST", ["tasks", prefix, action]) => {
+                let uuid = self.tasks.find_by_prefix(prefix)?.uuid();
+                match *action {
+                    "complete" => self.tasks.complete(uuid)?,
+                    "dot" => self.tasks.set_dot(uuid, serde_json::from_slice::<SetDot>(body)?.dot)?,
+                    "snooze" => self.tasks.snooze_tomorrow(uuid)?,
+                    "recur" => self.tasks.set_recur_daily(uuid, serde_json::from_slice::<SetRecur>(body)?.recur)?,
+                    _ => return Ok((404, json!({ "error": format!("Unknown action '{action}'") }))),
+                };
+                Ok((200, serde_json::to_value(self.tasks.get(uuid))?))
+            },
+            (_, ["version" | "tasks"] | ["tasks", _] | ["tasks", _, _]) =>
+                Ok((405, json!({ "error": format!("{method} isn't supported for {path}") }))),
+            _ => Ok((404, json!({ "error": format!("Nothing at {path}") }))),
+        }
+    }
+}
+
+/// Returns true if `host`, from a Host header, is this machine: `localhost`
+/// or a loopback address, with or without a port
+fn is_local_host(host: &str) -> bool {
+    let host = host.trim();
+    let name = match host.strip_prefix('[') {
+        Some(bracketed) => bracketed.split_once(']').map_or(bracketed, |(name, _)| name),
+        None => host.rsplit_once(':').map_or(host, |(name, _)| name),
+    };
+    name.eq_ignore_ascii_case("localhost") || name.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+}
+
+/// Compares tokens in constant time, so that how long it takes doesn't give
+/// away how much of a guess was right
+fn same_token(given: &str, token: &str) -> bool {
+    given.len() == token.len() && given.bytes().zip(token.bytes()).fold(0, |differ, (a, b)| differ | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpStream, time::{Duration, Instant}};
+
+    use super::*;
+    use crate::{http, MemoryStore};
+
+    #[test]
+    fn silent_clients_hold_up_no_one_else() {
+        let tasks = TaskList::load(MemoryStore::new(vec![Task::new("Only")])).expect("a list in memory");
+        let mut server = Server::bind("127.0.0.1:0", tasks, "token".to_string()).expect("a server");
+        let addr = server.local_addr().expect("the address").to_string();
+        thread::spawn(move || server.serve());
+        let _silent = TcpStream::connect(&addr).expect("a connection");
+        let started = Instant::now();
+        let (status, _) = http::request(&addr, "token", "GET", "/tasks", None, None).expect("a response");
+        assert_eq!(status, 200);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn only_local_hosts_are_local() {
+        for host in ["localhost", "LocalHost:7420", "127.0.0.1", "127.0.0.1:7420", "127.1.2.3:80", "[::1]", "[::1]:7420"] {
+            assert!(is_local_host(host), "{host}");
+        }
+        for host in ["", "example.com", "example.com:7420", "localhost.example.com", "192.168.1.1:7420", "[::2]:7420",
+                "::1", "0.0.0.0:7420"] {
+            assert!(!is_local_host(host), "{host}");
+        }
+    }
+
+    #[test]
+    fn tokens_must_match_exactly() {
+        assert!(same_token("abc123", "abc123"));
+        assert!(!same_token("abc124", "abc123"));
+        assert!(!same_token("abc12", "abc123"));
+        assert!(!same_token("", "abc123"));
+    }
+
+    #[test]
+    fn tokens_are_kept_private() {
+        let dir = std::env::temp_dir().join(format!("task-server-test-{}", Uuid::new_v4()));
+        std::fs::create_dir(&dir).expect("creating the test directory");
+        let first = create_server_token(&dir).expect("creating a token");
+        let second = create_server_token(&dir).expect("replacing the token");
+        let read = read_server_token(&dir).expect("reading the token");
+        #[cfg(unix)]
+        let mode = std::os::unix::fs::PermissionsExt::mode(&std::fs::metadata(dir.join(SERVER_TOKEN_FILE))
+            .expect("the token file").permissions());
+        std::fs::remove_dir_all(&dir).expect("removing the test directory");
+        assert_ne!(first, second);
+        assert_eq!(read, second);
+        #[cfg(unix)]
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,185 @@
+use std::{cmp::Reverse, collections::{HashMap, HashSet}, fs::{create_dir_all, read, read_dir, remove_file}, path::{Path, PathBuf}};
+
+use chrono::{Local, NaiveDateTime, Timelike};
+use uuid::Uuid;
+
+use crate::{atomicfile, decode_tasks, encode_tasks, SnapshotRetention, Task};
+
+const SNAPSHOT_DIR : &str = "snapshots";
+const SNAPSHOT_PREFIX : &str = "snapshot-";
+const SNAPSHOT_SUFFIX : &str = ".json";
+const SNAPSHOT_TIME_FORMAT : &str = "%Y%m%d-%H%M%S";
+
+/// A snapshot of the task list on disk
+#[derive(Clone)]
+pub struct Snapshot {
+    path: PathBuf,
+    taken: NaiveDateTime,
+}
+
+impl Snapshot {
+    #[must_use]
+    pub fn taken(&self) -> NaiveDateTime {
+        self.taken
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Loads the tasks in the snapshot
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the snapshot can't be read or parsed.
+    pub fn load(&self) -> std::io::Result<Vec<Task>> {
+        Ok(decode_tasks(&read(&self.path)?)?.tasks)
+    }
+}
+
+/// The differences between two versions of the task list
+#[derive(Default)]
+pub struct SnapshotDiff {
+    /// Tasks only in the other version
+    pub added: usize,
+    /// Tasks only in this version
+    pub removed: usize,
+    /// Tasks in both, but different
+    pub changed: usize,
+}
+
+impl SnapshotDiff {
+    /// Compares two versions of the task list, by task uuid
+    #[must_use]
+    pub fn between(from: &[Task], to: &[Task]) -> Self {
+        let from_by_uuid: HashMap<Uuid, &Task> = from.iter().map(|t| (t.uuid(), t)).collect();
+        let to_uuids: HashSet<Uuid> = to.iter().map(Task::uuid).collect();
+        let mut diff = Self::default();
+        for task in to {
+            match from_by_uuid.get(&task.uuid()) {
+                None => diff.added += 1,
+                Some(from_task) if *from_task != task => diff.changed += 1,
+                Some(_) => (),
+            }
+        }
+        diff.removed = from.iter().filter(|t| !to_uuids.contains(&t.uuid())).count();
+        diff
+    }
+}
+
+/// Rolling, timestamped snapshots of the task list, kept in the `snapshots`
+/// directory.  At most one snapshot is taken each hour, and old ones are pruned
+/// according to the configured retention.
+pub struct Snapshots {
+    dir: PathBuf,
+    retention: SnapshotRetention,
+    last_taken: Option<NaiveDateTime>,
+}
+
+impl Snapshots {
+    #[must_use]
+    pub fn new(data_dir: &Path, retention: SnapshotRetention) -> Self {
+        Self {
+            dir: data_dir.join(SNAPSHOT_DIR),
+            retention,
+            last_taken: None,
+        }
+    }
+
+    /// Returns all snapshots, newest first
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the snapshot directory exists but can't be read.
+    pub fn list(&self) -> std::io::Result<Vec<Snapshot>> {
+        if !self.dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut snapshots = vec![];
+        for entry in read_dir(&self.dir)? {
+            let path = entry?.path();
+            let taken = path.file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix(SNAPSHOT_PREFIX))
+                .and_then(|name| name.strip_suffix(SNAPSHOT_SUFFIX))
+                .and_then(|time| NaiveDateTime::parse_from_str(time, SNAPSHOT_TIME_FORMAT).ok());
+            if let Some(taken) = taken {
+                snapshots.push(Snapshot { path, taken });
+            }
+        }
+        snapshots.sort_by_key(|s| Reverse(s.taken));
+        Ok(snapshots)
+    }
+
+    /// Takes a snapshot of `tasks` if none has yet been taken this hour, and
+    /// the tasks have changed since the last one.
+    /// Returns true if a snapshot was taken.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the snapshot can't be written, or old ones pruned.
+    pub fn take_if_due(&mut self, tasks: &[Task]) -> std::io::Result<bool> {
+        let now = Local::now().naive_local();
+        if self.last_taken.is_none() {
+            self.last_taken = self.list()?.first().map(Snapshot::taken);
+        }
+        if self.last_taken.is_some_and(|last| Self::same_hour(last, now)) {
+            return Ok(false);
+        }
+        if let Some(latest) = self.list()?.first() {
+            if latest.load().is_ok_and(|latest_tasks| latest_tasks == tasks) {
+                self.last_taken = Some(now);
+                return Ok(false);
+            }
+        }
+        self.take(tasks)?;
+        Ok(true)
+    }
+
+    /// Takes a snapshot of `tasks` now, and prunes old snapshots.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the snapshot can't be written, or old ones pruned.
+    pub fn take(&mut self, tasks: &[Task]) -> std::io::Result<()> {
+        let now = Local::now().naive_local();
+        create_dir_all(&self.dir)?;
+        let file_name = format!("{SNAPSHOT_PREFIX}{}{SNAPSHOT_SUFFIX}", now.format(SNAPSHOT_TIME_FORMAT));
+        let serialized = encode_tasks(tasks)?;
+        atomicfile::write_atomic(&self.dir.join(file_name), None, serialized.as_bytes(), |written| {
+            decode_tasks(written).map(|_| ())
+        })?;
+        self.last_taken = Some(now);
+        self.prune()
+    }
+
+    fn same_hour(a: NaiveDateTime, b: NaiveDateTime) -> bool {
+        a.date() == b.date() && a.hour() == b.hour()
+    }
+
+    /// Deletes snapshots that are neither among the newest `hourly`, nor the
+    /// newest of the day for one of the last `daily` days.
+    fn prune(&self) -> std::io::Result<()> {
+        let snapshots = self.list()?;
+        let mut keep: HashSet<PathBuf> = snapshots.iter()
+            .take(self.retention.hourly)
+            .map(|s| s.path.clone())
+            .collect();
+        let mut days = HashSet::new();
+        for snapshot in &snapshots {
+            if days.len() >= self.retention.daily {
+                break;
+            }
+            if days.insert(snapshot.taken.date()) {
+                keep.insert(snapshot.path.clone());
+            }
+        }
+        for snapshot in snapshots {
+            if !keep.contains(&snapshot.path) {
+                remove_file(&snapshot.path)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+use std::{fs::{self, File}, io::Write, path::Path};
+
+/// Writes `contents` to `path` so that a crash at any point leaves either the
+/// old file or the complete new one in place.
+///
+/// The data is written to a temporary file alongside `path` and fsynced.  It is
+/// then read back and passed to `verify`, and only if that succeeds is it
+/// renamed over `path`.  If `backup` is given, the existing file at `path` is
+/// copied there (also atomically) just before the rename.
+///
+/// # Errors
+///
+/// Will return `Err` if any step fails, or `verify` rejects the written data.
+/// On error `path` is left untouched.
+pub fn write_atomic(
+        path: &Path,
+        backup: Option<&Path>,
+        contents: &[u8],
+        verify: impl Fn(&[u8]) -> std::io::Result<()>)
+            -> std::io::Result<()> {
+    let temp_path = temp_path(path);
+    let result = write_verified(&temp_path, contents, verify)
+        .and_then(|()| match backup {
+            Some(backup) if path.exists() => copy_atomic(path, backup),
+            _ => Ok(()),
+        })
+        .and_then(|()| fs::rename(&temp_path, path))
+        .and_then(|()| sync_dir(path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Copies `from` to `to` atomically, so that `to` is never partially written.
+///
+/// # Errors
+///
+/// Will return `Err` if `from` can't be read, or `to` can't be written.
+pub fn copy_atomic(from: &Path, to: &Path) -> std::io::Result<()> {
+    let contents = fs::read(from)?;
+    write_atomic(to, None, &contents, |_| Ok(()))
+}
+
+fn temp_path(path: &Path) -> std::path::PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".tmp");
+    path.with_file_name(file_name)
+}
+
+fn write_verified(path: &Path, contents: &[u8], verify: impl Fn(&[u8]) -> std::io::Result<()>) -> std::io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+    let written = fs::read(path)?;
+    if written != contents {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Data read back from {} doesn't match what was written", path.display()),
+        ));
+    }
+    verify(&written)
+}
+
+/// Makes a rename within the directory containing `path` durable
+fn sync_dir(path: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
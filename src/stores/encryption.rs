@@ -0,0 +1,176 @@
+use std::path::PathBuf;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::{aead::{rand_core::RngCore, Aead, KeyInit, OsRng, Payload}, XChaCha20Poly1305, XNonce};
+use zeroize::Zeroizing;
+
+/// Environment variable holding the passphrase for an encrypted list
+pub const PASSPHRASE_ENV : &str = "TASK_PASSPHRASE";
+/// Environment variable naming a file holding the passphrase for an encrypted list
+pub const KEYFILE_ENV : &str = "TASK_KEYFILE";
+
+const MAGIC : &[u8; 8] = b"TASKENC\x01";
+const SALT_LEN : usize = 16;
+const NONCE_LEN : usize = 24;
+const KEY_LEN : usize = 32;
+const HEADER_LEN : usize = MAGIC.len() + 3 * 4 + SALT_LEN + NONCE_LEN;
+
+/// A passphrase, wiped from memory when dropped
+pub type Passphrase = Zeroizing<String>;
+
+/// Why an encrypted task file couldn't be read.
+/// Carried inside the `std::io::Error` returned when loading, and found with
+/// ``EncryptionError::of``.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EncryptionError {
+    /// The file is encrypted, and no passphrase has been given
+    Locked,
+    /// The passphrase is wrong, or the file has been tampered with
+    WrongPassphrase,
+}
+
+impl std::fmt::Display for EncryptionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Locked => "Task list is encrypted, and needs a passphrase",
+            Self::WrongPassphrase => "Wrong passphrase, or the encrypted task list is damaged",
+        })
+    }
+}
+
+impl std::error::Error for EncryptionError {}
+
+impl From<EncryptionError> for std::io::Error {
+    fn from(err: EncryptionError) -> Self {
+        Self::new(std::io::ErrorKind::PermissionDenied, err)
+    }
+}
+
+impl EncryptionError {
+    /// Returns the encryption problem behind `err`, if that's what it is
+    #[must_use]
+    pub fn of(err: &std::io::Error) -> Option<Self> {
+        err.get_ref().and_then(|inner| inner.downcast_ref::<Self>()).copied()
+    }
+}
+
+/// Returns the passphrase from `$TASK_PASSPHRASE`, or else from the file named
+/// by `$TASK_KEYFILE` (without any trailing newline), if either is set.
+///
+/// # Errors
+///
+/// Will return `Err` if the keyfile can't be read.
+pub fn passphrase_from_env() -> std::io::Result<Option<Passphrase>> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        if !passphrase.is_empty() {
+            return Ok(Some(Zeroizing::new(passphrase)));
+        }
+    }
+    match std::env::var_os(KEYFILE_ENV).filter(|path| !path.is_empty()) {
+        Some(path) => {
+            let contents = Zeroizing::new(std::fs::read_to_string(PathBuf::from(path))?);
+            Ok(Some(Zeroizing::new(contents.trim_end_matches(['\r', '\n']).to_string())))
+        },
+        None => Ok(None),
+    }
+}
+
+/// Returns true if `data` is an encrypted task file
+#[must_use]
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// A key derived from a passphrase, kept so that the (deliberately slow) key
+/// derivation only runs once per salt
+#[derive(Clone)]
+pub struct DerivedKey {
+    salt: [u8; SALT_LEN],
+    params: [u32; 3],
+    key: Zeroizing<[u8; KEY_LEN]>,
+}
+
+fn derive(passphrase: &str, salt: [u8; SALT_LEN], params: [u32; 3]) -> std::io::Result<DerivedKey> {
+    let [m_cost, t_cost, p_cost] = params;
+    let argon2_params = Params::new(m_cost, t_cost, p_cost, Some(KEY_LEN))
+        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Bad key derivation parameters: {err}")))?;
+    let mut key = Zeroizing::new([0_u8; KEY_LEN]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, argon2_params)
+        .hash_password_into(passphrase.as_bytes(), &salt, key.as_mut())
+        .map_err(|err| std::io::Error::other(format!("Key derivation failed: {err}")))?;
+    Ok(DerivedKey { salt, params, key })
+}
+
+/// Encrypts `plain` with a key derived from `passphrase`.
+///
+/// The file is a header (magic, Argon2id parameters, salt and nonce)
+/// followed by the XChaCha20-Poly1305 ciphertext, which also authenticates
+/// the header.  The derived key is reused from `cache` if there is one, so a
+/// list keeps its salt until the passphrase changes; each write has a fresh
+/// random nonce.
+///
+/// # Errors
+///
+/// Will return `Err` if key derivation or encryption fails.
+pub fn encrypt(plain: &[u8], passphrase: &str, cache: &mut Option<DerivedKey>) -> std::io::Result<Vec<u8>> {
+    let key = if let Some(key) = cache {
+        &*key
+    } else {
+        let mut salt = [0_u8; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let defaults = Params::default();
+        &*cache.insert(derive(passphrase, salt, [defaults.m_cost(), defaults.t_cost(), defaults.p_cost()])?)
+    };
+    let mut nonce = [0_u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let mut data = Vec::with_capacity(HEADER_LEN + plain.len() + 16);
+    data.extend_from_slice(MAGIC);
+    for param in key.params {
+        data.extend_from_slice(&param.to_le_bytes());
+    }
+    data.extend_from_slice(&key.salt);
+    data.extend_from_slice(&nonce);
+    let ciphertext = XChaCha20Poly1305::new(key.key.as_ref().into())
+        .encrypt(XNonce::from_slice(&nonce), Payload { msg: plain, aad: &data })
+        .map_err(|_| std::io::Error::other("Encryption failed"))?;
+    data.extend_from_slice(&ciphertext);
+    Ok(data)
+}
+
+/// Decrypts a file written by ``encrypt``, deriving the key from `passphrase`
+/// unless `cache` already holds it.  On success, `cache` holds the key.
+///
+/// # Errors
+///
+/// Will return `Err` with an ``EncryptionError`` if there's no passphrase,
+/// or it's wrong, or any other error if the file isn't valid.
+pub fn decrypt(data: &[u8], passphrase: Option<&str>, cache: &mut Option<DerivedKey>) -> std::io::Result<Zeroizing<Vec<u8>>> {
+    if data.len() < HEADER_LEN || !is_encrypted(data) {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Not an encrypted task file"));
+    }
+    let (header, ciphertext) = data.split_at(HEADER_LEN);
+    let param = |index: usize| {
+        let start = MAGIC.len() + index * 4;
+        u32::from_le_bytes([header[start], header[start + 1], header[start + 2], header[start + 3]])
+    };
+    let params = [param(0), param(1), param(2)];
+    let mut salt = [0_u8; SALT_LEN];
+    salt.copy_from_slice(&header[MAGIC.len() + 12..MAGIC.len() + 12 + SALT_LEN]);
+    let nonce = &header[HEADER_LEN - NONCE_LEN..];
+    let reusable = cache.as_ref().is_some_and(|key| key.salt == salt && key.params == params);
+    let key = if reusable {
+        cache.take()
+    } else {
+        None
+    };
+    let key = match (key, passphrase) {
+        (Some(key), _) => key,
+        (None, Some(passphrase)) => derive(passphrase, salt, params)?,
+        (None, None) => return Err(EncryptionError::Locked.into()),
+    };
+    let plain = XChaCha20Poly1305::new(key.key.as_ref().into())
+        .decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad: header })
+        .map_err(|_| std::io::Error::from(EncryptionError::WrongPassphrase))?;
+    *cache = Some(key);
+    Ok(Zeroizing::new(plain))
+}
@@ -0,0 +1,264 @@
+use std::{fs::{create_dir_all, metadata, read, read_dir, rename}, path::{Path, PathBuf}, time::SystemTime};
+
+use chrono::Local;
+use dirs::config_local_dir;
+
+use crate::{atomicfile, decode_tasks, encode_tasks, encryption::{self, DerivedKey}, DecodedTasks, EncryptionError, Passphrase, Recovery, Task, TaskStore};
+
+const PATH : &str = "tasks.json";
+const BACKUP_PATH : &str = "tasks_backup.json";
+const CONFIG_DIR : &str = "task";
+
+/// Stores the task list as JSON in `tasks.json`, keeping the previous
+/// version in `tasks_backup.json`.
+///
+/// Files in an older format are migrated when loaded, and a copy of the
+/// original is kept as `tasks.v<version>.json`.
+///
+/// Saves are atomic: the new list is written to a temporary file, fsynced,
+/// verified by parsing it back, and only then renamed over `tasks.json`.  The
+/// backup is only rotated once the new file has been verified, so there is
+/// always a complete `tasks.json` on disk.
+///
+/// If `tasks.json` can't be loaded, ``recover`` moves it aside to a
+/// timestamped `tasks.corrupt-*.json` and falls back to the backup.
+///
+/// The modification time and size of `tasks.json` are noted whenever it is
+/// loaded or saved, so that changes made by other programs can be detected.
+///
+/// The list may be encrypted (see ``encryption``), in which case both files
+/// are encrypted, and a passphrase must be given with ``unlock`` to load it.
+pub struct JsonFileStore {
+    dir: PathBuf,
+    read_only: bool,
+    seen: Option<(SystemTime, u64)>,
+    encrypted: bool,
+    passphrase: Option<Passphrase>,
+    key: Option<DerivedKey>,
+}
+
+impl Default for JsonFileStore {
+    fn default() -> Self {
+        Self::new(Self::default_dir())
+    }
+}
+
+impl JsonFileStore {
+    #[must_use]
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into(), read_only: false, seen: None, encrypted: false, passphrase: None, key: None }
+    }
+
+    /// Creates a store which never writes to `dir`: older files aren't
+    /// migrated on disk, and saves fail with `PermissionDenied`.
+    #[must_use]
+    pub fn new_read_only(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into(), read_only: true, seen: None, encrypted: false, passphrase: None, key: None }
+    }
+
+    /// Returns the directory used to store tasks when none is specified
+    #[must_use]
+    pub fn default_dir() -> PathBuf {
+        let dir_path_buf = config_local_dir().unwrap_or_default();
+        dir_path_buf.join(Path::new(CONFIG_DIR))
+    }
+
+    /// Returns the directory holding the task files
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn save_path(&self) -> PathBuf {
+        self.dir.join(PATH)
+    }
+
+    fn backup_path(&self) -> PathBuf {
+        self.dir.join(BACKUP_PATH)
+    }
+
+    fn quarantine_path(&self) -> PathBuf {
+        self.dir.join(format!("tasks.corrupt-{}.json", Local::now().format("%Y%m%d-%H%M%S")))
+    }
+
+    fn pre_migration_path(&self, version: u32) -> PathBuf {
+        self.dir.join(format!("tasks.v{version}.json"))
+    }
+
+    /// Returns the modification time and size of `tasks.json`, or `None` if
+    /// it doesn't exist
+    fn stamp(&self) -> std::io::Result<Option<(SystemTime, u64)>> {
+        match metadata(self.save_path()) {
+            Ok(metadata) => Ok(Some((metadata.modified()?, metadata.len()))),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Loads the task file at `path`, decrypting it if necessary.
+    /// Returns the tasks, and whether the file was encrypted.
+    fn load_file(&mut self, path: &Path) -> std::io::Result<(DecodedTasks, bool)> {
+        let data = read(path)?;
+        if encryption::is_encrypted(&data) {
+            let plain = encryption::decrypt(&data, self.passphrase.as_deref().map(String::as_str), &mut self.key)?;
+            Ok((decode_tasks(&plain)?, true))
+        } else {
+            Ok((decode_tasks(&data)?, false))
+        }
+    }
+
+    /// Returns `tasks` as they are to be written to the task file
+    fn encode(&mut self, tasks: &[Task]) -> std::io::Result<Vec<u8>> {
+        let serialized = encode_tasks(tasks)?;
+        if self.encrypted {
+            let passphrase = self.passphrase.as_ref().ok_or(EncryptionError::Locked)?;
+            encryption::encrypt(serialized.as_bytes(), passphrase, &mut self.key)
+        } else {
+            Ok(serialized.into_bytes())
+        }
+    }
+}
+
+impl TaskStore for JsonFileStore {
+    /// Loads `tasks.json`.  If neither it nor the backup exist, this is a
+    /// fresh start, and the list is empty.
+    /// The file is only noted as seen once it has loaded, so that if it's
+    /// invalid, the change is still seen as external, and isn't overwritten.
+    fn load(&mut self) -> std::io::Result<Vec<Task>> {
+        let stamp = self.stamp()?;
+        if !self.save_path().exists() && !self.backup_path().exists() {
+            self.seen = stamp;
+            return Ok(vec![]);
+        }
+        let (decoded, encrypted) = self.load_file(&self.save_path())?;
+        self.seen = stamp;
+        self.encrypted = encrypted;
+        if decoded.migrated() && !self.read_only {
+            let pre_migration_path = self.pre_migration_path(decoded.from_version);
+            if !pre_migration_path.exists() {
+                atomicfile::copy_atomic(&self.save_path(), &pre_migration_path)?;
+            }
+            self.save(&decoded.tasks)?;
+        }
+        Ok(decoded.tasks)
+    }
+
+    /// Moves the unreadable `tasks.json` aside, and loads the backup in its place.
+    /// Files written by a newer version of the application, and encrypted
+    /// files we don't have the passphrase for, are left untouched.
+    fn recover(&mut self, error: &std::io::Error) -> std::io::Result<Recovery> {
+        if self.read_only || error.kind() == std::io::ErrorKind::Unsupported || EncryptionError::of(error).is_some() {
+            return Err(std::io::Error::new(error.kind(), error.to_string()));
+        }
+        let save_path = self.save_path();
+        let problem = if save_path.exists() {
+            let quarantine_path = self.quarantine_path();
+            rename(&save_path, &quarantine_path)?;
+            format!("{PATH} couldn't be loaded ({error}).\nIt has been moved to {}.\n\n", quarantine_path.display())
+        } else {
+            format!("{PATH} is missing.\n\n")
+        };
+        let (tasks, outcome) = match self.load_file(&self.backup_path()) {
+            Ok((DecodedTasks { tasks, .. }, _)) => {
+                let outcome = format!("Recovered {} tasks from {BACKUP_PATH}.", tasks.len());
+                (tasks, outcome)
+            },
+            Err(backup_error) => (vec![], format!(
+                "{BACKUP_PATH} couldn't be loaded either ({backup_error}).\nStarting with an empty task list."
+            )),
+        };
+        let report = problem + &outcome;
+        Ok(Recovery { tasks, report })
+    }
+
+    /// Refuses to overwrite changes made by something else which haven't been
+    /// loaded yet, e.g. because they aren't valid.
+    fn save(&mut self, tasks: &[Task]) -> std::io::Result<()> {
+        if self.read_only {
+            return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "Task store is read-only"));
+        }
+        if self.changed_externally()? {
+            return Err(std::io::Error::other(format!("{PATH} has been changed by another program since it was loaded")));
+        }
+        let encoded = self.encode(tasks)?;
+        create_dir_all(&self.dir)?;
+        atomicfile::write_atomic(&self.save_path(), Some(&self.backup_path()), &encoded, |written| {
+            let decoded = if encryption::is_encrypted(written) {
+                decode_tasks(&encryption::decrypt(written, None, &mut self.key.clone())?)?
+            } else {
+                decode_tasks(written)?
+            };
+            if decoded.tasks.len() == tasks.len() {
+                Ok(())
+            } else {
+                Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Saved task count doesn't match"))
+            }
+        })?;
+        self.seen = self.stamp()?;
+        Ok(())
+    }
+
+    fn unlock(&mut self, passphrase: Passphrase) -> std::io::Result<()> {
+        self.passphrase = Some(passphrase);
+        Ok(())
+    }
+
+    fn is_encrypted(&self) -> bool {
+        self.encrypted
+    }
+
+    fn task_files(&self) -> Vec<PathBuf> {
+        vec![self.save_path()]
+    }
+
+    fn supports_encryption(&self) -> bool {
+        true
+    }
+
+    /// Returns the copies kept from before migrations, and the unreadable
+    /// files moved aside, which aren't encrypted unless the list was then
+    fn unencrypted_copies(&self) -> std::io::Result<Vec<PathBuf>> {
+        let entries = match read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(err) => return Err(err),
+        };
+        let mut copies = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            let is_copy = path.file_name().and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".json"))
+                .is_some_and(|stem| stem.starts_with("tasks.corrupt-")
+                    || stem.strip_prefix("tasks.v").is_some_and(|version| version.parse::<u32>().is_ok()));
+            if is_copy && read(&path).is_ok_and(|data| !encryption::is_encrypted(&data)) {
+                copies.push(path);
+            }
+        }
+        copies.sort();
+        Ok(copies)
+    }
+
+    /// Rewrites `tasks.json`, then copies it over the backup, so that both are
+    /// encrypted with the new passphrase (or neither is).
+    fn set_encryption(&mut self, tasks: &[Task], passphrase: Option<Passphrase>) -> std::io::Result<()> {
+        if self.read_only {
+            return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "Task store is read-only"));
+        }
+        let previous = (self.encrypted, self.passphrase.take(), self.key.take());
+        self.encrypted = passphrase.is_some();
+        self.passphrase = passphrase;
+        if let Err(err) = self.save(tasks) {
+            (self.encrypted, self.passphrase, self.key) = previous;
+            return Err(err);
+        }
+        atomicfile::copy_atomic(&self.save_path(), &self.backup_path())
+    }
+
+    /// Checks whether `tasks.json` has been replaced or modified since we last
+    /// loaded or saved it.  A missing file isn't a change, as there is nothing
+    /// to merge.
+    fn changed_externally(&mut self) -> std::io::Result<bool> {
+        let stamp = self.stamp()?;
+        Ok(stamp.is_some() && stamp != self.seen)
+    }
+}
@@ -0,0 +1,33 @@
+use crate::{Task, TaskStore};
+
+/// Keeps the task list in memory only.  Useful for tests, and for embedding
+/// the task engine in tools that shouldn't touch the user's real task files.
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    tasks: Vec<Task>,
+}
+
+impl MemoryStore {
+    /// Creates a store which will initially load `tasks`
+    #[must_use]
+    pub fn new(tasks: Vec<Task>) -> Self {
+        Self { tasks }
+    }
+
+    /// Returns the tasks as last saved
+    #[must_use]
+    pub fn tasks(&self) -> &[Task] {
+        &self.tasks
+    }
+}
+
+impl TaskStore for MemoryStore {
+    fn load(&mut self) -> std::io::Result<Vec<Task>> {
+        Ok(self.tasks.clone())
+    }
+
+    fn save(&mut self, tasks: &[Task]) -> std::io::Result<()> {
+        self.tasks = tasks.to_vec();
+        Ok(())
+    }
+}
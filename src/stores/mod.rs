@@ -0,0 +1,24 @@
+pub mod taskstore;
+pub use taskstore::*;
+
+pub mod atomicfile;
+
+pub mod encryption;
+pub use encryption::{passphrase_from_env, EncryptionError, Passphrase};
+
+pub mod jsonfilestore;
+pub use jsonfilestore::*;
+
+pub mod memorystore;
+pub use memorystore::*;
+
+pub mod replicastore;
+pub use replicastore::*;
+
+pub mod remotestore;
+pub use remotestore::*;
+
+#[cfg(feature = "sqlite")]
+pub mod sqlitestore;
+#[cfg(feature = "sqlite")]
+pub use sqlitestore::*;
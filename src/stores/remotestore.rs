@@ -0,0 +1,77 @@
+use crate::{http::{self, VERSION_HEADER}, Task, TaskStore};
+
+/// Stores the task list on a ``Server``, so that it can be shared with
+/// the server's other clients.
+///
+/// Saves send the whole list, which the server applies as individual
+/// changes.  The version of the list last loaded or saved is noted, so that
+/// changes made by other clients can be detected and merged, and so that a
+/// save based on an older version is refused by the server rather than
+/// undoing them.
+pub struct RemoteStore {
+    addr: String,
+    token: String,
+    version: Option<u64>,
+}
+
+impl RemoteStore {
+    /// Creates a store using the server at `addr`, e.g. `127.0.0.1:7420`,
+    /// sending it `token` (see ``read_server_token``)
+    #[must_use]
+    pub fn new(addr: impl Into<String>, token: impl Into<String>) -> Self {
+        Self { addr: addr.into(), token: token.into(), version: None }
+    }
+
+    #[must_use]
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    /// Makes a request, based on `version` of the list if given, returning
+    /// the body and the list's version from the response, or the server's
+    /// error
+    fn request(&self, method: &str, path: &str, version: Option<u64>, body: Option<&[u8]>)
+            -> std::io::Result<(Vec<u8>, Option<u64>)> {
+        let (status, response) = http::request(&self.addr, &self.token, method, path, version, body)?;
+        if (200..300).contains(&status) {
+            let version = response.header(VERSION_HEADER).and_then(|version| version.parse().ok());
+            return Ok((response.body, version));
+        }
+        let message = serde_json::from_slice::<serde_json::Value>(&response.body).ok()
+            .and_then(|error| error["error"].as_str().map(ToString::to_string))
+            .unwrap_or_else(|| format!("Server returned status {status}"));
+        let kind = match status {
+            401 | 403 => std::io::ErrorKind::PermissionDenied,
+            404 => std::io::ErrorKind::NotFound,
+            400 | 415 => std::io::ErrorKind::InvalidInput,
+            _ => std::io::ErrorKind::Other,
+        };
+        Err(std::io::Error::new(kind, message))
+    }
+}
+
+impl TaskStore for RemoteStore {
+    fn load(&mut self) -> std::io::Result<Vec<Task>> {
+        let (body, version) = self.request("GET", "/tasks", None, None)?;
+        let tasks = serde_json::from_slice(&body)?;
+        self.version = version;
+        Ok(tasks)
+    }
+
+    /// Sends the list, as edited from the version last loaded or saved.  If
+    /// the server's list has changed since, the save is refused, and the
+    /// changes are to be merged in (see ``TaskList::merge_external_changes``)
+    /// before saving again.
+    fn save(&mut self, tasks: &[Task]) -> std::io::Result<()> {
+        let (_, version) = self.request("PUT", "/tasks", self.version, Some(&serde_json::to_vec(tasks)?))?;
+        self.version = version;
+        Ok(())
+    }
+
+    /// Checks whether the server's version of the list has moved on since we
+    /// last loaded or saved it
+    fn changed_externally(&mut self) -> std::io::Result<bool> {
+        let (_, version) = self.request("GET", "/version", None, None)?;
+        Ok(version != self.version)
+    }
+}
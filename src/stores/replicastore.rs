@@ -0,0 +1,171 @@
+use std::{collections::BTreeMap, fs::{create_dir_all, metadata, read_dir, read_to_string, write}, path::{Path, PathBuf}, time::SystemTime};
+
+use uuid::Uuid;
+
+use crate::{EncryptionError, Replica, Task, TaskStore};
+
+const REPLICAS_DIR : &str = "replicas";
+const REPLICA_ID_PATH : &str = "replica-id";
+const REPLICA_SUFFIX : &str = ".json";
+
+/// Stores the task list as one ``Replica`` among several, e.g. one per
+/// machine, so that each can be edited offline and they converge when they
+/// next see each other's changes.
+///
+/// Each replica writes its state only to its own file, `replicas/<id>.json`,
+/// and merges in every other file it finds there, so the `replicas`
+/// directory can be kept in step by a folder syncing tool without ever
+/// conflicting.  The replica's id is kept in `replica-id`, which mustn't be
+/// synced.
+///
+/// Deleted tasks are kept in the replica files, so that they stay deleted.
+///
+/// Until it has saved a replica file of its own, the replica starts from
+/// the list as stored before it was replicated, if it's given that store
+/// with ``seeded_from``.  Encrypted lists can't be replicated.
+pub struct ReplicaStore {
+    dir: PathBuf,
+    read_only: bool,
+    replica: Option<Replica>,
+    seen: BTreeMap<PathBuf, (SystemTime, u64)>,
+    seed: Option<Box<dyn TaskStore>>,
+}
+
+impl ReplicaStore {
+    #[must_use]
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into(), read_only: false, replica: None, seen: BTreeMap::new(), seed: None }
+    }
+
+    /// Starts the replica from the list in `store`, e.g. the one the list was
+    /// kept in before it was replicated, until it has saved a file of its own
+    #[must_use]
+    pub fn seeded_from(self, store: Box<dyn TaskStore>) -> Self {
+        Self { seed: Some(store), ..self }
+    }
+
+    /// Creates a store which never writes to `dir`, and whose saves fail with
+    /// `PermissionDenied`
+    #[must_use]
+    pub fn new_read_only(dir: impl Into<PathBuf>) -> Self {
+        Self { read_only: true, ..Self::new(dir) }
+    }
+
+    fn replicas_dir(&self) -> PathBuf {
+        self.dir.join(REPLICAS_DIR)
+    }
+
+    fn replica_path(&self, id: Uuid) -> PathBuf {
+        self.replicas_dir().join(format!("{id}{REPLICA_SUFFIX}"))
+    }
+
+    /// Returns this replica's id, choosing one the first time
+    fn replica_id(&self) -> std::io::Result<Uuid> {
+        let path = self.dir.join(REPLICA_ID_PATH);
+        match read_to_string(&path) {
+            Ok(id) => Uuid::parse_str(id.trim())
+                .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, format!("Bad {REPLICA_ID_PATH}: {err}"))),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                let id = Uuid::new_v4();
+                if !self.read_only {
+                    create_dir_all(&self.dir)?;
+                    write(&path, id.to_string())?;
+                }
+                Ok(id)
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Returns the modification time and size of every replica file
+    fn stamps(&self) -> std::io::Result<BTreeMap<PathBuf, (SystemTime, u64)>> {
+        let entries = match read_dir(self.replicas_dir()) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(err) => return Err(err),
+        };
+        let mut stamps = BTreeMap::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.to_string_lossy().ends_with(REPLICA_SUFFIX) {
+                let metadata = metadata(&path)?;
+                stamps.insert(path, (metadata.modified()?, metadata.len()));
+            }
+        }
+        Ok(stamps)
+    }
+
+    fn note_seen(&mut self, path: &Path) -> std::io::Result<()> {
+        let metadata = metadata(path)?;
+        self.seen.insert(path.to_path_buf(), (metadata.modified()?, metadata.len()));
+        Ok(())
+    }
+}
+
+/// Loads the list to start a replica from, refusing if it's encrypted, as
+/// replicas aren't
+fn load_seed(seed: &mut dyn TaskStore) -> std::io::Result<Vec<Task>> {
+    let refused = || std::io::Error::new(std::io::ErrorKind::Unsupported,
+        "This list is encrypted, and encrypted lists can't be replicated");
+    match seed.load() {
+        Ok(_) if seed.is_encrypted() => Err(refused()),
+        Err(err) if EncryptionError::of(&err).is_some() => Err(refused()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        loaded => loaded,
+    }
+}
+
+impl TaskStore for ReplicaStore {
+    /// Loads our replica, or starts it from the seed, and merges in all the
+    /// others
+    fn load(&mut self) -> std::io::Result<Vec<Task>> {
+        let id = self.replica_id()?;
+        let own_path = self.replica_path(id);
+        let stamps = self.stamps()?;
+        let mut replica = if own_path.exists() {
+            Replica::load(&own_path)?
+        } else {
+            let mut replica = Replica::new(id);
+            if let Some(seed) = &mut self.seed {
+                replica.update(&load_seed(seed.as_mut())?);
+            }
+            replica
+        };
+        for path in stamps.keys().filter(|path| **path != own_path) {
+            replica.merge_file(path)?;
+        }
+        let tasks = replica.tasks();
+        self.replica = Some(replica);
+        self.seen = stamps;
+        Ok(tasks)
+    }
+
+    fn save(&mut self, tasks: &[Task]) -> std::io::Result<()> {
+        if self.read_only {
+            return Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "Task store is read-only"));
+        }
+        if self.replica.is_none() {
+            self.load()?;
+        }
+        let replicas_dir = self.replicas_dir();
+        let Some(replica) = &mut self.replica else {
+            return Ok(());
+        };
+        replica.update(tasks);
+        create_dir_all(&replicas_dir)?;
+        let own_path = replicas_dir.join(format!("{}{REPLICA_SUFFIX}", replica.id()));
+        replica.save(&own_path)?;
+        self.note_seen(&own_path)
+    }
+
+    /// Our own replica file: the others are other machines' business
+    fn task_files(&self) -> Vec<PathBuf> {
+        self.replica.as_ref().map(|replica| self.replica_path(replica.id())).into_iter().collect()
+    }
+
+    /// Checks whether any replica file has appeared, or changed, since we last
+    /// loaded or saved
+    fn changed_externally(&mut self) -> std::io::Result<bool> {
+        Ok(self.stamps()? != self.seen)
+    }
+}
@@ -0,0 +1,194 @@
+use std::path::{Path, PathBuf};
+
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension, TransactionBehavior};
+use uuid::Uuid;
+
+use crate::{JsonFileStore, Task, TaskStore};
+
+const DB_PATH : &str = "tasks.db";
+
+/// Stores the task list in an `SQLite` database, one row per task.
+///
+/// List order is kept in a `position` column.  Positions only need to be
+/// increasing, not contiguous, so adding a task or moving one to the bottom
+/// writes a single row, and removing one just deletes it.
+///
+/// Changes committed by other connections to the database are detected with
+/// `PRAGMA data_version`, which only changes for commits made elsewhere.
+pub struct SqliteStore {
+    dir: PathBuf,
+    conn: Connection,
+    data_version: i64,
+}
+
+fn to_io_error(err: rusqlite::Error) -> std::io::Error {
+    std::io::Error::other(err)
+}
+
+impl SqliteStore {
+    /// Opens (creating if necessary) the database in `dir`.
+    /// If the database is new, and there's a `tasks.json` in `dir`, its tasks
+    /// are imported.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the database can't be opened or created, or if
+    /// an existing `tasks.json` can't be imported.
+    pub fn open(dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        let conn = Connection::open(dir.join(DB_PATH)).map_err(to_io_error)?;
+        let mut store = Self { dir, conn, data_version: 0 };
+        store.create_schema()?;
+        store.data_version = store.data_version()?;
+        Ok(store)
+    }
+
+    /// Opens the existing database in `dir` for reading only
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the database doesn't exist or can't be opened.
+    pub fn open_read_only(dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        let dir = dir.into();
+        let conn = Connection::open_with_flags(dir.join(DB_PATH), OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .map_err(to_io_error)?;
+        let mut store = Self { dir, conn, data_version: 0 };
+        store.data_version = store.data_version()?;
+        Ok(store)
+    }
+
+    /// Returns the directory holding the database
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Creates the tables if they don't exist, importing the tasks from an
+    /// existing `tasks.json`, if there is one.  Both are done in one
+    /// transaction, so if the import fails, the database is still new next
+    /// time, and the import is tried again.
+    fn create_schema(&mut self) -> std::io::Result<()> {
+        let transaction = self.conn.transaction_with_behavior(TransactionBehavior::Immediate)
+            .map_err(to_io_error)?;
+        let exists: Option<String> = transaction.query_row(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'tasks'",
+            [],
+            |row| row.get(0),
+        ).optional().map_err(to_io_error)?;
+        if exists.is_some() {
+            return Ok(());
+        }
+        transaction.execute_batch(
+            "CREATE TABLE tasks (
+                uuid TEXT PRIMARY KEY NOT NULL,
+                position INTEGER NOT NULL,
+                data TEXT NOT NULL
+            );
+            CREATE INDEX tasks_position ON tasks (position);"
+        ).map_err(to_io_error)?;
+        match JsonFileStore::new(&self.dir).load() {
+            Ok(tasks) => Self::insert_all(&transaction, &tasks)?,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err),
+        }
+        transaction.commit().map_err(to_io_error)
+    }
+
+    /// Inserts `tasks` into the (empty) table, in order
+    fn insert_all(conn: &Connection, tasks: &[Task]) -> std::io::Result<()> {
+        let mut insert = conn.prepare(
+            "INSERT INTO tasks (uuid, position, data) VALUES (?1, ?2, ?3)"
+        ).map_err(to_io_error)?;
+        for (position, task) in (0_i64..).zip(tasks) {
+            insert.execute(params![task.uuid().to_string(), position, serde_json::to_string(task)?])
+                .map_err(to_io_error)?;
+        }
+        Ok(())
+    }
+
+    fn data_version(&self) -> std::io::Result<i64> {
+        self.conn.query_row("PRAGMA data_version", [], |row| row.get(0)).map_err(to_io_error)
+    }
+
+    fn next_position(conn: &Connection) -> std::io::Result<i64> {
+        conn.query_row("SELECT IFNULL(MAX(position), -1) + 1 FROM tasks", [], |row| row.get(0))
+            .map_err(to_io_error)
+    }
+
+    fn find(tasks: &[Task], uuid: Uuid) -> std::io::Result<&Task> {
+        tasks.iter().find(|t| t.uuid() == uuid).ok_or_else(|| std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("Task {uuid} not in list"),
+        ))
+    }
+}
+
+impl TaskStore for SqliteStore {
+    fn load(&mut self) -> std::io::Result<Vec<Task>> {
+        let data_version = self.data_version()?;
+        let mut statement = self.conn.prepare("SELECT data FROM tasks ORDER BY position")
+            .map_err(to_io_error)?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0)).map_err(to_io_error)?;
+        let mut tasks = vec![];
+        for data in rows {
+            tasks.push(serde_json::from_str(&data.map_err(to_io_error)?)?);
+        }
+        // Only once loaded, so that a change we couldn't load is still seen
+        self.data_version = data_version;
+        Ok(tasks)
+    }
+
+    fn changed_externally(&mut self) -> std::io::Result<bool> {
+        Ok(self.data_version()? != self.data_version)
+    }
+
+    fn task_files(&self) -> Vec<PathBuf> {
+        vec![self.dir.join(DB_PATH)]
+    }
+
+    fn save(&mut self, tasks: &[Task]) -> std::io::Result<()> {
+        let transaction = self.conn.transaction().map_err(to_io_error)?;
+        transaction.execute("DELETE FROM tasks", []).map_err(to_io_error)?;
+        Self::insert_all(&transaction, tasks)?;
+        transaction.commit().map_err(to_io_error)
+    }
+
+    fn task_added(&mut self, tasks: &[Task], uuid: Uuid) -> std::io::Result<()> {
+        let task = Self::find(tasks, uuid)?;
+        let position = Self::next_position(&self.conn)?;
+        self.conn.execute(
+            "INSERT INTO tasks (uuid, position, data) VALUES (?1, ?2, ?3)",
+            params![uuid.to_string(), position, serde_json::to_string(task)?],
+        ).map_err(to_io_error)?;
+        Ok(())
+    }
+
+    fn task_replaced(&mut self, tasks: &[Task], uuid: Uuid) -> std::io::Result<()> {
+        let task = Self::find(tasks, uuid)?;
+        self.conn.execute(
+            "UPDATE tasks SET data = ?2 WHERE uuid = ?1",
+            params![uuid.to_string(), serde_json::to_string(task)?],
+        ).map_err(to_io_error)?;
+        Ok(())
+    }
+
+    fn task_removed(&mut self, _tasks: &[Task], uuid: Uuid) -> std::io::Result<()> {
+        self.conn.execute("DELETE FROM tasks WHERE uuid = ?1", params![uuid.to_string()])
+            .map_err(to_io_error)?;
+        Ok(())
+    }
+
+    fn tasks_moved_to_bottom(&mut self, tasks: &[Task], uuids: &[Uuid]) -> std::io::Result<()> {
+        let transaction = self.conn.transaction().map_err(to_io_error)?;
+        for uuid in uuids {
+            let task = Self::find(tasks, *uuid)?;
+            let position = Self::next_position(&transaction)?;
+            transaction.execute(
+                "UPDATE tasks SET position = ?2, data = ?3 WHERE uuid = ?1",
+                params![uuid.to_string(), position, serde_json::to_string(task)?],
+            ).map_err(to_io_error)?;
+        }
+        transaction.commit().map_err(to_io_error)
+    }
+}
@@ -0,0 +1,250 @@
+use std::path::Path;
+
+use uuid::Uuid;
+
+use crate::{Passphrase, Task};
+
+/// The result of recovering from a failed load
+pub struct Recovery {
+    /// The tasks that could be recovered, which may be none
+    pub tasks: Vec<Task>,
+    /// A description of what went wrong, and what was done about it, for the user
+    pub report: String,
+}
+
+/// Persistent storage for the tasks held by a ``TaskList``.
+///
+/// Implementations must be able to load and save the whole list.  The
+/// incremental hooks are called by ``TaskList`` after each mutation, with the
+/// list as it now stands, and by default simply save the whole list; stores
+/// that can write individual tasks should override them.
+pub trait TaskStore {
+    /// Loads every task, in list order.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the storage can't be read, or doesn't contain valid data.
+    fn load(&mut self) -> std::io::Result<Vec<Task>>;
+
+    /// Called when `load` has failed with `error`.  Attempts to recover the list
+    /// from whatever else the store has available, and moves any damaged data
+    /// out of the way, so that it can't be overwritten by the next save.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if recovery isn't possible.  This is the default.
+    fn recover(&mut self, error: &std::io::Error) -> std::io::Result<Recovery> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("Couldn't load tasks ({error}), and this store has no backup to recover from"),
+        ))
+    }
+
+    /// Returns whether the stored list has been changed by something other than
+    /// this store since it was last loaded or saved, e.g. by another program
+    /// editing the file.  By default, stores can't tell, and return false.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the storage can't be checked.
+    fn changed_externally(&mut self) -> std::io::Result<bool> {
+        Ok(false)
+    }
+
+    /// Gives the store the passphrase for an encrypted list, after `load` has
+    /// failed with ``EncryptionError::Locked``.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` with kind `Unsupported` if the store doesn't support
+    /// encryption.  This is the default.
+    fn unlock(&mut self, _passphrase: Passphrase) -> std::io::Result<()> {
+        Err(unsupported_encryption())
+    }
+
+    /// Returns true if the list is stored encrypted
+    fn is_encrypted(&self) -> bool {
+        false
+    }
+
+    /// Returns the files the list is stored in, e.g. to commit them to git.
+    /// By default, there are none.
+    fn task_files(&self) -> Vec<std::path::PathBuf> {
+        vec![]
+    }
+
+    /// Returns true if the store can encrypt the list.  By default, it can't.
+    fn supports_encryption(&self) -> bool {
+        false
+    }
+
+    /// Returns the files the store has kept which hold the list unencrypted,
+    /// other than the list itself, e.g. copies from before a migration, to be
+    /// deleted once it's encrypted.  By default, there are none.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the storage can't be checked.
+    fn unencrypted_copies(&self) -> std::io::Result<Vec<std::path::PathBuf>> {
+        Ok(vec![])
+    }
+
+    /// Rewrites the stored list, `tasks`, encrypted with `passphrase`, or
+    /// unencrypted if `None`.  Used to enable or disable encryption, or change
+    /// the passphrase.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the write to storage fails, or with kind
+    /// `Unsupported` if the store doesn't support encryption.  This is the
+    /// default.
+    fn set_encryption(&mut self, _tasks: &[Task], _passphrase: Option<Passphrase>) -> std::io::Result<()> {
+        Err(unsupported_encryption())
+    }
+
+    /// Replaces the stored list with `tasks`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the write to storage fails.
+    fn save(&mut self, tasks: &[Task]) -> std::io::Result<()>;
+
+    /// Called after the task `uuid` has been added to the bottom of `tasks`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the write to storage fails.
+    fn task_added(&mut self, tasks: &[Task], _uuid: Uuid) -> std::io::Result<()> {
+        self.save(tasks)
+    }
+
+    /// Called after the task `uuid` has been replaced in place in `tasks`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the write to storage fails.
+    fn task_replaced(&mut self, tasks: &[Task], _uuid: Uuid) -> std::io::Result<()> {
+        self.save(tasks)
+    }
+
+    /// Called after the task `uuid` has been removed from `tasks`.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the write to storage fails.
+    fn task_removed(&mut self, tasks: &[Task], _uuid: Uuid) -> std::io::Result<()> {
+        self.save(tasks)
+    }
+
+    /// Called after the tasks in `uuids` have been (possibly altered and) moved to
+    /// the bottom of `tasks`, in that order.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the write to storage fails.
+    fn tasks_moved_to_bottom(&mut self, tasks: &[Task], _uuids: &[Uuid]) -> std::io::Result<()> {
+        self.save(tasks)
+    }
+}
+
+fn unsupported_encryption() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Unsupported, "This task store doesn't support encryption")
+}
+
+impl<S: TaskStore + ?Sized> TaskStore for Box<S> {
+    fn load(&mut self) -> std::io::Result<Vec<Task>> {
+        (**self).load()
+    }
+
+    fn recover(&mut self, error: &std::io::Error) -> std::io::Result<Recovery> {
+        (**self).recover(error)
+    }
+
+    fn changed_externally(&mut self) -> std::io::Result<bool> {
+        (**self).changed_externally()
+    }
+
+    fn unlock(&mut self, passphrase: Passphrase) -> std::io::Result<()> {
+        (**self).unlock(passphrase)
+    }
+
+    fn is_encrypted(&self) -> bool {
+        (**self).is_encrypted()
+    }
+
+    fn task_files(&self) -> Vec<std::path::PathBuf> {
+        (**self).task_files()
+    }
+
+    fn supports_encryption(&self) -> bool {
+        (**self).supports_encryption()
+    }
+
+    fn unencrypted_copies(&self) -> std::io::Result<Vec<std::path::PathBuf>> {
+        (**self).unencrypted_copies()
+    }
+
+    fn set_encryption(&mut self, tasks: &[Task], passphrase: Option<Passphrase>) -> std::io::Result<()> {
+        (**self).set_encryption(tasks, passphrase)
+    }
+
+    fn save(&mut self, tasks: &[Task]) -> std::io::Result<()> {
+        (**self).save(tasks)
+    }
+
+    fn task_added(&mut self, tasks: &[Task], uuid: Uuid) -> std::io::Result<()> {
+        (**self).task_added(tasks, uuid)
+    }
+
+    fn task_replaced(&mut self, tasks: &[Task], uuid: Uuid) -> std::io::Result<()> {
+        (**self).task_replaced(tasks, uuid)
+    }
+
+    fn task_removed(&mut self, tasks: &[Task], uuid: Uuid) -> std::io::Result<()> {
+        (**self).task_removed(tasks, uuid)
+    }
+
+    fn tasks_moved_to_bottom(&mut self, tasks: &[Task], uuids: &[Uuid]) -> std::io::Result<()> {
+        (**self).tasks_moved_to_bottom(tasks, uuids)
+    }
+}
+
+/// Opens the store used for the task files in `dir`: a ``ReplicaStore`` if
+/// `replicated`, otherwise an ``SqliteStore`` when built with the `sqlite`
+/// feature, otherwise a ``JsonFileStore``.  A new replica starts from the
+/// list as stored before.
+/// If `read_only`, the store will never write to `dir`.
+///
+/// # Errors
+///
+/// Will return `Err` if the store can't be opened.
+pub fn open_store(dir: &Path, read_only: bool, replicated: bool) -> std::io::Result<Box<dyn TaskStore>> {
+    if replicated {
+        let store = if read_only {
+            crate::ReplicaStore::new_read_only(dir)
+        } else {
+            crate::ReplicaStore::new(dir)
+        };
+        // There's nothing to start from if the list was never stored otherwise
+        return Ok(Box::new(match open_store(dir, true, false) {
+            Ok(seed) => store.seeded_from(seed),
+            Err(_) => store,
+        }));
+    }
+    #[cfg(feature = "sqlite")]
+    {
+        if read_only {
+            Ok(Box::new(crate::SqliteStore::open_read_only(dir)?))
+        } else {
+            Ok(Box::new(crate::SqliteStore::open(dir)?))
+        }
+    }
+    #[cfg(not(feature = "sqlite"))]
+    {
+        if read_only {
+            Ok(Box::new(crate::JsonFileStore::new_read_only(dir)))
+        } else {
+            Ok(Box::new(crate::JsonFileStore::new(dir)))
+        }
+    }
+}
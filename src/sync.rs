@@ -0,0 +1,178 @@
+use std::{fs::read, path::Path};
+
+use crate::{atomicfile, decode_tasks, encode_tasks, encryption::{self, DerivedKey}, merge, MergeResult, Passphrase, Task};
+
+/// Reads the tasks from a task file, decrypting it with `passphrase` if it's
+/// encrypted.  Returns the tasks, and whether the file was encrypted.
+fn read_task_file(path: &Path, passphrase: Option<&Passphrase>, key: &mut Option<DerivedKey>) -> std::io::Result<(Vec<Task>, bool)> {
+    let data = read(path).map_err(|err| std::io::Error::new(err.kind(), format!("{}: {err}", path.display())))?;
+    if encryption::is_encrypted(&data) {
+        let plain = encryption::decrypt(&data, passphrase.map(|passphrase| passphrase.as_str()), key)?;
+        Ok((decode_tasks(&plain)?.tasks, true))
+    } else {
+        Ok((decode_tasks(&data)?.tasks, false))
+    }
+}
+
+/// Merges two copies of a task file that have diverged from a common
+/// `ancestor`, e.g. `tasks.json` changed on two machines since they last
+/// synced, writing the result to `output` (which may be one of the copies).
+///
+/// The merge is as for ``merge``, with `ours` taking precedence in
+/// conflicts, which are returned for reporting.  If there is no ancestor yet,
+/// it's taken to be empty.  The merged file is also written to `ancestor`,
+/// as the ancestor for the next sync.
+///
+/// Encrypted files are decrypted with `passphrase`, and the output is
+/// encrypted with it if either copy was encrypted.  The output, then the
+/// ancestor, are written atomically.
+///
+/// # Errors
+///
+/// Will return `Err` if a file can't be read or decrypted, or the output can't
+/// be written.
+pub fn sync_files(ancestor: &Path, ours: &Path, theirs: &Path, output: &Path, passphrase: Option<&Passphrase>)
+        -> std::io::Result<MergeResult> {
+    let base = if ancestor.exists() {
+        read_task_file(ancestor, passphrase, &mut None)?.0
+    } else {
+        vec![]
+    };
+    // Keep our key, so the output keeps our salt
+    let mut key = None;
+    let (our_tasks, ours_encrypted) = read_task_file(ours, passphrase, &mut key)?;
+    let (their_tasks, theirs_encrypted) = read_task_file(theirs, passphrase, &mut None)?;
+    let merged = merge(&base, &our_tasks, &their_tasks);
+
+    let serialized = encode_tasks(&merged.tasks)?;
+    let contents = match passphrase {
+        Some(passphrase) if ours_encrypted || theirs_encrypted =>
+            encryption::encrypt(serialized.as_bytes(), passphrase, &mut key)?,
+        _ => serialized.into_bytes(),
+    };
+    let verify = |written: &[u8]| {
+        let decoded = if encryption::is_encrypted(written) {
+            decode_tasks(&encryption::decrypt(written, None, &mut key.clone())?)?
+        } else {
+            decode_tasks(written)?
+        };
+        if decoded.tasks.len() == merged.tasks.len() {
+            Ok(())
+        } else {
+            Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Saved task count doesn't match"))
+        }
+    };
+    atomicfile::write_atomic(output, None, &contents, verify)?;
+    // Only once the output is safely written, or a failed sync could lose
+    // changes made since the last one
+    atomicfile::write_atomic(ancestor, None, &contents, verify)?;
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::{create_dir, remove_dir_all, write}, path::PathBuf};
+
+    use uuid::Uuid;
+    use zeroize::Zeroizing;
+
+    use super::*;
+
+    // A directory of task files, removed when dropped
+    struct TestDir(PathBuf);
+
+    impl TestDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("task-sync-test-{}", Uuid::new_v4()));
+            create_dir(&dir).expect("creating the test directory");
+            Self(dir)
+        }
+
+        fn write(&self, name: &str, tasks: &[Task]) -> PathBuf {
+            let path = self.0.join(name);
+            write(&path, encode_tasks(tasks).expect("encoding tasks")).expect("writing tasks");
+            path
+        }
+    }
+
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = remove_dir_all(&self.0);
+        }
+    }
+
+    fn descriptions(path: &Path, passphrase: Option<&Passphrase>) -> Vec<String> {
+        let (tasks, _) = read_task_file(path, passphrase, &mut None).expect("reading the output");
+        tasks.iter().map(|task| task.description().to_string()).collect()
+    }
+
+    #[test]
+    fn merges_diverged_copies_into_the_output() {
+        let dir = TestDir::new();
+        let base = vec![Task::new("one"), Task::new("two")];
+        let ancestor = dir.write("ancestor.json", &base);
+        let mut ours = base.clone();
+        ours.push(Task::new("ours"));
+        let ours = dir.write("ours.json", &ours);
+        let mut theirs = base.clone();
+        theirs.remove(0);
+        theirs[0].update_description("theirs");
+        let theirs = dir.write("theirs.json", &theirs);
+
+        let merged = sync_files(&ancestor, &ours, &theirs, &ours, None).expect("syncing");
+        assert!(merged.conflicts.is_empty());
+        assert_eq!(descriptions(&ours, None), ["theirs", "ours"]);
+        assert_eq!(descriptions(&ancestor, None), ["theirs", "ours"]);
+    }
+
+    #[test]
+    fn deletions_after_a_sync_stay_deleted() {
+        let dir = TestDir::new();
+        let ancestor = dir.0.join("ancestor.json");
+        let added = [Task::new("added on both")];
+        let ours = dir.write("ours.json", &added);
+        let theirs = dir.write("theirs.json", &added);
+        sync_files(&ancestor, &ours, &theirs, &ours, None).expect("the first sync");
+
+        // Deleted on our side only, and synced again
+        dir.write("ours.json", &[]);
+        let merged = sync_files(&ancestor, &ours, &theirs, &ours, None).expect("the second sync");
+        assert!(merged.conflicts.is_empty());
+        assert!(descriptions(&ours, None).is_empty());
+    }
+
+    #[test]
+    fn without_an_ancestor_keeps_both_and_reports_conflicts() {
+        let dir = TestDir::new();
+        let task = Task::new("one");
+        let mut edited = task.clone();
+        edited.update_description("edited");
+        let ours = dir.write("ours.json", &[task, Task::new("ours")]);
+        let theirs = dir.write("theirs.json", &[edited]);
+        let output = dir.0.join("output.json");
+
+        let merged = sync_files(&dir.0.join("ancestor.json"), &ours, &theirs, &output, None).expect("syncing");
+        // Both added the task, differently
+        assert_eq!(merged.conflicts.len(), 1);
+        assert!(merged.conflicts[0].fields.is_empty());
+        assert!(merged.conflicts[0].theirs.is_some());
+        assert_eq!(descriptions(&output, None), ["one", "ours"]);
+    }
+
+    #[test]
+    fn output_is_encrypted_if_either_copy_was() {
+        let dir = TestDir::new();
+        let passphrase: Passphrase = Zeroizing::new("passphrase".to_string());
+        let ours = dir.write("ours.json", &[Task::new("ours")]);
+        let plain = encode_tasks(&[Task::new("theirs")]).expect("encoding tasks");
+        let theirs = dir.0.join("theirs.json");
+        write(&theirs, encryption::encrypt(plain.as_bytes(), &passphrase, &mut None).expect("encrypting"))
+            .expect("writing tasks");
+        let output = dir.0.join("output.json");
+
+        sync_files(&dir.0.join("ancestor.json"), &ours, &theirs, &output, Some(&passphrase)).expect("syncing");
+        assert!(encryption::is_encrypted(&read(&output).expect("reading the output")));
+        assert_eq!(descriptions(&output, Some(&passphrase)), ["ours", "theirs"]);
+        assert!(sync_files(&dir.0.join("ancestor.json"), &ours, &theirs, &output, None).is_err());
+    }
+}
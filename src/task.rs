@@ -4,15 +4,19 @@ use chrono::{Days, Local, NaiveDateTime, NaiveTime, TimeDelta};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Clone, Serialize, Deserialize)]
+/// The longest interval between occurrences of a recurring task, in days.
+/// Imported tasks recurring less often than this are left non-recurring.
+pub const MAX_RECUR_DAYS : u64 = 3650;
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
 pub struct Task {
     description: String,
     dot: bool,
     uuid: Uuid,
 
     // The time the task was created, in local time.
-    // Use default for tasks that don't have this field.
-    #[serde(default)]
+    // Tasks from before this field existed are given the default when
+    // their file is migrated (see ``tasklist::format``).
     created: NaiveDateTime,
 
     // Contains an Instant if the task is complete
@@ -40,6 +44,14 @@ impl Task {
         }
     }
 
+    /// Rebuilds a task from its fields, as kept by a ``Replica``
+    pub(crate) fn from_fields(uuid: Uuid, created: NaiveDateTime, description: String, dot: bool,
+            completed: Option<NaiveDateTime>, recurrence: (Option<u64>, Option<NaiveDateTime>),
+            snooze_until: Option<NaiveDateTime>) -> Self {
+        let (recur_interval_days, recur_next) = recurrence;
+        Self { description, dot, uuid, created, completed, recur_next, recur_interval_days, snooze_until }
+    }
+
     #[must_use]
     pub fn detail_string(&self) -> String {
         let mut output = String::new();
@@ -92,19 +104,21 @@ impl Task {
         self.recur_interval_days = Some(1);
     }
 
-    fn in_n_days_5am(interval: u64) -> NaiveDateTime {
-        let mut next_5am = NaiveDateTime::new(Local::now().date_naive(),
-        NaiveTime::from_hms_opt(5,0,0).expect("5am not a valid time!"));
+    /// Returns 5am `interval` days from the next 5am, or None if that's
+    /// beyond the dates that can be held
+    fn in_n_days_5am(interval: u64) -> Option<NaiveDateTime> {
+        let mut next_5am = NaiveDateTime::new(Local::now().date_naive(), NaiveTime::from_hms_opt(5,0,0)?);
         if next_5am < Local::now().naive_local() {
-            next_5am = next_5am.checked_add_days(Days::new(1)).expect("Couldn't add 1 day in set_recur_daily");
+            next_5am = next_5am.checked_add_days(Days::new(1))?;
         }
-        next_5am.checked_add_days(Days::new(interval - 1)).expect("Couldn't add days in set_recur_daily")
+        next_5am.checked_add_days(Days::new(interval.checked_sub(1)?))
     }
 
-    /// Update ``recur_next`` field for recurring tasks
+    /// Update ``recur_next`` field for recurring tasks.  An interval too long
+    /// to reach leaves it as it was.
     fn recur_next(&mut self) {
-        if let Some(interval) = self.recur_interval_days {
-            self.recur_next = Some(Task::in_n_days_5am(interval));
+        if let Some(next) = self.recur_interval_days.and_then(Task::in_n_days_5am) {
+            self.recur_next = Some(next);
         }
     }
 
@@ -132,7 +146,7 @@ impl Task {
     }
 
     pub fn snooze_tomorrow(&mut self) {
-        self.snooze_until = Some(Task::in_n_days_5am(1));
+        self.snooze_until = Task::in_n_days_5am(1);
     }
 
     /// Snooze a task for 1 second, for testing purposes
@@ -191,6 +205,36 @@ impl Task {
         self.uuid
     }
 
+    /// Returns when the task was completed, if it has been
+    #[must_use]
+    pub fn completed(&self) -> Option<NaiveDateTime> {
+        self.completed
+    }
+
+    /// Returns when the task was created
+    #[must_use]
+    pub fn created(&self) -> NaiveDateTime {
+        self.created
+    }
+
+    /// Returns the number of days between occurrences, if the task is recurring
+    #[must_use]
+    pub fn recur_interval_days(&self) -> Option<u64> {
+        self.recur_interval_days
+    }
+
+    /// Returns the recurrence interval in days and the next occurrence, which
+    /// change together
+    pub(crate) fn recurrence(&self) -> (Option<u64>, Option<NaiveDateTime>) {
+        (self.recur_interval_days, self.recur_next)
+    }
+
+    /// Returns when the task is snoozed until, if it is
+    #[must_use]
+    pub fn snooze_until(&self) -> Option<NaiveDateTime> {
+        self.snooze_until
+    }
+
     pub fn update_description(&mut self, description: &str) {
         self.description = description.to_string();
     }
@@ -233,6 +277,37 @@ impl Task {
         self.clone_next_occurrence()
     }
 
+    /// Merges two versions of a task, `ours` and `theirs`, changed since their
+    /// common ancestor `base`.  The description, dot, completion, snooze and
+    /// recurrence are each taken from whichever side changed them.  A field
+    /// changed differently on both sides is a conflict, and is taken from
+    /// theirs if `prefer_theirs`, otherwise ours.
+    /// Returns the merged task, and the names of any conflicting fields.
+    #[must_use]
+    pub fn merge_fields(base: &Self, ours: &Self, theirs: &Self, prefer_theirs: bool) -> (Self, Vec<&'static str>) {
+        fn pick<T: PartialEq + Clone>(name: &'static str, base: &T, ours: &T, theirs: &T,
+                prefer_theirs: bool, conflicts: &mut Vec<&'static str>) -> T {
+            if ours == theirs || theirs == base {
+                ours.clone()
+            } else if ours == base {
+                theirs.clone()
+            } else {
+                conflicts.push(name);
+                if prefer_theirs { theirs.clone() } else { ours.clone() }
+            }
+        }
+        let mut conflicts = vec![];
+        let description = pick("description", &base.description, &ours.description, &theirs.description, prefer_theirs, &mut conflicts);
+        let dot = pick("dot", &base.dot, &ours.dot, &theirs.dot, prefer_theirs, &mut conflicts);
+        let created = pick("created", &base.created, &ours.created, &theirs.created, prefer_theirs, &mut conflicts);
+        let completed = pick("completed", &base.completed, &ours.completed, &theirs.completed, prefer_theirs, &mut conflicts);
+        let snooze_until = pick("snooze", &base.snooze_until, &ours.snooze_until, &theirs.snooze_until, prefer_theirs, &mut conflicts);
+        let (recur_interval_days, recur_next) = pick("recurrence",
+            &base.recurrence(), &ours.recurrence(), &theirs.recurrence(), prefer_theirs, &mut conflicts);
+        let merged = Self { description, dot, uuid: ours.uuid, created, completed, recur_next, recur_interval_days, snooze_until };
+        (merged, conflicts)
+    }
+
     #[must_use]
     pub fn is_complete(&self) -> bool {
         self.completed.is_some()
@@ -276,3 +351,61 @@ impl Display for Task {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completing_a_task_recurring_beyond_any_date_doesnt_panic() {
+        let task = Task::new("Forever");
+        let mut task = Task::from_fields(task.uuid(), task.created(), "Forever".to_string(), false, None,
+            (Some(u64::MAX), None), None);
+        let next = task.complete().expect("a next occurrence");
+        assert_eq!(next.recurrence(), (Some(u64::MAX), None));
+        assert!(task.remove());
+    }
+
+    #[test]
+    fn merge_fields_takes_each_field_from_the_side_that_changed_it() {
+        let base = Task::new("base");
+        let mut ours = base.clone();
+        ours.update_description("ours");
+        ours.snooze_tomorrow();
+        let mut theirs = base.clone();
+        theirs.toggle_dot();
+        theirs.set_recur_daily();
+
+        for prefer_theirs in [false, true] {
+            let (merged, conflicts) = Task::merge_fields(&base, &ours, &theirs, prefer_theirs);
+            assert!(conflicts.is_empty());
+            assert_eq!(merged.description(), "ours");
+            assert_eq!(merged.snooze_until(), ours.snooze_until());
+            assert!(merged.dot());
+            assert!(merged.recurrence() == theirs.recurrence());
+            assert_eq!(merged.uuid(), base.uuid());
+        }
+    }
+
+    #[test]
+    fn merge_fields_reports_fields_changed_differently() {
+        let base = Task::new("base");
+        let mut ours = base.clone();
+        ours.update_description("ours");
+        ours.snooze_tomorrow();
+        let mut theirs = base.clone();
+        theirs.update_description("theirs");
+        theirs.snooze_1s();
+        let _ = theirs.complete();
+
+        let (merged, conflicts) = Task::merge_fields(&base, &ours, &theirs, false);
+        assert_eq!(conflicts, ["description", "snooze"]);
+        assert_eq!(merged.description(), "ours");
+        assert_eq!(merged.snooze_until(), ours.snooze_until());
+        assert!(merged.is_complete());
+        let (merged, conflicts) = Task::merge_fields(&base, &ours, &theirs, true);
+        assert_eq!(conflicts, ["description", "snooze"]);
+        assert_eq!(merged.description(), "theirs");
+        assert_eq!(merged.snooze_until(), theirs.snooze_until());
+    }
+}
@@ -1,67 +1,496 @@
-use std::{fs::{create_dir_all, read_to_string, rename, File}, io::Write, path::{Path, PathBuf}};
+use std::path::PathBuf;
 
-use dirs::config_local_dir;
 use itertools::Itertools;
 use uuid::Uuid;
 
-use crate::Task;
+use crate::{merge, Conflict, Journal, JsonFileStore, Operation, Passphrase, Task, TaskStore};
 
-const PATH : &str = "tasks.json";
-const BACKUP_PATH : &str = "tasks_backup.json";
-const CONFIG_DIR : &str = "task";
+mod format;
+pub use format::*;
 
-pub struct TaskList {
+mod history;
+use history::History;
+
+/// The fewest characters of a uuid accepted as a prefix for finding a task,
+/// so that an empty or mistyped one can't match a task by chance
+pub const MIN_UUID_PREFIX : usize = 4;
+
+/// Checks that `prefix` could be the start of a uuid, as written with
+/// hyphens, and is long enough to find a task by
+///
+/// # Errors
+///
+/// Will return `Err` with kind `InvalidInput` if it is too short, or can't be
+/// part of a uuid
+pub fn check_uuid_prefix(prefix: &str) -> std::io::Result<()> {
+    if prefix.len() >= MIN_UUID_PREFIX && prefix.chars().all(|c| c.is_ascii_hexdigit() || c == '-') {
+        Ok(())
+    } else {
+        Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+            format!("'{prefix}' isn't at least {MIN_UUID_PREFIX} characters of a uuid")))
+    }
+}
+
+#[allow(clippy::struct_excessive_bools)]
+pub struct TaskList<S: TaskStore = JsonFileStore> {
     tasks: Vec<Task>,
+    // The tasks as last loaded from, or written to, the store: the common
+    // ancestor when merging in external changes
+    base: Vec<Task>,
+    store: S,
+    journal: Option<Journal>,
+    // The tasks as loaded, and the resets of recurring and snoozed tasks
+    // since, while there's no journal to record them in: kept so that
+    // attaching one can record them, rather than a whole checkpoint
+    unjournalled: Option<(Vec<Task>, Vec<Operation>)>,
+    history: History,
     show_completed: bool,
     future_filter: bool,
-    show_dotted_only: bool  // We actually also show all tasks below the last dotted
+    show_dotted_only: bool,  // We actually also show all tasks below the last dotted
+    read_only: bool,
 }
 
-impl Default for TaskList {
+impl<S: TaskStore + Default> Default for TaskList<S> {
     fn default() -> Self {
+        Self::new(S::default())
+    }
+}
+
+impl TaskList<JsonFileStore> {
+    /// Attempts to load the ``TaskList`` stored as JSON in `dir`, e.g. a
+    /// temporary directory in tests, as for ``load``.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the tasks can't be read, or aren't valid, or if
+    /// we can't write back after post-load alterations.
+    pub fn load_from_dir(dir: impl Into<PathBuf>) -> std::io::Result<Self> {
+        Self::load(JsonFileStore::new(dir))
+    }
+}
+
+impl<S: TaskStore> TaskList<S> {
+    /// Creates an empty list which will be written to `store`
+    #[must_use]
+    pub fn new(store: S) -> Self {
         Self {
             tasks: Vec::default(),
+            base: Vec::default(),
+            store,
+            journal: None,
+            unjournalled: None,
+            history: History::default(),
             show_completed: Default::default(),
             future_filter: true,
-            show_dotted_only: true
+            show_dotted_only: true,
+            read_only: false,
         }
     }
-}
 
-impl TaskList {
-    fn config_dir_pathbuf() -> PathBuf {
-        let dir_path_buf = config_local_dir().unwrap_or_default();
-        dir_path_buf.join(Path::new(CONFIG_DIR))
+    /// Attempts to load the ``TaskList`` object from `store`, and will
+    /// return it if found, and if valid.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the store can't be read, or doesn't contain
+    /// valid data, or if we can't write back after post-load alterations.
+    pub fn load(store: S) -> std::io::Result<Self> {
+        let mut task_list = Self::new(store);
+        task_list.reload()?;
+        Ok(task_list)
     }
 
-    fn path_to_save_file(file: &str) -> String {
-        Self::config_dir_pathbuf().join(Path::new(file)).to_string_lossy().to_string()
+    /// Replaces the tasks in the list with those in the store.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the store can't be read, or doesn't contain
+    /// valid data, or if we can't write back after post-load alterations.
+    pub fn reload(&mut self) -> std::io::Result<()> {
+        self.tasks = self.store.load()?;
+        if self.journal.is_none() {
+            self.unjournalled = Some((self.tasks.clone(), vec![]));
+        }
+        let reset = self.reset_recurring_and_snoozed();
+        self.base = self.tasks.clone();
+        reset
     }
 
-    fn save_path() -> String {
-        Self::path_to_save_file(PATH)
+    /// If the store has been changed by something else since we last loaded
+    /// it, merges those changes into the list, matching tasks by uuid, and
+    /// writes the result back if it differs.
+    ///
+    /// Returns `None` if there were no external changes, otherwise the tasks
+    /// that were changed both externally and here (without yet being written
+    /// to the store, e.g. because a write failed).  For those, our version is
+    /// kept, unless we deleted the task, in which case theirs is.
+    ///
+    /// External changes can't be undone, but are recorded in the journal.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the store can't be read or written
+    pub fn merge_external_changes(&mut self) -> std::io::Result<Option<Vec<Conflict>>> {
+        if self.read_only || !self.store.changed_externally()? {
+            return Ok(None);
+        }
+        let theirs = self.store.load()?;
+        let merged = merge(&self.base, &self.tasks, &theirs);
+        self.tasks = merged.tasks;
+        let stored = if self.tasks == theirs { Ok(()) } else { self.store.save(&self.tasks) };
+        self.base = theirs;
+        self.note_stored(&stored);
+        let recorded = self.record(Operation::Checkpoint { tasks: self.tasks.clone() });
+        stored.and(recorded)?;
+        Ok(Some(merged.conflicts))
     }
 
-    fn backup_path() -> String {
-        Self::path_to_save_file(BACKUP_PATH)
+    /// Settles a conflict reported by ``merge_external_changes`` by using our
+    /// version of the task, or theirs, and writes to storage.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the list is read-only, or the write to storage fails
+    pub fn resolve_conflict(&mut self, conflict: &Conflict, take_theirs: bool) -> std::io::Result<()> {
+        self.check_writable()?;
+        let uuid = conflict.uuid;
+        let chosen = if take_theirs { &conflict.theirs } else { &conflict.ours };
+        match (self.get(uuid).is_some(), chosen) {
+            (true, Some(task)) => self.replace(uuid, task.clone()),
+            (false, Some(task)) => self.add(task.clone()),
+            (true, None) => self.delete(uuid),
+            (false, None) => Ok(()),
+        }
     }
 
-    /// Attempts to load the ``TaskList`` object from storage, and will
-    /// return it if found, and if valid.
+    /// Called after ``reload`` fails with `error`, to recover what we can from
+    /// the store.  The list is made read-only, so that nothing is written until
+    /// the user has acknowledged the problem with ``set_read_only(false)``.
+    /// Returns a description of what was recovered, for the user.
+    pub fn recover(&mut self, error: &std::io::Error) -> String {
+        self.read_only = true;
+        match self.store.recover(error) {
+            Ok(recovery) => {
+                self.tasks = recovery.tasks;
+                self.base = self.tasks.clone();
+                recovery.report
+            },
+            Err(recover_error) => {
+                self.tasks.clear();
+                self.base.clear();
+                format!("{recover_error}.\n\nStarting with an empty task list.")
+            }
+        }
+    }
+
+    #[must_use]
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Allows or prevents changes to the list.
+    /// When read-only, attempts to change the list fail with
+    /// `PermissionDenied`, and nothing is written to the store.
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Writes the whole list to the store
     ///
     /// # Errors
     ///
-    /// Will return `Err` if the file doesn't exist, or doesn't contain
-    /// valid data, or if we can't write back after post-load alterations.
-    pub fn load() -> std::io::Result<Self> {
-        let serialized = read_to_string(Self::save_path())?;
-        let tasks = serde_json::from_str(&serialized)?;
-        let mut task_list = TaskList {
-            tasks,
-            ..Default::default()
+    /// Will return `Err` if the list is read-only, or the write to storage fails
+    pub fn save_all(&mut self) -> std::io::Result<()> {
+        self.check_writable()?;
+        let stored = self.store.save(&self.tasks);
+        self.note_stored(&stored);
+        let recorded = self.record(Operation::Checkpoint { tasks: self.tasks.clone() });
+        stored.and(recorded)
+    }
+
+    /// Replaces every task in the list, e.g. when restoring a snapshot, and
+    /// writes to storage.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the list is read-only, or the write to storage fails
+    pub fn replace_all(&mut self, tasks: Vec<Task>) -> std::io::Result<()> {
+        self.check_writable()?;
+        for task in std::mem::take(&mut self.tasks) {
+            self.history.record(Some((0, task)), None);
+        }
+        for (index, task) in tasks.iter().enumerate() {
+            self.history.record(None, Some((index, task.clone())));
+        }
+        self.tasks = tasks;
+        let stored = self.store.save(&self.tasks);
+        self.note_stored(&stored);
+        let recorded = self.record(Operation::Checkpoint { tasks: self.tasks.clone() });
+        stored.and(recorded)
+    }
+
+    /// Brings the list into line with `tasks`, an edited copy of it, e.g. sent
+    /// by a client, and writes to storage once.  Tasks are added, replaced and
+    /// removed individually, so that the journal records what changed, and
+    /// tasks moved to the bottom are moved likewise.  Any other reordering
+    /// replaces the whole list.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the list is read-only, or the write to storage fails
+    pub fn apply_edited(&mut self, tasks: Vec<Task>) -> std::io::Result<()> {
+        self.check_writable()?;
+        let mut operations = vec![];
+        while let Some(index) = self.tasks.iter().position(|t| !tasks.iter().any(|edited| edited.uuid() == t.uuid())) {
+            let before = self.tasks.remove(index);
+            operations.push(Operation::Remove { uuid: before.uuid() });
+            self.history.record(Some((index, before)), None);
+        }
+        for task in &tasks {
+            match self.tasks.iter().position(|t| t.uuid() == task.uuid()) {
+                None => {
+                    self.history.record(None, Some((self.tasks.len(), task.clone())));
+                    self.tasks.push(task.clone());
+                    operations.push(Operation::Add { task: task.clone() });
+                },
+                Some(index) if self.tasks[index] != *task => {
+                    let before = std::mem::replace(&mut self.tasks[index], task.clone());
+                    self.history.record(Some((index, before)), Some((index, task.clone())));
+                    operations.push(Operation::Replace { uuid: task.uuid(), task: task.clone() });
+                },
+                Some(_) => (),
+            }
+        }
+        let order = tasks.iter().map(Task::uuid).collect::<Vec<_>>();
+        if !self.tasks.iter().map(Task::uuid).eq(order.iter().copied()) {
+            // Find the fewest tasks which, moved to the bottom, give the new order
+            let moved = (1..order.len()).find(|&count| {
+                let (staying, moving) = order.split_at(order.len() - count);
+                self.tasks.iter().map(Task::uuid).filter(|uuid| !moving.contains(uuid)).eq(staying.iter().copied())
+            });
+            if let Some(count) = moved {
+                for task in &tasks[tasks.len() - count..] {
+                    if let Some(index) = self.tasks.iter().position(|t| t.uuid() == task.uuid()) {
+                        let before = self.tasks.remove(index);
+                        self.history.record(Some((index, before)), Some((self.tasks.len(), task.clone())));
+                        self.tasks.push(task.clone());
+                        operations.push(Operation::ReplaceAtBottom { uuid: task.uuid(), task: task.clone() });
+                    }
+                }
+            } else {
+                for task in std::mem::take(&mut self.tasks) {
+                    self.history.record(Some((0, task)), None);
+                }
+                for (index, task) in tasks.iter().enumerate() {
+                    self.history.record(None, Some((index, task.clone())));
+                }
+                self.tasks = tasks;
+                operations = vec![Operation::Checkpoint { tasks: self.tasks.clone() }];
+            }
+        }
+        if operations.is_empty() {
+            return Ok(());
+        }
+        self.save_operations(operations)
+    }
+
+    /// Starts recording every change to the list in `journal`.
+    /// Resets of recurring and snoozed tasks since the list was loaded are
+    /// recorded first.  If replaying the journal still doesn't give
+    /// the current list (e.g. because it is new, or the task file was changed
+    /// by something else), a checkpoint of the whole list is recorded instead.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the journal can't be read or written
+    pub fn set_journal(&mut self, mut journal: Journal) -> std::io::Result<()> {
+        let unjournalled = self.unjournalled.take();
+        if !self.read_only {
+            let mut journalled = journal.reconstruct(None)?;
+            let operations = match unjournalled {
+                Some((loaded, operations)) if loaded == journalled => operations,
+                _ => vec![],
+            };
+            for operation in &operations {
+                operation.apply(&mut journalled);
+            }
+            if journalled == self.tasks {
+                for operation in operations {
+                    journal.append(operation)?;
+                }
+            } else {
+                journal.append(Operation::Checkpoint { tasks: self.tasks.clone() })?;
+            }
+        }
+        self.journal = Some(journal);
+        Ok(())
+    }
+
+    /// Stops recording changes in the journal, e.g. because the list is now
+    /// encrypted, and the journal isn't
+    pub fn clear_journal(&mut self) {
+        self.journal = None;
+    }
+
+    /// Gives the store the passphrase for an encrypted list, and loads it
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` as for ``reload``, or with an ``EncryptionError`` if
+    /// the passphrase is wrong
+    pub fn unlock(&mut self, passphrase: Passphrase) -> std::io::Result<()> {
+        self.store.unlock(passphrase)?;
+        self.reload()
+    }
+
+    /// Rewrites the list encrypted with `passphrase`, or unencrypted if `None`
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the list is read-only, the store doesn't support
+    /// encryption, or the write to storage fails
+    pub fn set_encryption(&mut self, passphrase: Option<Passphrase>) -> std::io::Result<()> {
+        self.check_writable()?;
+        let stored = self.store.set_encryption(&self.tasks, passphrase);
+        self.note_stored(&stored);
+        stored
+    }
+
+    /// Starts grouping changes into a single user action that can be undone.
+    /// `selected` is the currently selected task, which will be re-selected if
+    /// the action is undone.
+    pub fn begin_action(&mut self, selected: Option<Uuid>) {
+        self.history.begin(selected);
+    }
+
+    /// Ends the user action started by ``begin_action``.
+    /// `selected` is the currently selected task, which will be re-selected if
+    /// the action is redone.
+    pub fn end_action(&mut self, selected: Option<Uuid>) {
+        self.history.end(selected);
+    }
+
+    /// Undoes the most recent user action, and writes to storage.
+    /// Returns the task that was selected before the action, if any.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the list is read-only, or the write to storage fails
+    pub fn undo(&mut self) -> std::io::Result<Option<Uuid>> {
+        self.check_writable()?;
+        let Some(action) = self.history.take_undo() else {
+            return Ok(None);
         };
-        task_list.reset_recurring_and_snoozed()?;
-        Ok(task_list)
+        let mut operations = vec![];
+        for change in action.changes.iter().rev() {
+            operations.extend(self.apply_change(change.after.as_ref(), change.before.as_ref()));
+        }
+        let selected = action.selected_before;
+        self.history.push_redo(action);
+        self.save_operations(operations)?;
+        Ok(selected)
+    }
+
+    /// Redoes the most recently undone user action, and writes to storage.
+    /// Returns the task that was selected after the action, if any.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the list is read-only, or the write to storage fails
+    pub fn redo(&mut self) -> std::io::Result<Option<Uuid>> {
+        self.check_writable()?;
+        let Some(action) = self.history.take_redo() else {
+            return Ok(None);
+        };
+        let mut operations = vec![];
+        for change in &action.changes {
+            operations.extend(self.apply_change(change.before.as_ref(), change.after.as_ref()));
+        }
+        let selected = action.selected_after;
+        self.history.push_undo(action);
+        self.save_operations(operations)?;
+        Ok(selected)
+    }
+
+    /// Replaces the task `from` with `to`, at their recorded positions,
+    /// returning the equivalent operations for the journal
+    fn apply_change(&mut self, from: Option<&(usize, Task)>, to: Option<&(usize, Task)>) -> Vec<Operation> {
+        let mut operations = vec![];
+        if let Some((index, task)) = from {
+            let uuid = task.uuid();
+            let position = if self.tasks.get(*index).is_some_and(|t| t.uuid() == uuid) {
+                Some(*index)
+            } else {
+                self.tasks.iter().position(|t| t.uuid() == uuid)
+            };
+            if let Some(position) = position {
+                self.tasks.remove(position);
+                operations.push(Operation::Remove { uuid });
+            }
+        }
+        if let Some((index, task)) = to {
+            let index = usize::min(*index, self.tasks.len());
+            self.tasks.insert(index, task.clone());
+            operations.push(Operation::Insert { index, task: task.clone() });
+        }
+        operations
+    }
+
+    fn save_operations(&mut self, operations: Vec<Operation>) -> std::io::Result<()> {
+        let stored = self.store.save(&self.tasks);
+        self.note_stored(&stored);
+        let mut recorded = Ok(());
+        for operation in operations {
+            recorded = recorded.and(self.record(operation));
+        }
+        stored.and(recorded)
+    }
+
+    /// Notes that the list has been written to the store, if it was, making
+    /// it the ancestor for merging in external changes
+    fn note_stored(&mut self, stored: &std::io::Result<()>) {
+        if stored.is_ok() {
+            self.base.clone_from(&self.tasks);
+        }
+    }
+
+    /// Records a change in the journal, if there is one.  Otherwise resets
+    /// are kept for when one is attached, but any other change means it will
+    /// need a checkpoint anyway.
+    fn record(&mut self, operation: Operation) -> std::io::Result<()> {
+        if let Some(journal) = &mut self.journal {
+            return journal.append(operation);
+        }
+        match (&mut self.unjournalled, operation) {
+            (Some((_, operations)), reset @ Operation::Reset { .. }) => operations.push(reset),
+            (unjournalled, _) => *unjournalled = None,
+        }
+        Ok(())
+    }
+
+    /// Checks that the list can be changed
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` with kind `PermissionDenied` if the list is read-only
+    pub fn check_writable(&self) -> std::io::Result<()> {
+        if self.read_only {
+            Err(std::io::Error::new(std::io::ErrorKind::PermissionDenied, "Task list is read-only"))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Returns the store the list is written to
+    #[must_use]
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// Returns the store the list is written to, e.g. to unlock it before
+    /// loading
+    pub fn store_mut(&mut self) -> &mut S {
+        &mut self.store
     }
 
     /// Attempts to add a task to the list, and write to storage.
@@ -70,8 +499,43 @@ impl TaskList {
     ///
     /// Will return `Err` if the write to storage fails
     pub fn add(&mut self, task: Task) -> std::io::Result<()> {
-        self.tasks.push(task);
-        self.save()
+        self.check_writable()?;
+        let uuid = task.uuid();
+        self.history.record(None, Some((self.tasks.len(), task.clone())));
+        self.tasks.push(task.clone());
+        let stored = self.store.task_added(&self.tasks, uuid);
+        self.note_stored(&stored);
+        let recorded = self.record(Operation::Add { task });
+        stored.and(recorded)
+    }
+
+    /// Adds imported tasks to the bottom of the list, in order, skipping
+    /// any already in it, and writes to storage.  Tasks are already in the
+    /// list if their uuid is, or if `match_descriptions`, if a task not yet
+    /// complete has the same description.  The tasks are written at once,
+    /// and journalled as added one by one.
+    /// Returns the number of tasks added.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the list is read-only, or the write to storage fails
+    pub fn add_imported(&mut self, imported: Vec<Task>, match_descriptions: bool) -> std::io::Result<usize> {
+        self.check_writable()?;
+        let mut operations = vec![];
+        for task in imported {
+            let duplicate = self.tasks.iter().any(|t| t.uuid() == task.uuid()
+                || match_descriptions && !t.is_complete() && t.description() == task.description());
+            if !duplicate {
+                self.history.record(None, Some((self.tasks.len(), task.clone())));
+                self.tasks.push(task.clone());
+                operations.push(Operation::Add { task });
+            }
+        }
+        let added = operations.len();
+        if added > 0 {
+            self.save_operations(operations)?;
+        }
+        Ok(added)
     }
 
     #[must_use]
@@ -113,11 +577,11 @@ impl TaskList {
 
     #[must_use]
     pub fn tasks_done_today(&self) -> Box<dyn DoubleEndedIterator<Item = &Task> + '_> {
-        return Box::new(
+        Box::new(
             self.tasks.iter().filter(
                 move |t| t.completed_today()
             ).sorted_by(|a, b| b.completed_date_time().cmp(&a.completed_date_time()))
-        );
+        )
     }
 
     /// Returns a slice containing all the tasks
@@ -132,6 +596,105 @@ impl TaskList {
         self.tasks.iter().find(|t| t.uuid() == uuid)
     }
 
+    /// Returns the task whose uuid starts with `prefix`, as written with
+    /// hyphens, ignoring case
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` with kind `NotFound` if no task matches, or
+    /// `InvalidInput` if more than one does, or the prefix is rejected by
+    /// ``check_uuid_prefix``.
+    pub fn find_by_prefix(&self, prefix: &str) -> std::io::Result<&Task> {
+        check_uuid_prefix(prefix)?;
+        let prefix = prefix.to_lowercase();
+        let mut matches = self.tasks.iter().filter(|t| t.uuid().to_string().starts_with(&prefix));
+        match (matches.next(), matches.next()) {
+            (Some(task), None) => Ok(task),
+            (None, _) => Err(std::io::Error::new(std::io::ErrorKind::NotFound, format!("No task with uuid starting '{prefix}'"))),
+            (Some(_), Some(_)) => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("More than one task has a uuid starting '{prefix}'"))),
+        }
+    }
+
+    /// Completes a task, adding its next occurrence if it's recurring, and
+    /// writes to storage.  Returns false if the task isn't found, or is
+    /// already complete, in which case nothing changes, so that completing
+    /// it twice doesn't add another occurrence.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the list is read-only, or the write to storage fails
+    pub fn complete(&mut self, uuid: Uuid) -> std::io::Result<bool> {
+        let Some(mut task) = self.get(uuid).filter(|task| !task.is_complete()).cloned() else {
+            return Ok(false);
+        };
+        if let Some(next_occurrence) = task.complete() {
+            self.add(next_occurrence)?;
+        }
+        self.replace(uuid, task)?;
+        Ok(true)
+    }
+
+    /// Dots a task, or undots it if not `dot`, moving it to the bottom when
+    /// undotted, and writes to storage.  Nothing changes if it's already so.
+    /// Returns false if the task isn't found.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the list is read-only, or the write to storage fails
+    pub fn set_dot(&mut self, uuid: Uuid, dot: bool) -> std::io::Result<bool> {
+        let Some(mut task) = self.get(uuid).cloned() else {
+            return Ok(false);
+        };
+        if task.dot() == dot {
+            return Ok(true);
+        }
+        task.toggle_dot();
+        if dot {
+            self.replace(uuid, task)?;
+        } else {
+            self.replace_at_bottom(uuid, task)?;
+        }
+        Ok(true)
+    }
+
+    /// Snoozes a task until tomorrow, and writes to storage.
+    /// Returns false if the task isn't found.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the list is read-only, or the write to storage fails
+    pub fn snooze_tomorrow(&mut self, uuid: Uuid) -> std::io::Result<bool> {
+        let Some(mut task) = self.get(uuid).cloned() else {
+            return Ok(false);
+        };
+        task.snooze_tomorrow();
+        self.replace(uuid, task)?;
+        Ok(true)
+    }
+
+    /// Sets a task to recur daily, or stops it recurring if not `recur`, and
+    /// writes to storage.  Nothing changes if it's already so.
+    /// Returns false if the task isn't found.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the list is read-only, or the write to storage fails
+    pub fn set_recur_daily(&mut self, uuid: Uuid, recur: bool) -> std::io::Result<bool> {
+        let Some(mut task) = self.get(uuid).cloned() else {
+            return Ok(false);
+        };
+        if recur && task.recur_interval_days() == Some(1) || !recur && !task.is_recurring() {
+            return Ok(true);
+        }
+        if recur {
+            task.set_recur_daily();
+        } else {
+            task.clear_recur();
+        }
+        self.replace(uuid, task)?;
+        Ok(true)
+    }
+
     /// Attempts to replace a task in the list, and write to storage.
     /// Fails silently if the task to replace isn't found!
     ///
@@ -139,10 +702,15 @@ impl TaskList {
     ///
     /// Will return `Err` if the write to storage fails
     pub fn replace(&mut self, uuid: Uuid, task: Task) -> std::io::Result<()> {
+        self.check_writable()?;
         if let Some(index) = self.tasks.iter().position(|t| t.uuid() == uuid) {
-            self.tasks.remove(index);
-            self.tasks.insert(index, task);
-            self.save()
+            let before = self.tasks.remove(index);
+            self.history.record(Some((index, before)), Some((index, task.clone())));
+            self.tasks.insert(index, task.clone());
+            let stored = self.store.task_replaced(&self.tasks, uuid);
+            self.note_stored(&stored);
+            let recorded = self.record(Operation::Replace { uuid, task });
+            stored.and(recorded)
         } else {
             Ok(())
         }
@@ -158,17 +726,77 @@ impl TaskList {
     ///
     /// Will return `Err` if the write to storage fails
     pub fn remove(&mut self, uuid: Uuid) -> std::io::Result<()> {
+        self.check_writable()?;
         if let Some(index) = self.tasks.iter().position(|t| t.uuid() == uuid) {
-            if !self.tasks[index].remove() {
+            let before = self.tasks[index].clone();
+            if self.tasks[index].remove() {
+                self.history.record(Some((index, before)), Some((index, self.tasks[index].clone())));
+                let stored = self.store.task_replaced(&self.tasks, uuid);
+                self.note_stored(&stored);
+                let recorded = self.record(Operation::Replace { uuid, task: self.tasks[index].clone() });
+                stored.and(recorded)
+            } else {
                 // If the task didn't remove itself, we remove it from the list
+                self.history.record(Some((index, before)), None);
                 self.tasks.remove(index);
+                let stored = self.store.task_removed(&self.tasks, uuid);
+                self.note_stored(&stored);
+                let recorded = self.record(Operation::Remove { uuid });
+                stored.and(recorded)
             }
-            self.save()
         } else {
             Ok(())
         }
     }
 
+    /// Removes a task from the list, even if it is recurring, e.g. when it has
+    /// been moved to another list, and writes to storage.
+    /// Fails silently if the task isn't found!
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the list is read-only, or the write to storage fails
+    pub fn delete(&mut self, uuid: Uuid) -> std::io::Result<()> {
+        self.check_writable()?;
+        if let Some(index) = self.tasks.iter().position(|t| t.uuid() == uuid) {
+            let before = self.tasks.remove(index);
+            self.history.record(Some((index, before)), None);
+            let stored = self.store.task_removed(&self.tasks, uuid);
+            self.note_stored(&stored);
+            let recorded = self.record(Operation::Remove { uuid });
+            stored.and(recorded)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Removes the tasks in `uuids` outright, e.g. once they have been
+    /// archived, or moved to another list, and writes to storage.  This can't
+    /// be undone, so earlier changes to the tasks can't be either, as that
+    /// would bring them back.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the list is read-only, or the write to storage fails
+    pub fn drop_tasks(&mut self, uuids: &[Uuid]) -> std::io::Result<()> {
+        self.check_writable()?;
+        let count = self.tasks.len();
+        self.tasks.retain(|t| !uuids.contains(&t.uuid()));
+        if self.tasks.len() == count {
+            return Ok(());
+        }
+        for uuid in uuids {
+            self.history.forget(*uuid);
+        }
+        let stored = self.store.save(&self.tasks);
+        self.note_stored(&stored);
+        let mut recorded = Ok(());
+        for uuid in uuids {
+            recorded = recorded.and(self.record(Operation::Remove { uuid: *uuid }));
+        }
+        stored.and(recorded)
+    }
+
     /// Attempts to replace a task in the list, but repositioned to the bottom
     /// and write to storage.
     /// Fails silently if the task to replace isn't found!
@@ -181,11 +809,16 @@ impl TaskList {
     }
 
     fn replace_at_bottom_saveopt(&mut self, uuid: Uuid, task: Task, save: bool) -> std::io::Result<()> {
+        self.check_writable()?;
         if let Some(index) = self.tasks.iter().position(|t| t.uuid() == uuid) {
-            self.tasks.remove(index);
-            self.tasks.push(task);
+            let before = self.tasks.remove(index);
+            self.history.record(Some((index, before)), Some((self.tasks.len(), task.clone())));
+            self.tasks.push(task.clone());
             if save {
-                self.save()
+                let stored = self.store.tasks_moved_to_bottom(&self.tasks, &[uuid]);
+                self.note_stored(&stored);
+                let recorded = self.record(Operation::ReplaceAtBottom { uuid, task });
+                stored.and(recorded)
             } else {
                 Ok(())
             }
@@ -201,6 +834,7 @@ impl TaskList {
     /// Performs pre-render processing.
     /// MUST be called before each time the list is going to be rendered
     /// (i.e. ``filtered_tasks`` is going to be called to obtain task list)
+    /// Does nothing if the list is read-only.
     ///
     /// # Errors
     /// Returns an error if it failed to write data to disk
@@ -222,6 +856,7 @@ impl TaskList {
     }
 
     /// Move all recurring tasks to bottom, remove dots if present
+    /// Does nothing if the list is read-only, as the changes couldn't be saved.
     ///
     /// NOTE!
     /// The logic below assumes that tasks cannot be both snoozed and recurring, if this becomes
@@ -229,6 +864,9 @@ impl TaskList {
     /// Basically, if a task is both recurring and snoozed, it will currently be unsnoozed by this
     /// method, which isn't what we'd want.
     fn reset_task_positions(&mut self, snoozed: bool, recurring: bool) -> std::io::Result<()> {
+        if self.read_only {
+            return Ok(());
+        }
         let mut reset_uuids : Vec<Uuid> = vec![];
         for task in &self.tasks {
             if snoozed && task.snooze_expiring() || recurring && task.is_recurring() {
@@ -238,65 +876,167 @@ impl TaskList {
         if reset_uuids.is_empty() {
             Ok(())
         } else {
-            for uuid in reset_uuids {
-                let mut task = self.get(uuid
+            for uuid in &reset_uuids {
+                let mut task = self.get(*uuid
                     ).expect("Should be able to find a task we know exists!").clone();
                 task.remove_dot();
                 task.unsnooze();
-                self.replace_at_bottom_nosave(uuid, task);
+                self.replace_at_bottom_nosave(*uuid, task);
             }
-            self.save()
+            let stored = self.store.tasks_moved_to_bottom(&self.tasks, &reset_uuids);
+            self.note_stored(&stored);
+            let reset_tasks = self.tasks[self.tasks.len() - reset_uuids.len()..].to_vec();
+            let recorded = self.record(Operation::Reset { tasks: reset_tasks });
+            stored.and(recorded)
         }
     }
 
-    fn save(&self) -> std::io::Result<()> {
-        let serialized = serde_json::to_string(&self.tasks)?;
-        let config_dir_path = &Self::config_dir_pathbuf();
-        if !config_dir_path.exists() {
-            create_dir_all(config_dir_path).expect("Couldn't create config dir");
-        }
-        assert!(config_dir_path.is_dir(), "Config dir path exists but is not a directory");
-        let _ = rename(Self::save_path(), Self::backup_path());
-        let mut file = File::create(Self::save_path())?;
-        file.write_all(serialized.as_bytes())?;
-        Ok(())
+    pub fn toggle_future_filter(&mut self) {
+        self.future_filter = !self.future_filter;
     }
 
-    /// Checks if the lock file is there, and returns error if it is, otherwise creates it.
-    ///
-    /// # Errors
-    /// Will return `Err` if the lock file already exists, or if we can't create it.
-    pub fn check_lock_file() -> std::io::Result<()> {
-        let lock_file_path = Self::config_dir_pathbuf().join("task.lock");
-        if lock_file_path.exists() {
-            return Err(std::io::Error::new(
-                std::io::ErrorKind::AlreadyExists,
-                "Task list is already locked by another process",
-            ));
-        }
-        File::create(lock_file_path)?;
-        Ok(())
+    pub fn toggle_dotted_only(&mut self) {
+        self.show_dotted_only = !self.show_dotted_only;
     }
 
-    /// Deletes the lock file if it exists.
-    ///
-    /// # Errors
-    /// Will return `Err` if we can't remove the lock file.
-    pub fn clear_lock_file() -> std::io::Result<()> {
-        let lock_file_path = Self::config_dir_pathbuf().join("task.lock");
-        if lock_file_path.exists() {
-            std::fs::remove_file(lock_file_path)?;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryStore;
+
+    // Counts the writes to the list
+    #[derive(Default)]
+    struct CountingStore {
+        store: MemoryStore,
+        saves: usize,
+    }
+
+    impl TaskStore for CountingStore {
+        fn load(&mut self) -> std::io::Result<Vec<Task>> {
+            self.store.load()
+        }
+
+        fn save(&mut self, tasks: &[Task]) -> std::io::Result<()> {
+            self.saves += 1;
+            self.store.save(tasks)
         }
-        Ok(())
     }
 
-    pub fn toggle_future_filter(&mut self) {
-        self.future_filter = !self.future_filter;
+    fn list(descriptions: &[&str]) -> TaskList<CountingStore> {
+        let tasks = descriptions.iter().map(|description| Task::new(description)).collect();
+        let store = CountingStore { store: MemoryStore::new(tasks), saves: 0 };
+        TaskList::load(store).expect("a list in memory")
     }
 
-    pub fn toggle_dotted_only(&mut self) {
-        self.show_dotted_only = !self.show_dotted_only;
+    fn descriptions<S: TaskStore>(tasks: &TaskList<S>) -> Vec<&str> {
+        tasks.tasks().iter().map(Task::description).collect()
     }
 
-}
+    /// Applies `edited` as one action, checking it's written once, and can be
+    /// undone
+    fn apply_and_undo(tasks: &mut TaskList<CountingStore>, edited: &[Task]) {
+        let original = tasks.tasks().to_vec();
+        tasks.begin_action(None);
+        tasks.apply_edited(edited.to_vec()).expect("applied");
+        tasks.end_action(None);
+        assert!(tasks.tasks() == edited);
+        assert!(tasks.store().store.tasks() == edited);
+        assert_eq!(tasks.store().saves, 1);
+        tasks.undo().expect("undone");
+        assert!(tasks.tasks() == original);
+    }
+
+    #[test]
+    fn apply_edited_writes_every_change_at_once() {
+        let mut tasks = list(&["one", "two", "three", "four"]);
+        let mut edited = tasks.tasks()[1..].to_vec();
+        edited[1].update_description("edited");
+        let moved = edited.remove(0);
+        edited.push(moved);
+        edited.push(Task::new("added"));
+        apply_and_undo(&mut tasks, &edited);
+    }
+
+    #[test]
+    fn apply_edited_can_reorder_the_whole_list() {
+        let mut tasks = list(&["one", "two", "three"]);
+        let mut edited = tasks.tasks().to_vec();
+        edited.reverse();
+        edited[0].toggle_dot();
+        apply_and_undo(&mut tasks, &edited);
+        assert_eq!(descriptions(&tasks), ["one", "two", "three"]);
+    }
+
+    #[test]
+    fn apply_edited_without_changes_doesnt_write() {
+        let mut tasks = list(&["one", "two"]);
+        tasks.apply_edited(tasks.tasks().to_vec()).expect("applied");
+        assert_eq!(tasks.store().saves, 0);
+    }
+
+    #[test]
+    fn completing_twice_adds_one_occurrence() {
+        let mut tasks = list(&["daily"]);
+        let uuid = tasks.tasks()[0].uuid();
+        assert!(tasks.set_recur_daily(uuid, true).expect("set"));
+        assert!(tasks.complete(uuid).expect("completed"));
+        let saves = tasks.store().saves;
+        assert!(!tasks.complete(uuid).expect("completed already"));
+        assert_eq!(tasks.store().saves, saves);
+        assert_eq!(tasks.tasks().len(), 2);
+        assert_eq!(tasks.tasks().iter().filter(|task| task.is_complete()).count(), 1);
+    }
+
+    #[test]
+    fn setting_what_is_already_set_doesnt_write() {
+        let mut tasks = list(&["one", "two"]);
+        let uuid = tasks.tasks()[0].uuid();
+        assert!(tasks.set_dot(uuid, true).expect("dotted"));
+        assert!(tasks.set_recur_daily(uuid, true).expect("set"));
+        assert_eq!(tasks.store().saves, 2);
+        assert!(tasks.set_dot(uuid, true).expect("dotted"));
+        assert!(tasks.set_recur_daily(uuid, true).expect("set"));
+        assert_eq!(tasks.store().saves, 2);
+        assert!(tasks.get(uuid).is_some_and(|task| task.dot() && task.recur_interval_days() == Some(1)));
 
+        assert!(tasks.set_dot(uuid, false).expect("undotted"));
+        assert!(tasks.set_recur_daily(uuid, false).expect("cleared"));
+        assert!(tasks.set_dot(uuid, false).expect("undotted"));
+        assert!(tasks.set_recur_daily(uuid, false).expect("cleared"));
+        assert_eq!(tasks.store().saves, 4);
+        // Undotting moves the task to the bottom
+        assert_eq!(descriptions(&tasks), ["two", "one"]);
+        assert!(!tasks.set_dot(Uuid::new_v4(), true).expect("not found"));
+    }
+
+    #[test]
+    fn tasks_are_only_found_by_a_long_enough_prefix() {
+        let tasks = list(&["only"]);
+        let uuid = tasks.tasks()[0].uuid().to_string();
+        assert_eq!(tasks.find_by_prefix(&uuid[..MIN_UUID_PREFIX]).expect("the task").description(), "only");
+        assert_eq!(tasks.find_by_prefix(&uuid.to_uppercase()).expect("the task").description(), "only");
+        for prefix in ["", "/", &uuid[..MIN_UUID_PREFIX - 1], "zzzzzzzz"] {
+            let err = tasks.find_by_prefix(prefix).err().map(|err| err.kind());
+            assert_eq!(err, Some(std::io::ErrorKind::InvalidInput), "{prefix}");
+        }
+    }
+
+    #[test]
+    fn imports_are_added_at_once_and_undone_together() {
+        let mut tasks = list(&["one", "two"]);
+        let original = tasks.tasks().to_vec();
+        let imported = vec![Task::new("three"), Task::new("one"), Task::new("four"), original[1].clone()];
+        tasks.begin_action(None);
+        assert_eq!(tasks.add_imported(imported, true).expect("imported"), 2);
+        tasks.end_action(None);
+        assert_eq!(descriptions(&tasks), ["one", "two", "three", "four"]);
+        assert!(tasks.store().store.tasks() == tasks.tasks());
+        assert_eq!(tasks.store().saves, 1);
+        assert_eq!(tasks.add_imported(vec![Task::new("three")], true).expect("nothing imported"), 0);
+        assert_eq!(tasks.store().saves, 1);
+        tasks.undo().expect("undone");
+        assert!(tasks.tasks() == original);
+    }
+}
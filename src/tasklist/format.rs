@@ -0,0 +1,133 @@
+use chrono::{Local, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::Task;
+
+/// The version of the on-disk format written by this build.
+/// Bump this, and add a migration to ``MIGRATIONS``, whenever the format changes.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Migrations from each older format version to the next.
+/// `MIGRATIONS[n]` upgrades a file from version `n` to version `n + 1`.
+const MIGRATIONS: [fn(Value) -> std::io::Result<Value>; FORMAT_VERSION as usize] = [
+    migrate_v0_to_v1,
+];
+
+/// Information about a saved task file
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Metadata {
+    /// Version of the application which wrote the file
+    pub app_version: String,
+    /// Local time at which the file was written
+    pub saved: NaiveDateTime,
+}
+
+impl Default for Metadata {
+    fn default() -> Self {
+        Self {
+            app_version: env!("CARGO_PKG_VERSION").to_string(),
+            saved: Local::now().naive_local(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope<T> {
+    version: u32,
+    metadata: Metadata,
+    tasks: T,
+}
+
+/// The result of decoding a task file
+pub struct DecodedTasks {
+    pub tasks: Vec<Task>,
+    pub metadata: Metadata,
+    /// The format version the file was written in, before any migrations
+    pub from_version: u32,
+}
+
+impl DecodedTasks {
+    /// Returns true if the file was in an older format, and has been migrated
+    #[must_use]
+    pub fn migrated(&self) -> bool {
+        self.from_version < FORMAT_VERSION
+    }
+}
+
+/// Serializes `tasks` in the current format
+///
+/// # Errors
+///
+/// Will return `Err` if serialization fails
+pub fn encode_tasks(tasks: &[Task]) -> std::io::Result<String> {
+    Ok(serde_json::to_string(&Envelope {
+        version: FORMAT_VERSION,
+        metadata: Metadata::default(),
+        tasks,
+    })?)
+}
+
+/// Parses a task file written in the current or any older format, migrating
+/// it to the current format as necessary.
+///
+/// # Errors
+///
+/// Will return `Err` with kind `InvalidData` if the data isn't a valid task file,
+/// or with kind `Unsupported` if it was written by a newer version of the
+/// application, in which case it must not be overwritten.
+pub fn decode_tasks(serialized: &[u8]) -> std::io::Result<DecodedTasks> {
+    let mut value: Value = serde_json::from_slice(serialized)?;
+    let from_version = format_version(&value)?;
+    if from_version > FORMAT_VERSION {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("Task file is format version {from_version}, written by a newer version of this application \
+                (this version supports up to {FORMAT_VERSION})"),
+        ));
+    }
+    for migration in &MIGRATIONS[from_version as usize..] {
+        value = migration(value)?;
+    }
+    let envelope: Envelope<Vec<Task>> = serde_json::from_value(value)?;
+    Ok(DecodedTasks {
+        tasks: envelope.tasks,
+        metadata: envelope.metadata,
+        from_version,
+    })
+}
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// Version 0 files are a bare array of tasks.  Later versions are an envelope
+/// object with a `version` field.
+fn format_version(value: &Value) -> std::io::Result<u32> {
+    match value {
+        Value::Array(_) => Ok(0),
+        Value::Object(object) => object.get("version")
+            .and_then(Value::as_u64)
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| invalid_data("Task file has no valid version")),
+        _ => Err(invalid_data("Task file is neither a list of tasks nor a versioned file")),
+    }
+}
+
+/// Wraps the bare array in an envelope, and gives tasks that predate the
+/// `created` field the default creation time.
+fn migrate_v0_to_v1(value: Value) -> std::io::Result<Value> {
+    let Value::Array(mut tasks) = value else {
+        return Err(invalid_data("Version 0 task file is not a list of tasks"));
+    };
+    for task in &mut tasks {
+        if let Value::Object(task) = task {
+            task.entry("created").or_insert_with(|| json!(NaiveDateTime::default()));
+        }
+    }
+    Ok(json!({
+        "version": 1,
+        "metadata": Metadata::default(),
+        "tasks": tasks,
+    }))
+}
@@ -0,0 +1,92 @@
+use uuid::Uuid;
+
+use crate::Task;
+
+/// A task's position and value
+pub(super) type Placed = (usize, Task);
+
+/// A single change to the list: the task before the change (if it existed),
+/// and after it (if it still exists)
+pub(super) struct Change {
+    pub before: Option<Placed>,
+    pub after: Option<Placed>,
+}
+
+/// All the changes made by one user action, and the selected task before
+/// and after it
+pub(super) struct Action {
+    pub changes: Vec<Change>,
+    pub selected_before: Option<Uuid>,
+    pub selected_after: Option<Uuid>,
+}
+
+/// Undo and redo stacks of user actions.
+///
+/// Changes are only recorded between ``begin`` and ``end``, so that automatic
+/// changes (such as snoozed tasks being reset) aren't undone.
+#[derive(Default)]
+pub(super) struct History {
+    undo: Vec<Action>,
+    redo: Vec<Action>,
+    open: Option<Action>,
+}
+
+impl History {
+    pub fn begin(&mut self, selected: Option<Uuid>) {
+        self.end(selected);
+        self.open = Some(Action {
+            changes: vec![],
+            selected_before: selected,
+            selected_after: selected,
+        });
+    }
+
+    /// Ends the current action, and makes it available to undo if it changed anything
+    pub fn end(&mut self, selected: Option<Uuid>) {
+        if let Some(mut action) = self.open.take() {
+            if !action.changes.is_empty() {
+                action.selected_after = selected;
+                self.undo.push(action);
+                self.redo.clear();
+            }
+        }
+    }
+
+    pub fn record(&mut self, before: Option<Placed>, after: Option<Placed>) {
+        if let Some(action) = &mut self.open {
+            action.changes.push(Change { before, after });
+        }
+    }
+
+    /// Forgets every action which changed the task `uuid`, once it has left
+    /// the list in a way that can't be undone, as undoing or redoing them
+    /// would bring it back
+    pub fn forget(&mut self, uuid: Uuid) {
+        let changed = |action: &Action| action.changes.iter()
+            .flat_map(|change| change.before.iter().chain(&change.after))
+            .any(|(_, task)| task.uuid() == uuid);
+        self.undo.retain(|action| !changed(action));
+        self.redo.retain(|action| !changed(action));
+        if self.open.as_ref().is_some_and(changed) {
+            self.open = None;
+        }
+    }
+
+    pub fn take_undo(&mut self) -> Option<Action> {
+        self.open = None;
+        self.undo.pop()
+    }
+
+    pub fn push_undo(&mut self, action: Action) {
+        self.undo.push(action);
+    }
+
+    pub fn take_redo(&mut self) -> Option<Action> {
+        self.open = None;
+        self.redo.pop()
+    }
+
+    pub fn push_redo(&mut self, action: Action) {
+        self.redo.push(action);
+    }
+}
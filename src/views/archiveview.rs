@@ -0,0 +1,89 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::{layout::{Constraint, Layout, Rect}, text::Line, widgets::{List, ListState}, Frame};
+
+use crate::{popup, Archive, Task};
+
+/// Pop-up listing archived tasks, most recently completed first, which can
+/// be searched by description
+#[derive(Default)]
+pub struct ArchiveView {
+    tasks: Option<Vec<Task>>,
+    query: String,
+    searching: bool,
+    state: ListState,
+}
+
+impl ArchiveView {
+
+    /// Opens the pop-up on the tasks in `archive`
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the archive can't be read
+    pub fn open(&mut self, archive: &Archive) -> std::io::Result<()> {
+        let tasks = archive.load_all()?;
+        self.state.select(if tasks.is_empty() { None } else { Some(0) });
+        self.tasks = Some(tasks);
+        self.query.clear();
+        self.searching = false;
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn is_visible(&self) -> bool {
+        self.tasks.is_some()
+    }
+
+    fn matching(&self) -> impl Iterator<Item = &Task> {
+        self.tasks.iter().flatten().filter(|t| Archive::matches(t, &self.query))
+    }
+
+    /// Renders the pop-up, if visible, over the passed area
+    pub fn render(&mut self, frame: &mut Frame, area: Rect) {
+        if self.tasks.is_none() {
+            return;
+        }
+        let lines = self.matching()
+            .map(|t| format!("{}  {}", t.completed_date_time().format("%Y-%m-%d %H:%M"), t.description()))
+            .collect::<Vec<_>>();
+        let inner = popup::render(frame, "Archive - / search, Esc close", 80, 70, area);
+        let [search_area, list_area] = Layout::vertical([Constraint::Length(2), Constraint::Min(0)]).areas(inner);
+        let cursor = if self.searching { "_" } else { "" };
+        frame.render_widget(Line::from(format!("Search: {}{cursor}  ({} tasks)", self.query, lines.len())), search_area);
+        let list = if lines.is_empty() {
+            List::new(["No archived tasks"])
+        } else {
+            List::new(lines).highlight_symbol(">> ")
+        };
+        frame.render_stateful_widget(list, list_area, &mut self.state);
+    }
+
+    /// Attempts to handle keyboard input
+    /// Returns true if it was handled, false if caller should handle.
+    /// While visible, every key is handled here.
+    pub fn handle_key(&mut self, key: KeyEvent) -> bool {
+        if self.tasks.is_none() {
+            return false;
+        }
+        if self.searching {
+            match key.code {
+                KeyCode::Char(c) => self.query.push(c),
+                KeyCode::Backspace => { self.query.pop(); },
+                KeyCode::Enter | KeyCode::Esc => self.searching = false,
+                _ => ()
+            }
+            let count = self.matching().count();
+            self.state.select(if count == 0 { None } else { Some(0) });
+            return true;
+        }
+        match key.code {
+            KeyCode::Char('j') | KeyCode::Down => self.state.select_next(),
+            KeyCode::Char('k') | KeyCode::Up => self.state.select_previous(),
+            KeyCode::Char('/') => self.searching = true,
+            KeyCode::Esc | KeyCode::Char('q') => self.tasks = None,
+            _ => ()
+        }
+        true
+    }
+
+}
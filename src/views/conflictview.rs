@@ -0,0 +1,107 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::{layout::Rect, text::Line, widgets::{List, ListItem, ListState}, Frame};
+
+use crate::{popup, Conflict, Task, TaskList, TaskListView, TaskStore};
+
+/// Pop-up listing tasks that were changed both here and by another program,
+/// letting the user choose which version of each to keep
+#[derive(Default)]
+pub struct ConflictView {
+    conflicts: Vec<Conflict>,
+    state: ListState,
+}
+
+fn describe(task: Option<&Task>) -> String {
+    let Some(task) = task else {
+        return "(deleted)".to_string();
+    };
+    let dotted = if task.dot() { " [dotted]" } else { "" };
+    let done = if task.is_complete() {
+        format!(" [done {}]", task.completed_date_time().format("%Y-%m-%d %H:%M"))
+    } else {
+        String::new()
+    };
+    format!("{}{dotted}{done}", task.description())
+}
+
+impl ConflictView {
+
+    /// Adds `conflicts` to those shown in the pop-up, showing it if necessary
+    pub fn show(&mut self, conflicts: Vec<Conflict>) {
+        for conflict in conflicts {
+            self.conflicts.retain(|c| c.uuid != conflict.uuid);
+            self.conflicts.push(conflict);
+        }
+        if self.state.selected().is_none() && !self.conflicts.is_empty() {
+            self.state.select(Some(0));
+        }
+    }
+
+    #[must_use]
+    pub fn is_visible(&self) -> bool {
+        !self.conflicts.is_empty()
+    }
+
+    /// Renders the pop-up, if visible, over the passed area
+    pub fn render(&mut self, frame: &mut Frame, area: Rect) {
+        if self.conflicts.is_empty() {
+            return;
+        }
+        let inner = popup::render(frame, "Changed here and elsewhere - o keep ours, t take theirs, Esc keep as merged",
+            80, 60, area);
+        let items = self.conflicts.iter().map(|conflict| {
+            let mut lines = vec![
+                Line::from(format!("ours:   {}", describe(conflict.ours.as_ref()))),
+                Line::from(format!("theirs: {}", describe(conflict.theirs.as_ref()))),
+            ];
+            if !conflict.fields.is_empty() {
+                lines.push(Line::from(format!("        both changed {}", conflict.fields.join(", "))));
+            }
+            lines.push(Line::default());
+            ListItem::new(lines)
+        });
+        frame.render_stateful_widget(List::new(items).highlight_symbol(">> "), inner, &mut self.state);
+    }
+
+    /// Attempts to handle keyboard input
+    /// Returns true if it was handled, false if caller should handle.
+    /// While visible, every key is handled here.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the write to storage fails after choosing a version
+    pub fn handle_key<S: TaskStore>(
+            &mut self,
+            key: KeyEvent,
+            task_list: &mut TaskList<S>,
+            task_list_view: &mut TaskListView)
+                -> std::io::Result<bool> {
+        if self.conflicts.is_empty() {
+            return Ok(false);
+        }
+        match key.code {
+            KeyCode::Char('j') | KeyCode::Down => self.state.select_next(),
+            KeyCode::Char('k') | KeyCode::Up => self.state.select_previous(),
+            KeyCode::Esc => {
+                // The merge has already kept our versions, or theirs where we'd
+                // deleted the task
+                self.conflicts.clear();
+                self.state.select(None);
+            },
+            KeyCode::Char(choice @ ('o' | 't')) => {
+                let index = self.state.selected().unwrap_or(0).min(self.conflicts.len() - 1);
+                let conflict = self.conflicts.remove(index);
+                if self.conflicts.is_empty() {
+                    self.state.select(None);
+                } else {
+                    self.state.select(Some(index.min(self.conflicts.len() - 1)));
+                }
+                task_list.resolve_conflict(&conflict, choice == 't')?;
+                task_list_view.fix_selection(task_list);
+            },
+            _ => ()
+        }
+        Ok(true)
+    }
+
+}
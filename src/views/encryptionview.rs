@@ -0,0 +1,176 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::{layout::Rect, widgets::{Paragraph, Wrap}, Frame};
+use zeroize::Zeroizing;
+
+use crate::{popup, Passphrase};
+
+/// What the user asked for from the encryption pop-up
+pub enum EncryptionRequest {
+    /// Load the encrypted list with this passphrase
+    Unlock(Passphrase),
+    /// Encrypt the list with this passphrase, or store it unencrypted if `None`
+    SetPassphrase(Option<Passphrase>),
+}
+
+enum Prompt {
+    Unlock,
+    Menu { encrypted: bool },
+    NewPassphrase { first: Option<Passphrase> },
+    ConfirmEncrypt { passphrase: Passphrase },
+    ConfirmDisable,
+}
+
+/// Pop-up asking for the passphrase of an encrypted list, and for enabling,
+/// disabling, or changing the passphrase of encryption
+#[derive(Default)]
+pub struct EncryptionView {
+    prompt: Option<Prompt>,
+    input: Passphrase,
+    error: Option<String>,
+    request: Option<EncryptionRequest>,
+    // The unencrypted copies of the list to be deleted once it's encrypted,
+    // as described to the user
+    unencrypted_copies: Vec<String>,
+}
+
+impl EncryptionView {
+
+    /// Asks for the passphrase to load the encrypted list.
+    /// `error` explains why it's being asked for again, if it is.
+    pub fn ask_unlock(&mut self, error: Option<String>) {
+        self.show(Prompt::Unlock);
+        self.error = error;
+    }
+
+    /// Opens the menu to enable encryption, or change the passphrase or
+    /// disable it if the list is already `encrypted`.  If there are
+    /// `unencrypted_copies` of the list, the user is asked to confirm they
+    /// will be deleted before it's encrypted.
+    pub fn open_menu(&mut self, encrypted: bool, unencrypted_copies: Vec<String>) {
+        self.unencrypted_copies = unencrypted_copies;
+        self.show(Prompt::Menu { encrypted });
+    }
+
+    fn show(&mut self, prompt: Prompt) {
+        self.prompt = Some(prompt);
+        self.input = Zeroizing::new(String::new());
+        self.error = None;
+    }
+
+    pub fn hide(&mut self) {
+        self.prompt = None;
+        self.input = Zeroizing::new(String::new());
+    }
+
+    #[must_use]
+    pub fn is_visible(&self) -> bool {
+        self.prompt.is_some()
+    }
+
+    /// Returns the user's request, if one has been made since this was last called
+    pub fn take_request(&mut self) -> Option<EncryptionRequest> {
+        self.request.take()
+    }
+
+    /// Renders the pop-up, if visible, over the passed area
+    pub fn render(&self, frame: &mut Frame, area: Rect) {
+        let Some(prompt) = &self.prompt else {
+            return;
+        };
+        let masked = "*".repeat(self.input.chars().count());
+        let (title, mut text) = match prompt {
+            Prompt::Unlock => ("Encrypted task list",
+                format!("Enter the passphrase for this task list.\n\n Passphrase: {masked}_\n\n ENT - Unlock\n Esc - Leave locked (read only)")),
+            Prompt::Menu { encrypted: false } => ("Encryption",
+                "The task list is stored unencrypted.\n\n e - Encrypt with a passphrase\n Esc - Close".to_string()),
+            Prompt::Menu { encrypted: true } => ("Encryption",
+                "The task list is encrypted.\n\n r - Change passphrase\n d - Disable encryption\n Esc - Close".to_string()),
+            Prompt::NewPassphrase { first } => ("New passphrase", format!(
+                "{}\n\n Passphrase: {masked}_\n\n\
+                While encrypted, snapshots, the journal, the archive and git history aren't \
+                written.\n\n ENT - Continue\n Esc - Cancel",
+                if first.is_some() { "Enter the new passphrase again." } else { "Enter the new passphrase." }
+            )),
+            Prompt::ConfirmEncrypt { .. } => ("Delete unencrypted copies", format!(
+                "These unencrypted copies of the task list will be deleted once it's encrypted:\n\n{}\n\n \
+                y - Encrypt, and delete them\n Esc - Cancel",
+                self.unencrypted_copies.iter().map(|copy| format!(" {copy}")).collect::<Vec<_>>().join("\n")
+            )),
+            Prompt::ConfirmDisable => ("Disable encryption",
+                "Store the task list unencrypted?\n\n y - Disable encryption\n Esc - Cancel".to_string()),
+        };
+        if let Some(error) = &self.error {
+            text.push_str("\n\n");
+            text.push_str(error);
+        }
+        let inner = popup::render(frame, title, 60, 50, area);
+        frame.render_widget(Paragraph::new(text).wrap(Wrap { trim: false }), inner);
+    }
+
+    /// Attempts to handle keyboard input
+    /// Returns true if it was handled, false if caller should handle.
+    /// While visible, every key is handled here.
+    /// Once the user has made a request, it is available from ``take_request``.
+    pub fn handle_key(&mut self, key: KeyEvent) -> bool {
+        let Some(prompt) = &mut self.prompt else {
+            return false;
+        };
+        match prompt {
+            Prompt::Unlock | Prompt::NewPassphrase { .. } => match key.code {
+                KeyCode::Char(c) => self.input.push(c),
+                KeyCode::Backspace => { self.input.pop(); },
+                KeyCode::Esc => self.hide(),
+                KeyCode::Enter => self.submit(),
+                _ => ()
+            },
+            Prompt::Menu { encrypted } => match (key.code, *encrypted) {
+                (KeyCode::Char('e'), false) | (KeyCode::Char('r'), true) => self.show(Prompt::NewPassphrase { first: None }),
+                (KeyCode::Char('d'), true) => self.show(Prompt::ConfirmDisable),
+                (KeyCode::Esc | KeyCode::Char('q'), _) => self.hide(),
+                _ => ()
+            },
+            Prompt::ConfirmEncrypt { passphrase } => {
+                if key.code == KeyCode::Char('y') {
+                    self.request = Some(EncryptionRequest::SetPassphrase(Some(passphrase.clone())));
+                }
+                self.hide();
+            },
+            Prompt::ConfirmDisable => {
+                if key.code == KeyCode::Char('y') {
+                    self.request = Some(EncryptionRequest::SetPassphrase(None));
+                }
+                self.hide();
+            },
+        }
+        true
+    }
+
+    fn submit(&mut self) {
+        let input = std::mem::replace(&mut self.input, Zeroizing::new(String::new()));
+        match &mut self.prompt {
+            Some(Prompt::Unlock) => {
+                self.request = Some(EncryptionRequest::Unlock(input));
+                self.hide();
+            },
+            Some(Prompt::NewPassphrase { first }) => {
+                if input.is_empty() {
+                    self.error = Some("The passphrase can't be empty.".to_string());
+                } else if let Some(first) = first.take() {
+                    if *first != *input {
+                        self.error = Some("The passphrases didn't match.  Start again.".to_string());
+                    } else if self.unencrypted_copies.is_empty() {
+                        self.request = Some(EncryptionRequest::SetPassphrase(Some(input)));
+                        self.hide();
+                    } else {
+                        self.show(Prompt::ConfirmEncrypt { passphrase: input });
+                    }
+                } else {
+                    *first = Some(input);
+                    self.error = None;
+                }
+            },
+            _ => ()
+        }
+    }
+
+}
@@ -0,0 +1,170 @@
+use std::path::PathBuf;
+
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::{layout::Rect, widgets::{Paragraph, Wrap}, Frame};
+
+use crate::{popup, DateRange, HistoryFormat};
+
+// The files offered for exports and imports, in the current directory
+const DEFAULT_PATH : &str = "tasks.md";
+const DEFAULT_HISTORY_PATH : &str = "completions";
+
+/// What the user asked for from the export pop-up
+pub enum ExportRequest {
+    /// Export the tasks shown, or the whole list, as a Markdown checklist
+    Markdown { path: PathBuf, whole_list: bool },
+    /// Add the unchecked items of a Markdown checklist as tasks
+    ImportMarkdown(PathBuf),
+    /// Export the tasks completed in `range`, from the list and its archive
+    History { path: PathBuf, format: HistoryFormat, range: DateRange },
+}
+
+#[derive(Clone, Copy)]
+enum Action {
+    ExportShown,
+    ExportList,
+    Import,
+    History(HistoryFormat, DateRange),
+}
+
+enum Prompt {
+    Menu,
+    Range(HistoryFormat),
+    Path(Action),
+}
+
+/// Pop-up for exporting the list to, and importing tasks from, other files
+#[derive(Default)]
+pub struct ExportView {
+    prompt: Option<Prompt>,
+    input: String,
+    error: Option<String>,
+    request: Option<ExportRequest>,
+}
+
+impl ExportView {
+
+    pub fn open(&mut self) {
+        self.show(Prompt::Menu, String::new());
+    }
+
+    fn show(&mut self, prompt: Prompt, input: String) {
+        self.prompt = Some(prompt);
+        self.input = input;
+        self.error = None;
+    }
+
+    pub fn hide(&mut self) {
+        self.prompt = None;
+        self.input.clear();
+    }
+
+    #[must_use]
+    pub fn is_visible(&self) -> bool {
+        self.prompt.is_some()
+    }
+
+    /// Returns the user's request, if one has been made since this was last called
+    pub fn take_request(&mut self) -> Option<ExportRequest> {
+        self.request.take()
+    }
+
+    /// Renders the pop-up, if visible, over the passed area
+    pub fn render(&self, frame: &mut Frame, area: Rect) {
+        let Some(prompt) = &self.prompt else {
+            return;
+        };
+        let (title, mut text) = match prompt {
+            Prompt::Menu => ("Export/import",
+                " v - Export the tasks shown as a Markdown checklist\n \
+                w - Export the whole list as a Markdown checklist\n \
+                i - Import a Markdown checklist\n \
+                c - Export the completion history as CSV\n \
+                j - Export the completion history as JSON Lines\n \
+                Esc - Close".to_string()),
+            Prompt::Range(_) => ("Completion history",
+                format!("Export tasks completed between which dates?  Enter FROM..TO, either of which \
+                    can be left out, or one date for just that day, as YYYY-MM-DD.  Leave it empty for \
+                    every day.\n\n Dates: {}_\n\n ENT - Continue\n Esc - Cancel", self.input)),
+            Prompt::Path(action) => {
+                let (title, verb) = match action {
+                    Action::ExportShown => ("Export the tasks shown", "Export"),
+                    Action::ExportList => ("Export the whole list", "Export"),
+                    Action::Import => ("Import a checklist", "Import"),
+                    Action::History(..) => ("Completion history", "Export"),
+                };
+                let explanation = match action {
+                    Action::Import => "Unchecked items are added to the bottom of the list, \
+                        unless the list already has a task to do just like it.",
+                    _ => "The file is replaced if it exists.",
+                };
+                (title, format!("{explanation}  Relative paths are from the current directory.\n\n \
+                    File: {}_\n\n ENT - {verb}\n Esc - Cancel", self.input))
+            },
+        };
+        if let Some(error) = &self.error {
+            text.push_str("\n\n");
+            text.push_str(error);
+        }
+        let inner = popup::render(frame, title, 60, 50, area);
+        frame.render_widget(Paragraph::new(text).wrap(Wrap { trim: false }), inner);
+    }
+
+    /// Attempts to handle keyboard input
+    /// Returns true if it was handled, false if caller should handle.
+    /// While visible, every key is handled here.
+    /// Once the user has made a request, it is available from ``take_request``.
+    pub fn handle_key(&mut self, key: KeyEvent) -> bool {
+        let Some(prompt) = &self.prompt else {
+            return false;
+        };
+        match prompt {
+            Prompt::Menu => match key.code {
+                KeyCode::Char('v') => self.ask_path(Action::ExportShown),
+                KeyCode::Char('w') => self.ask_path(Action::ExportList),
+                KeyCode::Char('i') => self.ask_path(Action::Import),
+                KeyCode::Char('c') => self.show(Prompt::Range(HistoryFormat::Csv), String::new()),
+                KeyCode::Char('j') => self.show(Prompt::Range(HistoryFormat::JsonLines), String::new()),
+                KeyCode::Esc | KeyCode::Char('q') => self.hide(),
+                _ => ()
+            },
+            Prompt::Range(format) => match key.code {
+                KeyCode::Char(c) => self.input.push(c),
+                KeyCode::Backspace => { self.input.pop(); },
+                KeyCode::Esc => self.hide(),
+                KeyCode::Enter => match DateRange::parse(&self.input) {
+                    Ok(range) => self.ask_path(Action::History(*format, range)),
+                    Err(error) => self.error = Some(error),
+                },
+                _ => ()
+            },
+            Prompt::Path(action) => match key.code {
+                KeyCode::Char(c) => self.input.push(c),
+                KeyCode::Backspace => { self.input.pop(); },
+                KeyCode::Esc => self.hide(),
+                KeyCode::Enter if !self.input.trim().is_empty() => {
+                    let path = PathBuf::from(self.input.trim());
+                    self.request = Some(match *action {
+                        Action::ExportShown => ExportRequest::Markdown { path, whole_list: false },
+                        Action::ExportList => ExportRequest::Markdown { path, whole_list: true },
+                        Action::Import => ExportRequest::ImportMarkdown(path),
+                        Action::History(format, range) => ExportRequest::History { path, format, range },
+                    });
+                    self.hide();
+                },
+                _ => ()
+            },
+        }
+        true
+    }
+
+    fn ask_path(&mut self, action: Action) {
+        let path = match action {
+            Action::History(HistoryFormat::Csv, _) => format!("{DEFAULT_HISTORY_PATH}.csv"),
+            Action::History(HistoryFormat::JsonLines, _) => format!("{DEFAULT_HISTORY_PATH}.jsonl"),
+            _ => DEFAULT_PATH.to_string(),
+        };
+        self.show(Prompt::Path(action), path);
+    }
+
+}
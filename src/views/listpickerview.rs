@@ -0,0 +1,157 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::{layout::{Constraint, Layout, Rect}, text::Line, widgets::{List, ListState}, Frame};
+use uuid::Uuid;
+
+use crate::{popup, TaskLists};
+
+/// What the user picked a list for
+pub enum ListPick {
+    /// Switch to the named list
+    Switch(String),
+    /// Move the task to the named list
+    MoveTask(Uuid, String),
+}
+
+/// Pop-up listing the named task lists, to switch to one, or to move the
+/// selected task to one.  New lists can be created from here too.
+#[derive(Default)]
+pub struct ListPickerView {
+    names: Option<Vec<String>>,
+    state: ListState,
+    // The task being moved, if we're picking a list to move it to
+    moving: Option<Uuid>,
+    // The name of a new list being entered
+    new_name: Option<String>,
+    error: Option<String>,
+    picked: Option<ListPick>,
+}
+
+impl ListPickerView {
+
+    /// Opens the pop-up to switch from the `current` list to another
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the lists can't be read
+    pub fn open_switch(&mut self, lists: &TaskLists, current: &str) -> std::io::Result<()> {
+        let names = lists.names()?;
+        let selected = names.iter().position(|n| n == current).unwrap_or(0);
+        self.show(names, selected, None);
+        Ok(())
+    }
+
+    /// Opens the pop-up to move the task `uuid` from the `current` list to another
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the lists can't be read
+    pub fn open_move(&mut self, lists: &TaskLists, current: &str, uuid: Uuid) -> std::io::Result<()> {
+        let names = lists.names()?.into_iter().filter(|n| n != current).collect();
+        self.show(names, 0, Some(uuid));
+        Ok(())
+    }
+
+    fn show(&mut self, names: Vec<String>, selected: usize, moving: Option<Uuid>) {
+        self.state.select(if names.is_empty() { None } else { Some(selected) });
+        self.names = Some(names);
+        self.moving = moving;
+        self.new_name = None;
+        self.error = None;
+    }
+
+    fn hide(&mut self) {
+        self.names = None;
+        self.moving = None;
+        self.new_name = None;
+    }
+
+    #[must_use]
+    pub fn is_visible(&self) -> bool {
+        self.names.is_some()
+    }
+
+    /// Renders the pop-up, if visible, over the passed area
+    pub fn render(&mut self, frame: &mut Frame, area: Rect) {
+        let Some(names) = &self.names else {
+            return;
+        };
+        let title = if self.moving.is_some() {
+            "Move task to list - ENT move, n new list, Esc close"
+        } else {
+            "Lists - ENT switch, n new list, Esc close"
+        };
+        let inner = popup::render(frame, title, 50, 50, area);
+        let [list_area, status_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(inner);
+        let list = if names.is_empty() {
+            List::new(["No other lists - n to create one"])
+        } else {
+            List::new(names.iter().map(String::as_str)).highlight_symbol(">> ")
+        };
+        frame.render_stateful_widget(list, list_area, &mut self.state);
+        let status = if let Some(new_name) = &self.new_name {
+            format!("New list: {new_name}_")
+        } else {
+            self.error.clone().unwrap_or_default()
+        };
+        frame.render_widget(Line::from(status), status_area);
+    }
+
+    /// Returns the list picked by the user, if one has been since this was last called
+    pub fn take_pick(&mut self) -> Option<ListPick> {
+        self.picked.take()
+    }
+
+    /// Attempts to handle keyboard input
+    /// Returns true if it was handled, false if caller should handle.
+    /// While visible, every key is handled here.
+    /// Once a list is picked, it is available from ``take_pick``.
+    pub fn handle_key(&mut self, key: KeyEvent, lists: &TaskLists) -> bool {
+        let Some(names) = &self.names else {
+            return false;
+        };
+        if let Some(new_name) = &mut self.new_name {
+            match key.code {
+                KeyCode::Char(c) => new_name.push(c),
+                KeyCode::Backspace => { new_name.pop(); },
+                KeyCode::Esc => self.new_name = None,
+                KeyCode::Enter => match lists.create(new_name) {
+                    Ok(_) => {
+                        let name = new_name.clone();
+                        self.pick(name);
+                    },
+                    Err(err) => {
+                        self.error = Some(err.to_string());
+                        self.new_name = None;
+                    },
+                },
+                _ => ()
+            }
+            return true;
+        }
+        match key.code {
+            KeyCode::Char('j') | KeyCode::Down => self.state.select_next(),
+            KeyCode::Char('k') | KeyCode::Up => self.state.select_previous(),
+            KeyCode::Char('n') => {
+                self.new_name = Some(String::new());
+                self.error = None;
+            },
+            KeyCode::Esc | KeyCode::Char('q') => self.hide(),
+            KeyCode::Enter => {
+                if let Some(name) = self.state.selected().and_then(|index| names.get(index)).cloned() {
+                    self.pick(name);
+                }
+            },
+            _ => ()
+        }
+        true
+    }
+
+    fn pick(&mut self, name: String) {
+        self.picked = Some(match self.moving {
+            Some(uuid) => ListPick::MoveTask(uuid, name),
+            None => ListPick::Switch(name),
+        });
+        self.hide();
+    }
+
+}
@@ -0,0 +1,52 @@
+use ratatui::{layout::Rect, widgets::{Paragraph, Wrap}, Frame};
+
+use crate::{popup, LockOwner};
+
+/// Pop-up asking whether to break a lock whose owner may no longer be running
+#[derive(Default)]
+pub struct LockView {
+    owner: Option<LockOwner>,
+    error: Option<String>,
+}
+
+impl LockView {
+
+    /// Shows the pop-up for a lock held by `owner`
+    pub fn show(&mut self, owner: LockOwner) {
+        self.owner = Some(owner);
+        self.error = None;
+    }
+
+    /// Shows an error from a failed attempt to break the lock, keeping the pop-up open
+    pub fn show_error(&mut self, error: &std::io::Error) {
+        self.error = Some(error.to_string());
+    }
+
+    pub fn hide(&mut self) {
+        self.owner = None;
+        self.error = None;
+    }
+
+    #[must_use]
+    pub fn is_visible(&self) -> bool {
+        self.owner.is_some()
+    }
+
+    /// Renders the pop-up, if visible, over the passed area
+    pub fn render(&self, frame: &mut Frame, area: Rect) {
+        if let Some(owner) = &self.owner {
+            let inner = popup::render(frame, "Task list is locked", 70, 50, area);
+            let mut text = format!(
+                "The task list is locked by {owner}.\n\n\
+                That instance may have crashed, or may still be running on another machine.  \
+                Only break the lock if you're sure it isn't running.\n\n b - Break lock and continue\n q - Quit"
+            );
+            if let Some(error) = &self.error {
+                text.push_str("\n\nCouldn't break lock: ");
+                text.push_str(error);
+            }
+            frame.render_widget(Paragraph::new(text).wrap(Wrap { trim: false }), inner);
+        }
+    }
+
+}
@@ -1,22 +1,57 @@
-use std::io::{Result, Stdout};
+use std::{io::{Result, Stdout}, path::{Path, PathBuf}, time::{Duration, Instant}};
 
-use crossterm::event::{self, KeyCode, KeyEventKind};
+use crossterm::event::{self, KeyCode, KeyEvent, KeyEventKind};
+use uuid::Uuid;
 use ratatui::{backend::CrosstermBackend,
     layout::{Constraint, Direction, Layout, Rect},
     style::{Stylize },
     widgets::{Block, Borders },
     Frame, Terminal};
 
-use crate::{taskdetailview, TaskEditView, TaskList, TaskListView, TaskDoneView, TaskNextView};
+use crate::{completions, from_markdown, open_store, taskdetailview, read_server_token, to_history, to_markdown, passphrase_from_env, Archive, ArchiveView, Config, ConflictView, EncryptionError, EncryptionRequest, EncryptionView, ExportRequest, ExportView, GitHistory, InstanceLock, Journal, JsonFileStore, ListPick, ListPickerView, LockAttempt, LockOwner, LockView, MemoryStore, Passphrase, RecoveryView, RemoteStore, SnapshotView, Snapshots, TaskEditView, TaskList, TaskListView, TaskLists, TaskDoneView, TaskNextView, TaskStore, DEFAULT_LIST};
+
+// How often to check the control socket while waiting for keys
+const CONTROL_POLL : Duration = Duration::from_millis(100);
 
 pub struct MainView {
-    tasks: TaskList,
-    load_failed: bool,
+    lists: TaskLists,
+    list_name: String,
+    config: Config,
+    // The directory holding the current list
+    data_dir: PathBuf,
+    tasks: TaskList<Box<dyn TaskStore>>,
+    lock: Option<InstanceLock>,
+    // When another instance holds the lock, we're just a viewer, periodically
+    // reloading the list
+    viewer: bool,
+    // The address of the server holding the list, when running as its client
+    remote: Option<String>,
+    locked_by: Option<LockOwner>,
+    viewer_reload: Duration,
+    last_reload: Instant,
     task_list_view: TaskListView,
     task_done_view: TaskDoneView,
     task_next_view: TaskNextView,
     task_edit_view: TaskEditView,
+    recovery_view: RecoveryView,
+    lock_view: LockView,
+    snapshot_view: SnapshotView,
+    conflict_view: ConflictView,
+    list_picker_view: ListPickerView,
+    archive_view: ArchiveView,
+    archive: Archive,
+    encryption_view: EncryptionView,
+    export_view: ExportView,
+    // The passphrase for encrypted lists, if we have one
+    passphrase: Option<Passphrase>,
+    snapshots: Snapshots,
+    // Commits the data directory to git, if enabled in the config
+    git_history: Option<GitHistory>,
+    // Commands from other programs, while we hold the lock
+    #[cfg(unix)]
+    control_socket: Option<crate::ControlSocket>,
     write_fails: i32,
+    notice: Option<String>,
     details_pane: bool,
     help_pane: bool,
 }
@@ -28,45 +63,503 @@ impl Default for MainView {
 }
 
 impl MainView {
+    /// Creates the view on the default list in the default data directory,
+    /// taking the lock on it and loading it if possible.  If another instance
+    /// holds the lock, the list is loaded read-only, for viewing.
     #[must_use]
     pub fn new() -> Self {
-        let (tasks, load_failed) = TaskList::load().map_or_else(|_| (TaskList::default(), true), |tl| (tl, false));
-        MainView {
-            tasks,
-            load_failed,
+        Self::open(JsonFileStore::default_dir(), DEFAULT_LIST)
+    }
+
+    /// Creates the view on the named list in `data_dir`, as for ``new``.
+    /// If the list doesn't exist yet, it is created when first saved.
+    #[must_use]
+    pub fn open(data_dir: PathBuf, list_name: &str) -> Self {
+        let mut main_view = Self::build(data_dir);
+        if main_view.config.git.enabled {
+            match GitHistory::open(main_view.lists.root(), &main_view.config.git) {
+                Ok(git_history) => main_view.git_history = Some(git_history),
+                Err(err) => main_view.notice = Some(format!("Git history disabled: {err}")),
+            }
+        }
+        main_view.open_list(list_name);
+        main_view
+    }
+
+    /// Creates the view as a client of the ``Server`` at `addr`, which holds
+    /// the list named `list_name` in `data_dir`, and has left its token there.
+    /// Other lists, snapshots, the archive and encryption settings are the
+    /// server's business, so aren't available.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the server's token can't be read, or the list can't be
+    /// loaded from the server.
+    pub fn connect(data_dir: PathBuf, list_name: &str, addr: &str) -> Result<Self> {
+        let mut main_view = Self::build(data_dir);
+        let token = read_server_token(&main_view.lists.dir(list_name)?)?;
+        main_view.tasks = TaskList::new(Box::new(RemoteStore::new(addr, token)));
+        main_view.tasks.reload()?;
+        main_view.remote = Some(addr.to_string());
+        Ok(main_view)
+    }
+
+    /// Creates the view, with settings from `data_dir`, but no list
+    fn build(data_dir: PathBuf) -> Self {
+        let lists = TaskLists::new(data_dir);
+        let (config, config_error) = Config::load_or_default(lists.root());
+        let mut main_view = MainView {
+            data_dir: PathBuf::default(),
+            tasks: TaskList::new(Box::new(MemoryStore::default())),
+            lock: None,
+            viewer: false,
+            remote: None,
+            locked_by: None,
+            viewer_reload: Duration::from_secs(config.viewer_reload_secs),
+            last_reload: Instant::now(),
             task_list_view: TaskListView::default(),
             task_done_view: TaskDoneView::default(),
             task_edit_view: TaskEditView::default(),
             task_next_view: TaskNextView::default(),
+            recovery_view: RecoveryView::default(),
+            lock_view: LockView::default(),
+            snapshot_view: SnapshotView::default(),
+            conflict_view: ConflictView::default(),
+            list_picker_view: ListPickerView::default(),
+            archive_view: ArchiveView::default(),
+            archive: Archive::new(lists.root(), config.archive.period),
+            encryption_view: EncryptionView::default(),
+            export_view: ExportView::default(),
+            passphrase: None,
+            snapshots: Snapshots::new(lists.root(), config.snapshots.clone()),
+            git_history: None,
+            #[cfg(unix)]
+            control_socket: None,
             write_fails: i32::default(),
+            notice: None,
             details_pane: bool::default(),
             help_pane: bool::default(),
+            list_name: String::default(),
+            lists,
+            config,
+        };
+        match passphrase_from_env() {
+            Ok(passphrase) => main_view.passphrase = passphrase,
+            Err(err) => main_view.notice = Some(format!("Couldn't read the keyfile: {err}")),
+        }
+        // Without its settings, git history, archiving and replication are
+        // off, which mustn't go unnoticed
+        if let Some(err) = config_error {
+            main_view.notice = Some(format!("{err}. Using the default settings"));
+        }
+        main_view
+    }
+
+    /// Switches to the named list, releasing the lock on the current one, and
+    /// taking the lock on the new one and loading it if possible.  If another
+    /// instance holds the lock, the list is loaded read-only, for viewing.
+    fn open_list(&mut self, list_name: &str) {
+        self.commit_to_git(true);
+        if let Some(git_history) = &mut self.git_history {
+            git_history.forget();
+        }
+        self.close_control_socket();
+        self.lock = None;
+        self.viewer = false;
+        self.locked_by = None;
+        self.tasks = TaskList::new(Box::new(MemoryStore::default()));
+        self.tasks.set_read_only(true);
+        self.task_list_view = TaskListView::default();
+        self.task_edit_view = TaskEditView::default();
+        self.recovery_view = RecoveryView::default();
+        self.lock_view = LockView::default();
+        self.snapshot_view = SnapshotView::default();
+        self.conflict_view = ConflictView::default();
+        self.archive_view = ArchiveView::default();
+        self.encryption_view = EncryptionView::default();
+        self.list_name = list_name.to_string();
+        self.data_dir = match self.lists.dir(list_name) {
+            Ok(dir) => dir,
+            Err(err) => {
+                self.notice = Some(err.to_string());
+                return;
+            }
+        };
+        self.snapshots = Snapshots::new(&self.data_dir, self.config.snapshots.clone());
+        self.archive = Archive::new(&self.data_dir, self.config.archive.period);
+        match InstanceLock::acquire(&self.data_dir) {
+            Ok(LockAttempt::Acquired(lock)) => {
+                self.lock = Some(lock);
+                self.load();
+            },
+            Ok(LockAttempt::Ambiguous(owner)) => self.lock_view.show(owner),
+            Ok(LockAttempt::Held(owner)) => {
+                self.viewer = true;
+                self.locked_by = owner;
+                if let Some(problem) = self.load_for_viewing() {
+                    self.ask_unlock(problem);
+                }
+            },
+            Err(err) => self.notice = Some(format!("Couldn't open list {list_name}: {err}")),
+        }
+    }
+
+    /// Moves the task `uuid` to the bottom of the named list.  The other list
+    /// must not be in use by another instance.  Moves can't be undone.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if this list is read-only, or the other list can't be
+    /// loaded or written, or the write to storage fails
+    fn move_task(&mut self, uuid: Uuid, list_name: &str) -> Result<()> {
+        self.tasks.check_writable()?;
+        let Some(task) = self.tasks.get(uuid).cloned() else {
+            return Ok(());
+        };
+        let dir = self.lists.dir(list_name)?;
+        let LockAttempt::Acquired(_lock) = InstanceLock::acquire(&dir)? else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::WouldBlock,
+                format!("List {list_name} is in use by another instance"),
+            ));
+        };
+        let mut other_tasks = TaskList::load(self.open_store(&dir, false)?)?;
+        if !other_tasks.store().is_encrypted() {
+            // Not being able to keep the journal mustn't stop us using the list
+            let _ = other_tasks.set_journal(Journal::new(&dir));
+        }
+        other_tasks.add(task)?;
+        // Undo would only bring the task back here, leaving it in both lists
+        self.tasks.drop_tasks(&[uuid])?;
+        self.task_list_view.fix_selection(&self.tasks);
+        self.notice = Some(format!("Moved task to {list_name}"));
+        Ok(())
+    }
+
+    /// Acts on a list picked in the list picker
+    fn list_picked(&mut self, pick: ListPick) {
+        match pick {
+            ListPick::Switch(list_name) => {
+                if list_name != self.list_name {
+                    self.open_list(&list_name);
+                }
+            },
+            ListPick::MoveTask(uuid, list_name) => {
+                if let Err(err) = self.move_task(uuid, &list_name) {
+                    self.notice = Some(format!("Couldn't move task: {err}"));
+                }
+            },
+        }
+    }
+
+    /// Opens the store for the list in `dir`, giving it our passphrase, if we
+    /// have one, in case the list is encrypted
+    fn open_store(&self, dir: &Path, read_only: bool) -> Result<Box<dyn TaskStore>> {
+        let mut store = open_store(dir, read_only, self.config.replicated)?;
+        if let Some(passphrase) = &self.passphrase {
+            // Stores that don't support encryption have nothing to unlock
+            let _ = store.unlock(passphrase.clone());
+        }
+        Ok(store)
+    }
+
+    /// Loads the task list read-only, without touching the files on disk.
+    /// If loading fails, we keep whatever we had before, which may be nothing.
+    /// Returns the problem if the list couldn't be loaded because it's encrypted.
+    fn load_for_viewing(&mut self) -> Option<EncryptionError> {
+        let mut problem = None;
+        if let Ok(store) = self.open_store(&self.data_dir, true) {
+            let mut tasks = TaskList::new(store);
+            tasks.set_read_only(true);
+            match tasks.reload() {
+                Ok(()) => {
+                    self.tasks = tasks;
+                    if self.task_list_view.selected_uuid().is_some() {
+                        self.task_list_view.fix_selection(&self.tasks);
+                    }
+                },
+                Err(err) => problem = EncryptionError::of(&err),
+            }
+        }
+        self.last_reload = Instant::now();
+        problem
+    }
+
+    /// Loads the task list, once we hold the lock, recovering from a failed
+    /// load if necessary.  If the store can't be opened, the list is shown
+    /// read-only, if it can be read at all, so no edits are made that can't
+    /// be saved.
+    fn load(&mut self) {
+        let store = match self.open_store(&self.data_dir, false) {
+            Ok(store) => store,
+            Err(err) => {
+                self.notice = Some(format!("Couldn't open list {}, so it's read-only: {err}", self.list_name));
+                let _ = self.load_for_viewing();
+                return;
+            },
+        };
+        self.tasks = TaskList::new(store);
+        self.reload();
+        self.open_control_socket();
+    }
+
+    /// Listens for commands from other programs on the list's control socket
+    #[cfg(unix)]
+    fn open_control_socket(&mut self) {
+        self.control_socket = None;
+        match crate::ControlSocket::bind(&self.data_dir) {
+            Ok(control_socket) => self.control_socket = Some(control_socket),
+            Err(err) => self.notice = Some(format!("Control socket unavailable: {err}")),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn open_control_socket(&mut self) {}
+
+    #[cfg(unix)]
+    fn close_control_socket(&mut self) {
+        self.control_socket = None;
+    }
+
+    #[cfg(not(unix))]
+    fn close_control_socket(&mut self) {}
+
+    /// Carries out any commands received on the control socket, undoable
+    /// together like a key press.  Returns true if the list may have changed.
+    #[cfg(unix)]
+    fn service_control_socket(&mut self) -> bool {
+        if !self.control_socket.as_mut().is_some_and(crate::ControlSocket::receive) {
+            return false;
+        }
+        // Pick up anything changed elsewhere, so a command doesn't overwrite it
+        self.merge_external_changes();
+        let Some(control_socket) = &mut self.control_socket else {
+            return false;
+        };
+        self.tasks.begin_action(self.task_list_view.selected_uuid());
+        let changed = control_socket.service(&mut self.tasks);
+        self.tasks.end_action(self.task_list_view.selected_uuid());
+        if changed {
+            self.task_list_view.fix_selection(&self.tasks);
+        }
+        changed
+    }
+
+    #[cfg(not(unix))]
+    fn service_control_socket(&mut self) -> bool {
+        false
+    }
+
+    /// Reloads the task list we hold the lock on, recovering from a failure if
+    /// necessary, or asking for the passphrase if it's encrypted.
+    fn reload(&mut self) {
+        if let Err(err) = self.tasks.reload() {
+            if let Some(problem) = EncryptionError::of(&err) {
+                // Nothing can be saved until we have the passphrase
+                self.tasks.set_read_only(true);
+                self.ask_unlock(problem);
+                return;
+            }
+            if err.kind() == std::io::ErrorKind::Unsupported {
+                // Written by a newer version, so must be left alone
+                self.tasks.set_read_only(true);
+                self.recovery_view.show_unrecoverable(err.to_string());
+            } else {
+                self.recovery_view.show(self.tasks.recover(&err));
+            }
+        }
+        // The journal and archive aren't encrypted, so aren't kept for
+        // encrypted lists
+        if self.tasks.store().is_encrypted() {
+            if self.git_history.is_some() {
+                self.notice = Some("Git history isn't kept for encrypted lists".to_string());
+            }
+        } else {
+            // Not being able to keep the journal mustn't stop us using the list
+            let _ = self.tasks.set_journal(Journal::new(&self.data_dir));
+            if let Some(after_days) = self.config.archive.after_days {
+                if self.archive.archive_completed(&mut self.tasks, after_days).is_err() {
+                    self.write_fails += 1;
+                }
+            }
+        }
+    }
+
+    fn ask_unlock(&mut self, problem: EncryptionError) {
+        self.encryption_view.ask_unlock((problem == EncryptionError::WrongPassphrase).then(|| problem.to_string()));
+    }
+
+    /// Acts on a request made from the encryption pop-up
+    fn encryption_requested(&mut self, request: EncryptionRequest) {
+        match request {
+            EncryptionRequest::Unlock(passphrase) => {
+                self.passphrase = Some(passphrase.clone());
+                if self.viewer {
+                    if let Some(problem) = self.load_for_viewing() {
+                        self.ask_unlock(problem);
+                    }
+                } else {
+                    // Stores that don't support encryption have nothing to unlock
+                    let _ = self.tasks.store_mut().unlock(passphrase);
+                    self.tasks.set_read_only(false);
+                    self.reload();
+                }
+            },
+            EncryptionRequest::SetPassphrase(passphrase) => {
+                let encrypting = passphrase.is_some();
+                match self.tasks.set_encryption(passphrase.clone()) {
+                    Ok(()) => {
+                        self.passphrase = passphrase;
+                        if encrypting {
+                            self.tasks.clear_journal();
+                            let deleted = self.unencrypted_copies()
+                                .and_then(|copies| copies.iter().try_for_each(std::fs::remove_file));
+                            self.notice = Some(match deleted {
+                                Ok(()) => "Task list encrypted".to_string(),
+                                Err(err) => format!("Task list encrypted, but unencrypted copies remain: {err}"),
+                            });
+                        } else {
+                            let _ = self.tasks.set_journal(Journal::new(&self.data_dir));
+                            self.notice = Some("Task list no longer encrypted".to_string());
+                        }
+                    },
+                    Err(err) => self.notice = Some(format!("Couldn't change encryption: {err}")),
+                }
+            },
+        }
+    }
+
+    /// Returns the files holding unencrypted copies of the list, which are
+    /// deleted when it's encrypted: the journal, snapshots, archive, and any
+    /// kept by the store
+    fn unencrypted_copies(&self) -> Result<Vec<PathBuf>> {
+        let mut copies = self.tasks.store().unencrypted_copies()?;
+        copies.extend(Journal::new(&self.data_dir).files());
+        copies.extend(self.snapshots.list()?.iter().map(|snapshot| snapshot.path().to_path_buf()));
+        copies.extend(self.archive.files()?);
+        Ok(copies)
+    }
+
+    /// Describes `copies` for the user, as paths within the list's directory,
+    /// counting the files in each subdirectory
+    fn describe_copies(&self, copies: &[PathBuf]) -> Vec<String> {
+        let mut described: Vec<(String, usize, bool)> = vec![];
+        for copy in copies {
+            let relative = copy.strip_prefix(&self.data_dir).unwrap_or(copy);
+            let mut components = relative.components();
+            let first = components.next().map(|first| first.as_os_str().to_string_lossy().to_string()).unwrap_or_default();
+            let name = if components.next().is_some() { format!("{first}/") } else { first };
+            match described.iter_mut().find(|(described, _, _)| *described == name) {
+                Some((_, count, _)) => *count += 1,
+                None => described.push((name, 1, copy.starts_with(self.archive.dir()))),
+            }
         }
+        described.into_iter().map(|(name, count, archived)| {
+            let files = if count == 1 { "1 file".to_string() } else { format!("{count} files") };
+            if archived {
+                format!("{name} ({files}: export the completion history first to keep them)")
+            } else if name.ends_with('/') {
+                format!("{name} ({files})")
+            } else {
+                name
+            }
+        }).collect()
     }
 
+    /// Runs the UI until the user quits.
+    /// Returns false if we couldn't run because the lock couldn't be checked.
     pub fn run(&mut self, terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> bool{
-        if TaskList::check_lock_file().is_err() {
+        if !self.owns_list() && !self.viewer && !self.lock_view.is_visible() {
             return false;
         }
         loop {
+            if self.viewer && self.last_reload.elapsed() >= self.viewer_reload {
+                let _ = self.load_for_viewing();
+            }
+            if self.owns_list() {
+                self.merge_external_changes();
+            }
             match self.tasks.pre_render() {
                 Ok(()) => (),
                 _ => self.write_fails += 1
             }
+            // Snapshots aren't encrypted, so aren't taken of encrypted lists,
+            // and the server takes its own
+            if !self.tasks.is_read_only() && !self.tasks.store().is_encrypted() && self.remote.is_none()
+                    && self.snapshots.take_if_due(self.tasks.tasks()).is_err() {
+                self.write_fails += 1;
+            }
+            self.commit_to_git(false);
             self.render(terminal);
             match self.check_events() {
                 Ok(true) => break,
                 Ok(false) => (),
+                Err(err) if err.kind() == std::io::ErrorKind::PermissionDenied =>
+                    self.notice = Some("Read-only: changes are disabled".to_string()),
+                // A write refused because the list had been changed elsewhere
+                // is retried, with those changes merged in
+                Err(_) if self.owns_list() && self.merge_external_changes() => (),
                 _ => self.write_fails += 1
             }
         }
-        // Ignore the (unlikely) error.  The failure will affect subsequent runs, and we'll
-        // see an error then which we can resolve by manually deleting the lock,
-        // if appropriate.
-        let _ = TaskList::clear_lock_file();
+        self.commit_to_git(true);
+        self.close_control_socket();
+        // Dropping the lock releases it
+        self.lock = None;
         true
     }
 
+    /// Returns true if the list is ours to change: we hold its lock, or are a
+    /// client of the server that does
+    fn owns_list(&self) -> bool {
+        self.lock.is_some() || self.remote.is_some()
+    }
+
+    /// Commits the list to git if it's due, or straight away if `now`, as long
+    /// as it's ours to change, and isn't encrypted.  Git history is turned off
+    /// if committing fails.
+    fn commit_to_git(&mut self, now: bool) {
+        let Some(git_history) = &mut self.git_history else {
+            return;
+        };
+        if self.lock.is_none() || self.tasks.is_read_only() || self.tasks.store().is_encrypted() {
+            return;
+        }
+        let files = self.tasks.store().task_files();
+        let result = if now {
+            git_history.commit_now(self.tasks.tasks(), &files)
+        } else {
+            git_history.commit_if_due(self.tasks.tasks(), &files)
+        };
+        if let Err(err) = result {
+            self.git_history = None;
+            self.notice = Some(format!("Git history disabled: {err}"));
+        }
+    }
+
+    /// Merges in any changes made to the task list by other programs, and
+    /// writes the result, keeping the selected task selected, and showing any
+    /// conflicting edits.  Returns true if there were changes, and they were
+    /// merged.
+    fn merge_external_changes(&mut self) -> bool {
+        match self.tasks.merge_external_changes() {
+            Ok(Some(conflicts)) => {
+                if self.task_list_view.selected_uuid().is_some() {
+                    self.task_list_view.fix_selection(&self.tasks);
+                }
+                self.notice = Some("Merged changes made by another program".to_string());
+                self.conflict_view.show(conflicts);
+                true
+            },
+            Ok(None) => false,
+            // Until they can be loaded, the changes aren't overwritten
+            Err(err) => {
+                self.notice = Some(format!("Couldn't merge changes made by another program: {err}"));
+                false
+            },
+        }
+    }
+
     pub fn render_help(&self, frame: &mut Frame, area: Rect) {
         let block = Block::new().title("Help").borders(Borders::all());
         let inner = block.inner(area);
@@ -116,48 +609,223 @@ impl MainView {
                     Constraint::Length(1),
                 ]
                 ).split(area);
-            frame.render_widget(Block::new().borders(Borders::TOP).title("Tasks ".bold()), main_layout[0]);
+            let title = if let Some(addr) = &self.remote {
+                format!("Tasks @ {addr} ")
+            } else if self.list_name == DEFAULT_LIST {
+                "Tasks ".to_string()
+            } else {
+                format!("Tasks: {} ", self.list_name)
+            };
+            frame.render_widget(Block::new().borders(Borders::TOP).title(title.bold()), main_layout[0]);
             self.render_panes(frame, main_layout[1]);
             self.task_edit_view.render(frame, main_layout[2]);
             frame.render_widget(Block::new().borders(Borders::TOP).title(
                     if self.write_fails > 0 {
                         format!("** ERROR: Write failed {0} times", self.write_fails)
                     }
-                    else if self.load_failed {
-                        "** ERROR: Load failed - started with empty task list".to_string()
+                    else if let Some(notice) = &self.notice {
+                        format!("** {notice}")
+                    }
+                    else if self.viewer {
+                        format!("** READ ONLY: Locked by {} - viewing only, q = quit",
+                            self.locked_by.as_ref().map_or_else(|| "another instance".to_string(), ToString::to_string))
+                    }
+                    else if self.tasks.is_read_only() {
+                        "** READ ONLY: Changes are disabled".to_string()
                     } else {
                         "j/k = down/up, . = dot, q = quit".to_string()
                     }
                     ), main_layout[3]);
+            self.snapshot_view.render(frame, area);
+            self.conflict_view.render(frame, area);
+            self.list_picker_view.render(frame, area);
+            self.archive_view.render(frame, area);
+            self.encryption_view.render(frame, area);
+            self.export_view.render(frame, area);
+            self.recovery_view.render(frame, area);
+            self.lock_view.render(frame, area);
         });
     }
 
+    /// Waits up to a second for a key press, meanwhile carrying out commands
+    /// from the control socket as they arrive.
     /// Returns Ok(false) normally, Ok(true) if we're to quit.
     ///
     /// # Errors
     /// Returns an error if an activity results in a write fail
     fn check_events(&mut self) -> Result<bool> {
-        if event::poll(std::time::Duration::from_millis(1000))? {
-            if let event::Event::Key(key) = event::read()? {
-                if key.kind == KeyEventKind::Press
-                        && !self.task_edit_view.handle_key(key, &mut self.tasks, &mut self.task_list_view)?
-                        && !self.task_list_view.handle_key(key, &mut self.tasks)?
-                {
-                    match key.code {
-                        KeyCode::Char('q') => return Ok(true),
-                        KeyCode::Char('p') => self.details_pane = !self.details_pane,
-                        KeyCode::Char('h') => self.help_pane = !self.help_pane,
-                        KeyCode::Char('f') => {
-                            self.tasks.toggle_future_filter();
-                            self.task_list_view.fix_selection(&self.tasks);
-                        },
-                        KeyCode::Char('o') => {
-                            self.tasks.toggle_dotted_only();
-                            self.task_list_view.fix_selection(&self.tasks);
+        let waited = Instant::now();
+        while !event::poll(CONTROL_POLL)? {
+            if self.service_control_socket() || waited.elapsed() >= Duration::from_secs(1) {
+                return Ok(false);
+            }
+        }
+        if let event::Event::Key(key) = event::read()? {
+            if key.kind == KeyEventKind::Press {
+                self.notice = None;
+                // Pick up anything changed elsewhere while we waited, so
+                // this key press doesn't overwrite it
+                if self.owns_list() {
+                    self.merge_external_changes();
+                }
+                // Everything a key press changes is undone together
+                self.tasks.begin_action(self.task_list_view.selected_uuid());
+                let result = self.handle_key(key);
+                self.tasks.end_action(self.task_list_view.selected_uuid());
+                return result;
+            }
+        }
+        Ok(false)
+    }
+
+    /// Handles keys while asking whether to break the lock.
+    /// Returns true if we're to quit.
+    fn handle_lock_key(&mut self, key: KeyEvent) -> bool {
+        match key.code {
+            KeyCode::Char('b') => match InstanceLock::break_lock(&self.data_dir) {
+                Ok(lock) => {
+                    self.lock = Some(lock);
+                    self.lock_view.hide();
+                    self.load();
+                },
+                Err(err) => self.lock_view.show_error(&err),
+            },
+            KeyCode::Char('q') => return true,
+            _ => ()
+        }
+        false
+    }
+
+    /// Carries out an export or import the user asked for, saying how it went.
+    ///
+    /// # Errors
+    /// Returns an error if importing results in a write fail
+    fn export_requested(&mut self, request: ExportRequest) -> Result<()> {
+        match request {
+            ExportRequest::Markdown { path, whole_list } => {
+                let (exported, count) = if whole_list {
+                    (to_markdown(self.tasks.tasks()), self.tasks.tasks().len())
+                } else {
+                    (to_markdown(self.tasks.filtered_tasks()), self.tasks.filtered_tasks().count())
+                };
+                self.notice = Some(match std::fs::write(&path, exported) {
+                    Ok(()) => format!("Exported {count} tasks to {}", path.display()),
+                    Err(err) => format!("Couldn't export to {}: {err}", path.display()),
+                });
+            },
+            ExportRequest::ImportMarkdown(path) => {
+                let text = match std::fs::read_to_string(&path) {
+                    Ok(text) => text,
+                    Err(err) => {
+                        self.notice = Some(format!("Couldn't import {}: {err}", path.display()));
+                        return Ok(());
+                    },
+                };
+                let (imported, report) = from_markdown(&text);
+                let added = self.tasks.add_imported(imported, true)?;
+                self.task_list_view.fix_selection(&self.tasks);
+                let mut notice = format!("Imported {added} tasks from {}", path.display());
+                for line in report {
+                    notice.push_str("; ");
+                    notice.push_str(&line);
+                }
+                self.notice = Some(notice);
+            },
+            ExportRequest::History { path, format, range } => {
+                // The archive isn't kept for encrypted lists, and a server
+                // keeps its own
+                let archived = if self.remote.is_none() && !self.tasks.store().is_encrypted() {
+                    match self.archive.load_all() {
+                        Ok(archived) => archived,
+                        Err(err) => {
+                            self.notice = Some(format!("Couldn't read the archive: {err}"));
+                            return Ok(());
                         },
-                        _ => ()
                     }
-                }
+                } else {
+                    vec![]
+                };
+                let completed = completions(self.tasks.tasks().iter().chain(&archived), range);
+                self.notice = Some(match to_history(&completed, format).and_then(|history| std::fs::write(&path, history)) {
+                    Ok(()) => format!("Exported {} completed tasks to {}", completed.len(), path.display()),
+                    Err(err) => format!("Couldn't export to {}: {err}", path.display()),
+                });
+            },
+        }
+        Ok(())
+    }
+
+    /// Returns Ok(false) normally, Ok(true) if we're to quit.
+    ///
+    /// # Errors
+    /// Returns an error if handling the key results in a write fail
+    fn handle_key(&mut self, key: KeyEvent) -> Result<bool> {
+        if self.lock_view.is_visible() {
+            return Ok(self.handle_lock_key(key));
+        }
+        if self.encryption_view.handle_key(key) {
+            if let Some(request) = self.encryption_view.take_request() {
+                self.encryption_requested(request);
+            }
+            return Ok(false);
+        }
+        if self.export_view.handle_key(key) {
+            if let Some(request) = self.export_view.take_request() {
+                self.export_requested(request)?;
+            }
+            return Ok(false);
+        }
+        if self.archive_view.handle_key(key) {
+            return Ok(false);
+        }
+        if self.list_picker_view.handle_key(key, &self.lists) {
+            if let Some(pick) = self.list_picker_view.take_pick() {
+                self.list_picked(pick);
+            }
+            return Ok(false);
+        }
+        if !self.recovery_view.handle_key(key, &mut self.tasks)?
+                && !self.conflict_view.handle_key(key, &mut self.tasks, &mut self.task_list_view)?
+                && !self.snapshot_view.handle_key(key, &mut self.tasks, &mut self.task_list_view, &mut self.snapshots)?
+                && !self.task_edit_view.handle_key(key, &mut self.tasks, &mut self.task_list_view)?
+                && !self.task_list_view.handle_key(key, &mut self.tasks)?
+        {
+            match key.code {
+                KeyCode::Char('q') => return Ok(true),
+                KeyCode::Char('p') => self.details_pane = !self.details_pane,
+                KeyCode::Char('h') => self.help_pane = !self.help_pane,
+                KeyCode::Char('f') => {
+                    self.tasks.toggle_future_filter();
+                    self.task_list_view.fix_selection(&self.tasks);
+                },
+                KeyCode::Char('o') => {
+                    self.tasks.toggle_dotted_only();
+                    self.task_list_view.fix_selection(&self.tasks);
+                },
+                KeyCode::Char('S' | 'E' | 'A' | 'l' | 'M') if self.remote.is_some() =>
+                    self.notice = Some("Not available while connected to a server".to_string()),
+                KeyCode::Char('S') => self.snapshot_view.open(&self.snapshots, self.tasks.tasks())?,
+                KeyCode::Char('E') => {
+                    self.tasks.check_writable()?;
+                    if self.git_history.is_some() && !self.tasks.store().is_encrypted() {
+                        self.notice = Some("Turn off git history first: commits of the list stay unencrypted".to_string());
+                    } else if self.tasks.store().supports_encryption() {
+                        let copies = self.describe_copies(&self.unencrypted_copies()?);
+                        self.encryption_view.open_menu(self.tasks.store().is_encrypted(), copies);
+                    } else {
+                        self.notice = Some("This list's storage doesn't support encryption".to_string());
+                    }
+                },
+                KeyCode::Char('A') => self.archive_view.open(&self.archive)?,
+                KeyCode::Char('l') => self.list_picker_view.open_switch(&self.lists, &self.list_name)?,
+                KeyCode::Char('M') => {
+                    if let Some(uuid) = self.task_list_view.selected_uuid() {
+                        self.tasks.check_writable()?;
+                        self.list_picker_view.open_move(&self.lists, &self.list_name, uuid)?;
+                    }
+                },
+                KeyCode::Char('X') => self.export_view.open(),
+                _ => ()
             }
         }
         Ok(false)
@@ -17,3 +17,27 @@ pub use tasknextview::*;
 
 pub mod popup;
 
+
+pub mod recoveryview;
+pub use recoveryview::*;
+
+pub mod snapshotview;
+pub use snapshotview::*;
+
+pub mod lockview;
+pub use lockview::*;
+
+pub mod conflictview;
+pub use conflictview::*;
+
+pub mod listpickerview;
+pub use listpickerview::*;
+
+pub mod archiveview;
+pub use archiveview::*;
+
+pub mod encryptionview;
+pub use encryptionview::*;
+
+pub mod exportview;
+pub use exportview::*;
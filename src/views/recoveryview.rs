@@ -0,0 +1,70 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::{layout::Rect, widgets::{Paragraph, Wrap}, Frame};
+
+use crate::{popup, TaskList, TaskStore};
+
+/// Pop-up shown when the task list couldn't be loaded, explaining what was
+/// recovered.  The list stays read-only until the user accepts it here.
+#[derive(Default)]
+pub struct RecoveryView {
+    report: Option<String>,
+    can_accept: bool,
+}
+
+impl RecoveryView {
+
+    /// Shows the pop-up with a report of what was recovered
+    pub fn show(&mut self, report: String) {
+        self.report = Some(report);
+        self.can_accept = true;
+    }
+
+    /// Shows the pop-up for a problem that can't be recovered from here, so
+    /// the user can only quit
+    pub fn show_unrecoverable(&mut self, report: String) {
+        self.report = Some(report);
+        self.can_accept = false;
+    }
+
+    #[must_use]
+    pub fn is_visible(&self) -> bool {
+        self.report.is_some()
+    }
+
+    /// Renders the pop-up, if visible, over the passed area
+    pub fn render(&self, frame: &mut Frame, area: Rect) {
+        if let Some(report) = &self.report {
+            let inner = popup::render(frame, "Load failed", 70, 50, area);
+            let text = if self.can_accept {
+                format!("{report}\n\nNothing will be saved until you accept this.\n\n y - Accept and resume saving\n q - Quit without saving")
+            } else {
+                format!("{report}\n\nNothing will be saved.\n\n q - Quit")
+            };
+            frame.render_widget(Paragraph::new(text).wrap(Wrap { trim: false }), inner);
+        }
+    }
+
+    /// Attempts to handle keyboard input
+    /// Returns true if it was handled, false if caller should handle.
+    /// While visible, every key except `q` is handled here.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the user accepted the recovered list, but the write to storage fails
+    pub fn handle_key<S: TaskStore>(&mut self, key: KeyEvent, task_list: &mut TaskList<S>) -> std::io::Result<bool> {
+        if self.report.is_none() {
+            return Ok(false);
+        }
+        match key.code {
+            KeyCode::Char('y') if self.can_accept => {
+                self.report = None;
+                task_list.set_read_only(false);
+                task_list.save_all()?;
+            },
+            KeyCode::Char('q') => return Ok(false),
+            _ => ()
+        }
+        Ok(true)
+    }
+
+}
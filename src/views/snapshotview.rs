@@ -0,0 +1,98 @@
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::{layout::Rect, widgets::{List, ListState}, Frame};
+
+use crate::{popup, Snapshot, SnapshotDiff, Snapshots, Task, TaskList, TaskListView, TaskStore};
+
+struct SnapshotEntry {
+    snapshot: Snapshot,
+    summary: String,
+}
+
+/// Pop-up listing the rolling snapshots, from which one can be restored
+#[derive(Default)]
+pub struct SnapshotView {
+    entries: Option<Vec<SnapshotEntry>>,
+    state: ListState,
+}
+
+impl SnapshotView {
+
+    /// Opens the pop-up, summarising each snapshot against the current `tasks`
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if the snapshots can't be listed
+    pub fn open(&mut self, snapshots: &Snapshots, tasks: &[Task]) -> std::io::Result<()> {
+        let entries = snapshots.list()?.into_iter().map(|snapshot| {
+            let taken = snapshot.taken().format("%Y-%m-%d %H:%M:%S");
+            let summary = match snapshot.load() {
+                Ok(snapshot_tasks) => {
+                    let diff = SnapshotDiff::between(tasks, &snapshot_tasks);
+                    format!("{taken}  {:>5} tasks  vs current: +{} -{} ~{}",
+                        snapshot_tasks.len(), diff.added, diff.removed, diff.changed)
+                },
+                Err(err) => format!("{taken}  unreadable ({err})"),
+            };
+            SnapshotEntry { snapshot, summary }
+        }).collect::<Vec<_>>();
+        self.state.select(if entries.is_empty() { None } else { Some(0) });
+        self.entries = Some(entries);
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn is_visible(&self) -> bool {
+        self.entries.is_some()
+    }
+
+    /// Renders the pop-up, if visible, over the passed area
+    pub fn render(&mut self, frame: &mut Frame, area: Rect) {
+        if let Some(entries) = &self.entries {
+            let inner = popup::render(frame, "Snapshots - ENT restore, Esc close", 80, 60, area);
+            let list = if entries.is_empty() {
+                List::new(["No snapshots yet"])
+            } else {
+                List::new(entries.iter().map(|e| e.summary.clone())).highlight_symbol(">> ")
+            };
+            frame.render_stateful_widget(list, inner, &mut self.state);
+        }
+    }
+
+    /// Attempts to handle keyboard input
+    /// Returns true if it was handled, false if caller should handle.
+    /// While visible, every key is handled here.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Err` if a snapshot was restored, but it couldn't be read, or the
+    /// write to storage failed
+    pub fn handle_key<S: TaskStore>(
+            &mut self,
+            key: KeyEvent,
+            task_list: &mut TaskList<S>,
+            task_list_view: &mut TaskListView,
+            snapshots: &mut Snapshots)
+                -> std::io::Result<bool> {
+        let Some(entries) = &self.entries else {
+            return Ok(false);
+        };
+        match key.code {
+            KeyCode::Char('j') | KeyCode::Down => self.state.select_next(),
+            KeyCode::Char('k') | KeyCode::Up => self.state.select_previous(),
+            KeyCode::Esc | KeyCode::Char('q') => self.entries = None,
+            KeyCode::Enter => {
+                if let Some(entry) = self.state.selected().and_then(|index| entries.get(index)) {
+                    let restored = entry.snapshot.load()?;
+                    // Snapshot the current list first, so the restore can itself be undone
+                    snapshots.take(task_list.tasks())?;
+                    task_list.replace_all(restored)?;
+                    task_list_view.fix_selection(task_list);
+                }
+                self.entries = None;
+            },
+            _ => ()
+        }
+        Ok(true)
+    }
+
+}
@@ -1,9 +1,9 @@
 use ratatui::{layout::Rect, widgets::{Block, Borders, Paragraph, Wrap}, Frame};
 use uuid::Uuid;
 
-use crate::TaskList;
+use crate::{TaskList, TaskStore};
 
-pub fn render(frame: &mut Frame, area: Rect, task_uuid: Option<Uuid>, task_list: &TaskList) {
+pub fn render<S: TaskStore>(frame: &mut Frame, area: Rect, task_uuid: Option<Uuid>, task_list: &TaskList<S>) {
     let task = if let Some(task_uuid) = task_uuid {
         task_list.get(task_uuid)
     } else {
@@ -1,6 +1,6 @@
 use ratatui::{layout::Rect, style::Stylize, widgets::{List}, Frame};
 
-use crate::{TaskList};
+use crate::{TaskList, TaskStore};
 
 #[derive(Default)]
 pub struct TaskDoneView {
@@ -10,7 +10,7 @@ pub struct TaskDoneView {
 impl TaskDoneView {
 
     /// Renders view to a frame area
-    pub fn render(&mut self, frame: &mut Frame, area: Rect, task_list: &TaskList) {
+    pub fn render<S: TaskStore>(&mut self, frame: &mut Frame, area: Rect, task_list: &TaskList<S>) {
         let filtered_tasks = task_list.tasks_done_today();
         // let count_tasks = filtered_tasks.size();
         // if count_tasks > area.height {
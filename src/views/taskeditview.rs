@@ -2,7 +2,7 @@ use crossterm::event::{KeyCode, KeyEvent};
 use ratatui::{layout::Rect, style::Style, text::{Line, Span, Text}, Frame};
 use uuid::Uuid;
 
-use crate::{Task, TaskList, TaskListView};
+use crate::{Task, TaskList, TaskListView, TaskStore};
 
 #[derive(Default)]
 pub enum InputMode {
@@ -82,7 +82,7 @@ impl TaskEditView {
         self.index = 0;
     }
 
-    fn save_task(&mut self, task_list: &mut TaskList, task_list_view: &mut TaskListView) -> std::io::Result<()> {
+    fn save_task<S: TaskStore>(&mut self, task_list: &mut TaskList<S>, task_list_view: &mut TaskListView) -> std::io::Result<()> {
         let result = if let Some(task_uuid) = self.task_uuid {
             let task = task_list.get(task_uuid).expect("Couldn't retrieve uuid'd task in TaskEditView::save_task\n{task_uuid}");
             let mut task = task.clone();
@@ -128,11 +128,12 @@ impl TaskEditView {
     ///
     /// # Errors
     ///
-    /// Returns `Err` if we attempted to add a task, but the write to storage fails
-    pub fn handle_key(
+    /// Returns `Err` if we attempted to add or modify a task, but the list is
+    /// read-only or the write to storage fails
+    pub fn handle_key<S: TaskStore>(
             &mut self,
             key: KeyEvent,
-            task_list: &mut TaskList,
+            task_list: &mut TaskList<S>,
             task_list_view: &mut TaskListView)
                 -> std::io::Result<bool> {
         match self.mode {
@@ -140,12 +141,14 @@ impl TaskEditView {
                 if key.modifiers.is_empty() {
                     match key.code {
                         KeyCode::Char('a') => {
+                            task_list.check_writable()?;
                             self.mode = InputMode::Editing;
                             self.task_uuid = None;
                             Ok(true)
                         },
                         KeyCode::Char('m') => {
                             if let Some(task_uuid) = task_list_view.selected_uuid() {
+                                task_list.check_writable()?;
                                 self.mode = InputMode::Editing;
                                 self.task_uuid = Some(task_uuid);
                                 if let Some(task) = task_list.get(task_uuid) {
@@ -175,7 +178,7 @@ impl TaskEditView {
                     KeyCode::End => self.index = self.input.len(),
                     KeyCode::Delete => self.delete_at_cursor(),
                     _ => ()
-                };
+                }
                 Ok(true)
             }
         }
@@ -2,7 +2,7 @@ use crossterm::event::{KeyCode, KeyModifiers};
 use ratatui::{layout::Rect, text::Text, widgets::{List, ListState}, Frame};
 use uuid::Uuid;
 
-use crate::{Task, TaskList};
+use crate::{Task, TaskList, TaskStore};
 
 #[derive(Default)]
 pub struct TaskListView {
@@ -18,12 +18,12 @@ impl TaskListView {
     ///
     /// # Errors
     /// Returns an error if write to disk failed
-    pub fn pre_render(&mut self, task_list: &mut TaskList) -> std::io::Result<()> {
+    pub fn pre_render<S: TaskStore>(&mut self, task_list: &mut TaskList<S>) -> std::io::Result<()> {
         task_list.pre_render()
     }
 
     /// Renders view to a frame area
-    pub fn render(&mut self, frame: &mut Frame, area: Rect, task_list: &TaskList) {
+    pub fn render<S: TaskStore>(&mut self, frame: &mut Frame, area: Rect, task_list: &TaskList<S>) {
         let mut filtered_tasks = task_list.filtered_tasks().peekable();
         if self.state.selected().is_none() && filtered_tasks.peek().is_some() {
             if let Some(pos) = task_list.filtered_tasks().rev().position(Task::dot) {
@@ -45,12 +45,12 @@ impl TaskListView {
     }
 
     #[must_use]
-    pub fn selected_index(&self, task_list: &TaskList) -> Option<usize> {
+    pub fn selected_index<S: TaskStore>(&self, task_list: &TaskList<S>) -> Option<usize> {
         self.selected_uuid.and_then(
             |selected_uuid| task_list.filtered_tasks().position(|t| t.uuid()==selected_uuid))
     }
 
-    fn select(&mut self, task_list: &TaskList, index: usize) {
+    fn select<S: TaskStore>(&mut self, task_list: &TaskList<S>, index: usize) {
         if let Some(task) = task_list.filtered_tasks().nth(index) {
             self.selected_uuid = Some(task.uuid());
         } else {
@@ -65,8 +65,8 @@ impl TaskListView {
     /// Should be called by class users whenever the filtering of tasks changes,
     /// particularly in a way that is likely to decrease the number of tasks in
     /// the visible list.
-    pub fn fix_selection(&mut self, task_list: &TaskList) {
-        let last_index = task_list.filtered_tasks().count() - 1;
+    pub fn fix_selection<S: TaskStore>(&mut self, task_list: &TaskList<S>) {
+        let last_index = task_list.filtered_tasks().count().saturating_sub(1);
         // If we have a selected task, try to re-select it
         if let Some(uuid) = self.selected_uuid {
             if let Some(index) = task_list.filtered_tasks().position(|t| t.uuid() == uuid) {
@@ -88,7 +88,17 @@ impl TaskListView {
         self.select(task_list, index);
     }
 
-    pub fn move_up_n(&mut self, task_list: &TaskList, n: usize) {
+    /// Selects the task with `uuid`, if it is in the filtered list, otherwise
+    /// fixes the selection as for ``fix_selection``.
+    pub fn select_uuid<S: TaskStore>(&mut self, task_list: &TaskList<S>, uuid: Uuid) {
+        if let Some(index) = task_list.filtered_tasks().position(|t| t.uuid() == uuid) {
+            self.select(task_list, index);
+        } else {
+            self.fix_selection(task_list);
+        }
+    }
+
+    pub fn move_up_n<S: TaskStore>(&mut self, task_list: &TaskList<S>, n: usize) {
         if let Some(current) = self.state.selected() {
             if current > n {
                 self.select(task_list, current - n);
@@ -98,17 +108,17 @@ impl TaskListView {
         }
     }
 
-    pub fn move_up(&mut self, task_list: &TaskList) {
+    pub fn move_up<S: TaskStore>(&mut self, task_list: &TaskList<S>) {
         self.move_up_n(task_list, 1);
     }
 
-    pub fn focus_next_task(&mut self, task_list: &TaskList) {
+    pub fn focus_next_task<S: TaskStore>(&mut self, task_list: &TaskList<S>) {
         if let Some(next_index) = task_list.last_dotted_task_index() {
             self.select(task_list, next_index);
         }
     }
 
-    pub fn move_down_n(&mut self, task_list: &TaskList, n: usize) {
+    pub fn move_down_n<S: TaskStore>(&mut self, task_list: &TaskList<S>, n: usize) {
         if let Some(current) = self.state.selected() {
             if current+n < task_list.filtered_tasks().count()-1 {
                 self.select(task_list, current + n);
@@ -118,22 +128,22 @@ impl TaskListView {
         }
     }
 
-    pub fn move_down(&mut self, task_list: &TaskList) {
+    pub fn move_down<S: TaskStore>(&mut self, task_list: &TaskList<S>) {
         self.move_down_n(task_list, 1);
     }
 
-    pub fn move_start(&mut self, task_list: &TaskList) {
+    pub fn move_start<S: TaskStore>(&mut self, task_list: &TaskList<S>) {
         self.select(task_list, 0);
     }
 
-    pub fn move_end(&mut self, task_list: &TaskList) {
+    pub fn move_end<S: TaskStore>(&mut self, task_list: &TaskList<S>) {
         self.select(task_list, task_list.filtered_tasks().count()-1);
     }
 
     /// Returns true if the currently selected task is at the end of the list.
     /// Also returns true if there is no currently selected task.
     #[must_use]
-    pub fn is_at_end(&mut self, task_list: &TaskList) -> bool {
+    pub fn is_at_end<S: TaskStore>(&mut self, task_list: &TaskList<S>) -> bool {
         if let Some(selected_index) = self.selected_index(task_list) {
             selected_index == task_list.filtered_tasks().count() - 1
         } else {
@@ -149,7 +159,7 @@ impl TaskListView {
     /// # Errors
     ///
     /// Will return `Err` if the write to storage fails.
-    pub fn toggle_dot(&mut self, task_list: &mut TaskList) -> std::io::Result<()> {
+    pub fn toggle_dot<S: TaskStore>(&mut self, task_list: &mut TaskList<S>) -> std::io::Result<()> {
         if let Some(selected_uuid) = self.selected_uuid {
             if let Some(task) = task_list.get(selected_uuid) {
                 let mut task = task.clone();
@@ -176,7 +186,7 @@ impl TaskListView {
     /// # Errors
     ///
     /// Will return `Err` if the write to storage fails.
-    pub fn delete(&mut self, task_list: &mut TaskList) -> std::io::Result<()> {
+    pub fn delete<S: TaskStore>(&mut self, task_list: &mut TaskList<S>) -> std::io::Result<()> {
         if let Some(selected_uuid) = self.selected_uuid {
             self.move_down(task_list);
             task_list.remove(selected_uuid)?;
@@ -192,20 +202,12 @@ impl TaskListView {
     /// # Errors
     ///
     /// Will return `Err` if the write to storage fails.
-    pub fn complete(&mut self, task_list: &mut TaskList) -> std::io::Result<()> {
+    pub fn complete<S: TaskStore>(&mut self, task_list: &mut TaskList<S>) -> std::io::Result<()> {
         if let Some(selected_uuid) = self.selected_uuid {
-            if let Some(task) = task_list.get(selected_uuid) {
-                let mut task = task.clone();
-                let next_occurrence = task.complete();
-                if let Some(next_occurrence) = next_occurrence {
-                    // If the task is recurring, we add the next occurrence to the list
-                    task_list.add(next_occurrence)?;
-                }
-                // When we complete a task, by default we want to select the next task in the list
-                self.move_down(task_list);
-                task_list.replace(selected_uuid, task)?;
-                self.fix_selection(task_list);
-            }
+            // When we complete a task, by default we want to select the next task in the list
+            self.move_down(task_list);
+            task_list.complete(selected_uuid)?;
+            self.fix_selection(task_list);
         }
         Ok(())
     }
@@ -220,18 +222,11 @@ impl TaskListView {
     /// # Errors
     ///
     /// Will return `Err` if the write to storage fails.
-    pub fn recur_daily(&mut self, task_list: &mut TaskList) -> std::io::Result<()> {
+    pub fn recur_daily<S: TaskStore>(&mut self, task_list: &mut TaskList<S>) -> std::io::Result<()> {
         if let Some(selected_uuid) = self.selected_uuid {
-            if let Some(task) = task_list.get(selected_uuid) {
-                let mut task = task.clone();
-                if task.is_recurring() {
-                    task.clear_recur();
-                } else {
-                    task.set_recur_daily();
-                }
-                task_list.replace(selected_uuid, task)?;
-                self.fix_selection(task_list);
-            }
+            let recurring = task_list.get(selected_uuid).is_some_and(Task::is_recurring);
+            task_list.set_recur_daily(selected_uuid, !recurring)?;
+            self.fix_selection(task_list);
         }
         Ok(())
     }
@@ -243,15 +238,11 @@ impl TaskListView {
     /// # Errors
     ///
     /// Will return `Err` if the write to storage fails.
-    pub fn snooze_tomorrow(&mut self, task_list: &mut TaskList) -> std::io::Result<()> {
+    pub fn snooze_tomorrow<S: TaskStore>(&mut self, task_list: &mut TaskList<S>) -> std::io::Result<()> {
         if let Some(selected_uuid) = self.selected_uuid {
-            if let Some(task) = task_list.get(selected_uuid) {
-                let mut task = task.clone();
-                task.snooze_tomorrow();
-                self.move_down(task_list);
-                task_list.replace(selected_uuid, task)?;
-                self.fix_selection(task_list);
-            }
+            self.move_down(task_list);
+            task_list.snooze_tomorrow(selected_uuid)?;
+            self.fix_selection(task_list);
         }
         Ok(())
     }
@@ -263,7 +254,7 @@ impl TaskListView {
     /// # Errors
     ///
     /// Will return `Err` if the write to storage fails.
-    pub fn snooze_1s(&mut self, task_list: &mut TaskList) -> std::io::Result<()> {
+    pub fn snooze_1s<S: TaskStore>(&mut self, task_list: &mut TaskList<S>) -> std::io::Result<()> {
         if let Some(selected_uuid) = self.selected_uuid {
             if let Some(task) = task_list.get(selected_uuid) {
                 let mut task = task.clone();
@@ -276,13 +267,43 @@ impl TaskListView {
         Ok(())
     }
 
+    /// Undoes the last change to the task list, restoring the selection from
+    /// before the change, and attempts to write the updated task list to storage.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the write to storage fails.
+    pub fn undo<S: TaskStore>(&mut self, task_list: &mut TaskList<S>) -> std::io::Result<()> {
+        if let Some(uuid) = task_list.undo()? {
+            self.select_uuid(task_list, uuid);
+        } else {
+            self.fix_selection(task_list);
+        }
+        Ok(())
+    }
+
+    /// Redoes the last undone change to the task list, restoring the selection
+    /// from after the change, and attempts to write the updated task list to storage.
+    ///
+    /// # Errors
+    ///
+    /// Will return `Err` if the write to storage fails.
+    pub fn redo<S: TaskStore>(&mut self, task_list: &mut TaskList<S>) -> std::io::Result<()> {
+        if let Some(uuid) = task_list.redo()? {
+            self.select_uuid(task_list, uuid);
+        } else {
+            self.fix_selection(task_list);
+        }
+        Ok(())
+    }
+
     /// Attempts to handle keyboard input
     /// Returns true if it was handled, false if caller should handle
     ///
     /// # Errors
     ///
     /// Returns `Err` if we attempted to add a task, but the write to storage fails
-    pub fn handle_key(&mut self, key: crossterm::event::KeyEvent, tasks: &mut TaskList) -> std::io::Result<bool> {
+    pub fn handle_key<S: TaskStore>(&mut self, key: crossterm::event::KeyEvent, tasks: &mut TaskList<S>) -> std::io::Result<bool> {
         let page_height: usize = if let Some(area) = self.last_rendered_area {
             Into::<usize>::into(area.height)/2
         } else {
@@ -301,12 +322,14 @@ impl TaskListView {
                 KeyCode::Char('x') => self.delete(tasks)?,
                 KeyCode::Char('z') => self.snooze_tomorrow(tasks)?,
                 KeyCode::Char('Z') => self.snooze_1s(tasks)?,
+                KeyCode::Char('u') => self.undo(tasks)?,
                 _ => return Ok(false)
-            };
+            }
         } else if key.modifiers.intersects(KeyModifiers::CONTROL) {
             match key.code {
                 KeyCode::Char('u') => self.move_up_n(tasks, page_height),
                 KeyCode::Char('d') => self.move_down_n(tasks, page_height),
+                KeyCode::Char('r') => self.redo(tasks)?,
                 _ => return Ok(false)
             }
         }
@@ -335,10 +358,19 @@ impl TaskListView {
  x - Delete task
  z - Snooze until tomorrow
  Z - Snooze for 1s (test)
+ u - Undo
+ ^r - Redo
 
  f - Toggle future task filter
  o - Toggle dotted only filter
 
+ S - Browse/restore snapshots
+ A - Browse/search archive
+ E - Encryption settings
+ l - Switch/create list
+ M - Move task to another list
+ X - Export/import
+
  h - Toggle help pane
  p - Toggle details pane
 "
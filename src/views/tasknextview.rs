@@ -12,7 +12,7 @@ impl TaskNextView {
     }
 
     /// Renders view to a frame area
-    pub fn render(&mut self, frame: &mut ratatui::Frame, area: ratatui::layout::Rect, task_list: &crate::TaskList) {
+    pub fn render<S: crate::TaskStore>(&mut self, frame: &mut ratatui::Frame, area: ratatui::layout::Rect, task_list: &crate::TaskList<S>) {
         let text = if let Some (task) = task_list.last_dotted_task() {
             format!("Next: {}", task.description())
         } else {